@@ -0,0 +1,288 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tungstenite::http::StatusCode;
+
+use crate::config::AdmissionConfig;
+
+/// handshake attempts are counted over windows this long.
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// the request of a refused conn is read and dropped up to this, so closing doesn't reset the response away.
+const MAX_DISCARDED: usize = 16 * 1024;
+
+/// why a conn is turned away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    Denied,
+    NotAllowed,
+    TooManyHandshakes,
+    TooManyConns,
+    TooManyConnsFromIp,
+}
+
+impl Rejection {
+    pub fn reason(self) -> &'static str {
+        match self {
+            Rejection::Denied => "denied",
+            Rejection::NotAllowed => "not allowed",
+            Rejection::TooManyHandshakes => "too many handshakes",
+            Rejection::TooManyConns => "too many conns",
+            Rejection::TooManyConnsFromIp => "too many conns from ip",
+        }
+    }
+
+    /// banned peers get 403, so they stop retrying. limited ones get 429, and 503 when the server is full.
+    pub fn status(self) -> StatusCode {
+        match self {
+            Rejection::Denied | Rejection::NotAllowed => StatusCode::FORBIDDEN,
+            Rejection::TooManyHandshakes | Rejection::TooManyConnsFromIp => StatusCode::TOO_MANY_REQUESTS,
+            Rejection::TooManyConns => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    /// whether the status is worth a tls handshake. banned and flooding peers just have their tcp conn closed.
+    pub fn answered_over_tls(self) -> bool {
+        matches!(self, Rejection::TooManyConns | Rejection::TooManyConnsFromIp)
+    }
+}
+
+/// answer a turned away conn with the status of rejection, then close it.
+pub async fn refuse<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S, rejection: Rejection) -> std::io::Result<()> {
+    let status = rejection.status();
+    let reason = rejection.reason();
+    let retry_after = match rejection {
+        Rejection::TooManyHandshakes => format!("Retry-After: {}\r\n", WINDOW.as_secs()),
+        _ => String::new(),
+    };
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\n{}Connection: close\r\n\r\n{}",
+        status.as_str(),
+        status.canonical_reason().unwrap_or_default(),
+        reason.len(),
+        retry_after,
+        reason
+    );
+
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+
+    let mut discarded = 0;
+    let mut buf = [0; 1024];
+    while discarded < MAX_DISCARDED {
+        match stream.read(&mut buf).await? {
+            0 => break,
+            read => discarded += read,
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+struct State {
+    conns: usize,
+    conns_per_ip: HashMap<IpAddr, usize>,
+    /// start of the current window of each ip, and its attempts in it.
+    attempts: HashMap<IpAddr, (Instant, u32)>,
+    /// windows that ended are dropped at most this often.
+    pruned_at: Option<Instant>,
+}
+
+/// decides from its address alone whether a tcp conn may handshake, before anything is read from it.
+#[derive(Debug)]
+pub struct Admission {
+    config: AdmissionConfig,
+    state: Mutex<State>,
+}
+
+/// an admitted conn. its slot is freed when the permit is dropped, with the conn.
+#[derive(Debug)]
+pub struct Permit {
+    admission: Arc<Admission>,
+    ip: IpAddr,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let mut state = self.admission.lock();
+
+        state.conns -= 1;
+        if let Some(conns) = state.conns_per_ip.get_mut(&self.ip) {
+            *conns -= 1;
+            if *conns == 0 {
+                state.conns_per_ip.remove(&self.ip);
+            }
+        }
+    }
+}
+
+impl Admission {
+    pub fn new(config: AdmissionConfig) -> Arc<Self> {
+        Arc::new(Admission {
+            config,
+            state: Mutex::new(State::default()),
+        })
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// admit a conn from ip, or why it is turned away.
+    pub fn admit(self: &Arc<Self>, ip: IpAddr) -> Result<Permit, Rejection> {
+        self.admit_at(ip, Instant::now())
+    }
+
+    fn admit_at(self: &Arc<Self>, ip: IpAddr, now: Instant) -> Result<Permit, Rejection> {
+        // v4 peers of a dual stack listener show up as v4 mapped v6 addresses.
+        let ip = ip.to_canonical();
+
+        if self.config.deny.iter().any(|net| net.contains(&ip)) {
+            return Err(Rejection::Denied);
+        }
+        if !self.config.allow.is_empty() && !self.config.allow.iter().any(|net| net.contains(&ip)) {
+            return Err(Rejection::NotAllowed);
+        }
+
+        let mut state = self.lock();
+
+        if state.pruned_at.is_none_or(|pruned_at| now.duration_since(pruned_at) >= WINDOW) {
+            state.attempts.retain(|_, (started, _)| now.duration_since(*started) < WINDOW);
+            state.pruned_at = Some(now);
+        }
+
+        let (started, attempts) = state.attempts.entry(ip).or_insert((now, 0));
+        if now.duration_since(*started) >= WINDOW {
+            *started = now;
+            *attempts = 0;
+        }
+        *attempts += 1;
+        if *attempts > self.config.handshakes_per_ip_per_minute {
+            return Err(Rejection::TooManyHandshakes);
+        }
+
+        if state.conns >= self.config.max_conns {
+            return Err(Rejection::TooManyConns);
+        }
+        let conns = state.conns_per_ip.get(&ip).copied().unwrap_or_default();
+        if conns >= self.config.max_conns_per_ip {
+            return Err(Rejection::TooManyConnsFromIp);
+        }
+
+        state.conns += 1;
+        state.conns_per_ip.insert(ip, conns + 1);
+
+        Ok(Permit { admission: self.clone(), ip })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn admission(config: AdmissionConfig) -> Arc<Admission> {
+        Admission::new(config)
+    }
+
+    fn ip(raw: &str) -> IpAddr {
+        raw.parse().unwrap()
+    }
+
+    #[test]
+    fn deny_wins_over_allow() {
+        let admission = admission(AdmissionConfig {
+            allow: vec!["10.0.0.0/8".parse().unwrap()],
+            deny: vec!["10.1.0.0/16".parse().unwrap()],
+            ..Default::default()
+        });
+
+        assert!(admission.admit(ip("10.2.0.1")).is_ok());
+        assert_eq!(admission.admit(ip("10.1.0.1")).unwrap_err(), Rejection::Denied);
+        assert_eq!(admission.admit(ip("192.168.0.1")).unwrap_err(), Rejection::NotAllowed);
+        // v4 mapped v6 peers are matched as v4.
+        assert_eq!(admission.admit(ip("::ffff:10.1.0.1")).unwrap_err(), Rejection::Denied);
+    }
+
+    #[test]
+    fn conns_are_capped_per_ip_and_in_total() {
+        let admission = admission(AdmissionConfig {
+            max_conns: 3,
+            max_conns_per_ip: 2,
+            ..Default::default()
+        });
+
+        let a1 = admission.admit(ip("10.0.0.1")).unwrap();
+        let _a2 = admission.admit(ip("10.0.0.1")).unwrap();
+        assert_eq!(admission.admit(ip("10.0.0.1")).unwrap_err(), Rejection::TooManyConnsFromIp);
+
+        let _b1 = admission.admit(ip("10.0.0.2")).unwrap();
+        assert_eq!(admission.admit(ip("10.0.0.3")).unwrap_err(), Rejection::TooManyConns);
+
+        // dropping a permit frees its slot.
+        drop(a1);
+        let _a3 = admission.admit(ip("10.0.0.1")).unwrap();
+        assert_eq!(admission.admit(ip("10.0.0.3")).unwrap_err(), Rejection::TooManyConns);
+    }
+
+    #[test]
+    fn permits_release_their_slots() {
+        let admission = admission(AdmissionConfig::default());
+
+        let permits: Vec<_> = (0..3).map(|_| admission.admit(ip("10.0.0.1")).unwrap()).collect();
+        assert_eq!(admission.lock().conns, 3);
+        assert_eq!(admission.lock().conns_per_ip[&ip("10.0.0.1")], 3);
+
+        drop(permits);
+        assert_eq!(admission.lock().conns, 0);
+        assert!(admission.lock().conns_per_ip.is_empty());
+    }
+
+    #[test]
+    fn handshakes_are_limited_per_window() {
+        let admission = admission(AdmissionConfig {
+            handshakes_per_ip_per_minute: 2,
+            ..Default::default()
+        });
+        let now = Instant::now();
+
+        // attempts count whether or not the conn is still open.
+        drop(admission.admit_at(ip("10.0.0.1"), now).unwrap());
+        drop(admission.admit_at(ip("10.0.0.1"), now).unwrap());
+        assert_eq!(admission.admit_at(ip("10.0.0.1"), now).unwrap_err(), Rejection::TooManyHandshakes);
+        assert!(admission.admit_at(ip("10.0.0.2"), now).is_ok());
+
+        // the next window starts over.
+        assert!(admission.admit_at(ip("10.0.0.1"), now + WINDOW).is_ok());
+    }
+
+    #[test]
+    fn only_overloads_are_answered_over_tls() {
+        assert!(!Rejection::Denied.answered_over_tls());
+        assert!(!Rejection::NotAllowed.answered_over_tls());
+        assert!(!Rejection::TooManyHandshakes.answered_over_tls());
+        assert!(Rejection::TooManyConns.answered_over_tls());
+        assert!(Rejection::TooManyConnsFromIp.answered_over_tls());
+    }
+
+    #[tokio::test]
+    async fn refusals_carry_the_status() {
+        let (client, server) = tokio::io::duplex(1024);
+        let (mut read, mut write) = tokio::io::split(client);
+        write.write_all(b"GET / HTTP/1.1\r\n\r\n").await.unwrap();
+        write.shutdown().await.unwrap();
+
+        refuse(server, Rejection::TooManyHandshakes).await.unwrap();
+
+        let mut response = String::new();
+        read.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 429 Too Many Requests\r\n"), "{}", response);
+        assert!(response.contains("Retry-After: 60\r\n"));
+        assert!(response.ends_with("too many handshakes"));
+    }
+}
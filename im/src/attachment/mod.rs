@@ -0,0 +1,38 @@
+mod signer;
+mod storage;
+
+use serde::{Deserialize, Serialize};
+
+use crate::auth::RoomId;
+
+pub use signer::UrlSigner;
+pub use storage::{AttachmentStorage, LocalDiskStorage};
+
+/// metadata of an uploaded file. the room it belongs to decides who may fetch it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Attachment {
+    pub id: String,
+    pub room_id: RoomId,
+    /// member id of the uploader.
+    pub uploader: String,
+    pub name: String,
+    pub mime: String,
+    pub size: u64,
+    pub created_at: u64,
+}
+
+/// check declared mime type against the allowed ones and the file signature.
+pub fn check_content(allowed: &[String], mime: &str, data: &[u8]) -> bool {
+    if !allowed.iter().any(|allowed| allowed == mime) {
+        return false;
+    }
+
+    match mime {
+        "image/png" => data.starts_with(b"\x89PNG\r\n\x1a\n"),
+        "image/jpeg" => data.starts_with(b"\xff\xd8\xff"),
+        "image/gif" => data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a"),
+        "image/webp" => data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WEBP",
+        "application/pdf" => data.starts_with(b"%PDF-"),
+        _ => true,
+    }
+}
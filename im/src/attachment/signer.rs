@@ -0,0 +1,81 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// signs attachment download urls so they expire.
+pub struct UrlSigner {
+    key: Vec<u8>,
+}
+
+impl UrlSigner {
+    pub fn new(key: impl Into<Vec<u8>>) -> Self {
+        UrlSigner { key: key.into() }
+    }
+
+    fn mac(&self, id: &str, expires: u64) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("hmac accepts any key length");
+        mac.update(format!("{}:{}", id, expires).as_bytes());
+        mac
+    }
+
+    /// returns the hex signature for id valid until expires (unix seconds).
+    pub fn sign(&self, id: &str, expires: u64) -> String {
+        hex::encode(self.mac(id, expires).finalize().into_bytes())
+    }
+
+    /// check the signature in constant time, and that expires (unix seconds) is not before now.
+    pub fn verify(&self, id: &str, expires: u64, signature: &str, now: u64) -> bool {
+        if expires < now {
+            return false;
+        }
+
+        let signature = match hex::decode(signature) {
+            Ok(signature) => signature,
+            Err(_) => return false,
+        };
+
+        self.mac(id, expires).verify_slice(&signature).is_ok()
+    }
+
+    /// build a download url path for id.
+    pub fn url(&self, id: &str, expires: u64) -> String {
+        format!("/attachments/{}?expires={}&sig={}", id, expires, self.sign(id, expires))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signatures_bind_the_id_and_expiry() {
+        let signer = UrlSigner::new("k1");
+        let sig = signer.sign("a1", 100);
+
+        assert!(signer.verify("a1", 100, &sig, 99));
+        assert!(!signer.verify("a2", 100, &sig, 99));
+        assert!(!signer.verify("a1", 101, &sig, 99));
+        assert!(!UrlSigner::new("k2").verify("a1", 100, &sig, 99));
+        assert!(!signer.verify("a1", 100, "not hex", 99));
+        assert!(!signer.verify("a1", 100, &sig[..sig.len() - 2], 99));
+    }
+
+    #[test]
+    fn urls_expire() {
+        let signer = UrlSigner::new("k1");
+        let sig = signer.sign("a1", 100);
+
+        assert!(signer.verify("a1", 100, &sig, 100));
+        assert!(!signer.verify("a1", 100, &sig, 101));
+    }
+
+    #[test]
+    fn urls_carry_the_signature() {
+        let signer = UrlSigner::new("k1");
+        assert_eq!(
+            signer.url("a1", 100),
+            format!("/attachments/a1?expires=100&sig={}", signer.sign("a1", 100))
+        );
+    }
+}
@@ -0,0 +1,291 @@
+use std::{fs, io, path::PathBuf};
+
+use anyhow::{anyhow, Result};
+
+use super::Attachment;
+
+/// AttachmentStorage keeps uploaded file content and metadata.
+/// implementations are blocking, callers run them on the blocking pool.
+pub trait AttachmentStorage: Send + Sync {
+    fn put(&self, attachment: &Attachment, data: &[u8]) -> Result<()>;
+
+    fn metadata(&self, id: &str) -> Result<Option<Attachment>>;
+
+    fn read(&self, id: &str) -> Result<Option<Vec<u8>>>;
+
+    fn delete(&self, id: &str) -> Result<()>;
+
+    /// metadata of the attachments of room_id.
+    fn list_room(&self, room_id: &str) -> Result<Vec<Attachment>>;
+
+    /// give the attachments of room_id to room to instead, e.g. once an erasure renamed the room.
+    fn move_room(&self, room_id: &str, to: &str) -> Result<()>;
+}
+
+/// stores attachments as files in a local directory.
+/// content goes to `<id>.bin`, metadata to `<id>.json`, and an empty `rooms/<hex room id>/<id>`
+/// indexes it by room.
+pub struct LocalDiskStorage {
+    root: PathBuf,
+}
+
+impl LocalDiskStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Result<Self> {
+        let root = root.into();
+        fs::create_dir_all(&root)?;
+
+        let storage = LocalDiskStorage { root };
+        if !storage.rooms_dir().is_dir() {
+            storage.build_index()?;
+        }
+
+        Ok(storage)
+    }
+
+    /// ids are generated by the server, anything else could escape the root.
+    fn path(&self, id: &str, ext: &str) -> Result<PathBuf> {
+        check_id(id)?;
+
+        Ok(self.root.join(format!("{}.{}", id, ext)))
+    }
+
+    fn rooms_dir(&self) -> PathBuf {
+        self.root.join("rooms")
+    }
+
+    /// room ids are chosen by clients, hex keeps them a single path component.
+    fn room_dir(&self, room_id: &str) -> PathBuf {
+        self.rooms_dir().join(hex::encode(room_id))
+    }
+
+    fn index(&self, attachment: &Attachment) -> Result<()> {
+        check_id(&attachment.id)?;
+        let dir = self.room_dir(&attachment.room_id);
+
+        // the dir of a room goes with its last attachment, possibly right after it was created here.
+        for _ in 0..2 {
+            fs::create_dir_all(&dir)?;
+            match fs::write(dir.join(&attachment.id), b"") {
+                Ok(()) => return Ok(()),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Err(anyhow!("failed to index attachment {}", attachment.id))
+    }
+
+    /// forget the dir of room_id once it has no attachment left. room ids name customers.
+    fn unindex(&self, room_id: &str, id: &str) -> Result<()> {
+        let dir = self.room_dir(room_id);
+        not_found_to_none(fs::remove_file(dir.join(id)))?;
+
+        // fails while other attachments are left.
+        let _ = fs::remove_dir(dir);
+
+        Ok(())
+    }
+
+    /// index the attachments stored before there was an index. written last, so an interrupted run starts over.
+    fn build_index(&self) -> Result<()> {
+        let building = self.root.join("rooms.building");
+        if building.exists() {
+            fs::remove_dir_all(&building)?;
+        }
+
+        for entry in fs::read_dir(&self.root)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+
+            let attachment: Attachment = serde_json::from_slice(&fs::read(&path)?)?;
+            check_id(&attachment.id)?;
+            let dir = building.join(hex::encode(&attachment.room_id));
+            fs::create_dir_all(&dir)?;
+            fs::write(dir.join(&attachment.id), b"")?;
+        }
+
+        fs::create_dir_all(&building)?;
+        fs::rename(building, self.rooms_dir())?;
+
+        Ok(())
+    }
+
+    fn write_metadata(&self, attachment: &Attachment) -> Result<()> {
+        fs::write(self.path(&attachment.id, "json")?, serde_json::to_vec(attachment)?)?;
+
+        Ok(())
+    }
+}
+
+fn check_id(id: &str) -> Result<()> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Err(anyhow!("invalid attachment id: {}", id));
+    }
+
+    Ok(())
+}
+
+fn not_found_to_none<T>(res: io::Result<T>) -> Result<Option<T>> {
+    match res {
+        Ok(value) => Ok(Some(value)),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+impl AttachmentStorage for LocalDiskStorage {
+    fn put(&self, attachment: &Attachment, data: &[u8]) -> Result<()> {
+        fs::write(self.path(&attachment.id, "bin")?, data)?;
+        self.write_metadata(attachment)?;
+        self.index(attachment)
+    }
+
+    fn metadata(&self, id: &str) -> Result<Option<Attachment>> {
+        match not_found_to_none(fs::read(self.path(id, "json")?))? {
+            Some(raw) => Ok(Some(serde_json::from_slice(&raw)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn read(&self, id: &str) -> Result<Option<Vec<u8>>> {
+        not_found_to_none(fs::read(self.path(id, "bin")?))
+    }
+
+    fn delete(&self, id: &str) -> Result<()> {
+        if let Some(attachment) = self.metadata(id)? {
+            self.unindex(&attachment.room_id, id)?;
+        }
+        not_found_to_none(fs::remove_file(self.path(id, "bin")?))?;
+        not_found_to_none(fs::remove_file(self.path(id, "json")?))?;
+
+        Ok(())
+    }
+
+    fn list_room(&self, room_id: &str) -> Result<Vec<Attachment>> {
+        let entries = match not_found_to_none(fs::read_dir(self.room_dir(room_id)))? {
+            Some(entries) => entries,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut attachments = Vec::new();
+        for entry in entries {
+            let name = entry?.file_name();
+
+            // removed between listing and reading.
+            if let Some(attachment) = self.metadata(&name.to_string_lossy())? {
+                attachments.push(attachment);
+            }
+        }
+
+        Ok(attachments)
+    }
+
+    fn move_room(&self, room_id: &str, to: &str) -> Result<()> {
+        for mut attachment in self.list_room(room_id)? {
+            attachment.room_id = to.to_string();
+            self.write_metadata(&attachment)?;
+            self.index(&attachment)?;
+            self.unindex(room_id, &attachment.id)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("im-attachments-{}", uuid::Uuid::new_v4()))
+    }
+
+    fn attachment(id: &str, room_id: &str) -> Attachment {
+        Attachment {
+            id: id.to_string(),
+            room_id: room_id.to_string(),
+            uploader: "a1".to_string(),
+            name: "notes.txt".to_string(),
+            mime: "text/plain".to_string(),
+            size: 2,
+            created_at: 0,
+        }
+    }
+
+    fn ids(mut attachments: Vec<Attachment>) -> Vec<String> {
+        attachments.sort_by(|a, b| a.id.cmp(&b.id));
+        attachments.into_iter().map(|attachment| attachment.id).collect()
+    }
+
+    #[test]
+    fn attachments_are_listed_by_room() {
+        let dir = temp_dir();
+        let storage = LocalDiskStorage::new(&dir).unwrap();
+        storage.put(&attachment("1", "c1-a1"), b"hi").unwrap();
+        storage.put(&attachment("2", "c1-a1"), b"hi").unwrap();
+        storage.put(&attachment("3", "c2-a1"), b"hi").unwrap();
+
+        assert_eq!(ids(storage.list_room("c1-a1").unwrap()), ["1", "2"]);
+        assert!(storage.list_room("../c1-a1").unwrap().is_empty());
+
+        storage.delete("1").unwrap();
+        storage.delete("2").unwrap();
+        assert!(storage.list_room("c1-a1").unwrap().is_empty());
+        assert!(!storage.room_dir("c1-a1").exists());
+        assert_eq!(storage.read("3").unwrap().as_deref(), Some(&b"hi"[..]));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn moved_attachments_name_the_new_room_only() {
+        let dir = temp_dir();
+        let storage = LocalDiskStorage::new(&dir).unwrap();
+        storage.put(&attachment("1", "c1-a1"), b"hi").unwrap();
+
+        storage.move_room("c1-a1", "erased-1").unwrap();
+
+        assert!(storage.list_room("c1-a1").unwrap().is_empty());
+        assert!(!storage.room_dir("c1-a1").exists());
+        assert_eq!(ids(storage.list_room("erased-1").unwrap()), ["1"]);
+        assert_eq!(storage.metadata("1").unwrap().unwrap().room_id, "erased-1");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn attachments_stored_before_the_index_are_indexed() {
+        let dir = temp_dir();
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("1.bin"), b"hi").unwrap();
+        fs::write(dir.join("1.json"), serde_json::to_vec(&attachment("1", "c1-a1")).unwrap()).unwrap();
+
+        let storage = LocalDiskStorage::new(&dir).unwrap();
+        assert_eq!(ids(storage.list_room("c1-a1").unwrap()), ["1"]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn ids_are_single_path_components() {
+        let dir = temp_dir();
+        let storage = LocalDiskStorage::new(&dir).unwrap();
+
+        for id in ["", "..", "../1", "a/b", "a\\b", "1.bin", "a b", "/etc/passwd"] {
+            assert!(storage.put(&attachment(id, "c1-a1"), b"hi").is_err(), "{:?}", id);
+            assert!(storage.metadata(id).is_err(), "{:?}", id);
+            assert!(storage.read(id).is_err(), "{:?}", id);
+            assert!(storage.delete(id).is_err(), "{:?}", id);
+        }
+        assert!(fs::read_dir(&dir).unwrap().all(|entry| entry.unwrap().file_name() == "rooms"));
+
+        let id = uuid::Uuid::new_v4().to_string();
+        storage.put(&attachment(&id, "c1-a1"), b"hi").unwrap();
+        assert_eq!(storage.read(&id).unwrap().as_deref(), Some(&b"hi"[..]));
+        assert!(storage.read("0").unwrap().is_none());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,28 @@
+use tracing::warn;
+
+use crate::auth::{Member, RoomId};
+
+/// write an audit record for a message that was rejected before reaching the room.
+pub fn reject_message(member: &Member, room_id: &RoomId, reason: &str) {
+    warn!(
+        target: "audit",
+        member_id = member.id(),
+        user_type = ?member.user_type(),
+        room_id = %room_id,
+        reason,
+        "rejected message"
+    );
+}
+
+/// write an audit record for a message moderation blocked or flagged.
+pub fn moderate_message(member: &Member, room_id: &RoomId, action: &str, reason: &str) {
+    warn!(
+        target: "audit",
+        member_id = member.id(),
+        user_type = ?member.user_type(),
+        room_id = %room_id,
+        action,
+        reason,
+        "moderated message"
+    );
+}
@@ -1,66 +1,116 @@
-use jsonwebtoken::{DecodingKey, Validation};
 use tokio_tungstenite::{
-    accept_hdr_async,
-    tungstenite::handshake::server::{ErrorResponse, Request, Response},
+    accept_hdr_async_with_config,
+    tungstenite::{
+        handshake::server::{ErrorResponse, Request, Response},
+        protocol::WebSocketConfig,
+    },
     WebSocketStream,
 };
 
-use tokio::net::TcpStream;
+use tungstenite::http::{
+    header::{ACCEPT_LANGUAGE, SEC_WEBSOCKET_PROTOCOL},
+    HeaderValue, StatusCode,
+};
+
+use crate::{
+    admission::Permit,
+    i18n,
+    message::{
+        codec::CodecKind,
+        version::{self, Negotiation, ProtocolVersion},
+    },
+    tls::ClientStream,
+};
 
-use super::{Member, UserType};
+use super::{
+    token::{self, TokenVerifier},
+    Member, UserType,
+};
 
 #[derive(Debug)]
 pub struct ConnWrapper {
-    pub stream: WebSocketStream<TcpStream>,
+    pub stream: WebSocketStream<ClientStream>,
     pub member: Member,
+    pub version: ProtocolVersion,
+    pub codec: CodecKind,
+    /// requested locales, most preferred first.
+    pub locales: Vec<String>,
+    pub permit: Permit,
 }
 
-pub async fn handshake(stream: TcpStream) -> Result<ConnWrapper, ErrorResponse> {
+/// refuse the upgrade with status. tungstenite doesn't send a rejection with a success status.
+fn reject(status: StatusCode, reason: &str) -> ErrorResponse {
+    let mut response = ErrorResponse::new(Some(reason.to_string()));
+    *response.status_mut() = status;
+    response
+}
+
+#[allow(clippy::result_large_err)]
+pub async fn handshake(stream: ClientStream, permit: Permit, tokens: &TokenVerifier, config: WebSocketConfig) -> Result<ConnWrapper, ErrorResponse> {
     let mut user_id = String::new();
     let mut user_type = UserType::Customer;
     let mut user_name = String::new();
+    let mut protocol_version = ProtocolVersion::V1;
+    let mut codec = CodecKind::Json;
+    let mut locales = Vec::new();
 
-    let callback = |request: &Request, response: Response| {
+    let callback = |request: &Request, mut response: Response| {
         let headers = request.headers();
 
         let auth_header = match headers.get("Authorization") {
             Some(auth_header) => auth_header,
-            None => return Err(ErrorResponse::new(Some("invalid token".to_string()))),
+            None => return Err(reject(StatusCode::UNAUTHORIZED, "invalid token")),
         };
 
-        let token = match auth_header.to_str() {
-            Ok(token_str) => {
-                if !token_str.starts_with("Bearer ") {
-                    return Err(ErrorResponse::new(Some("invalid token".to_string())));
-                }
+        let token = match auth_header.to_str().ok().and_then(token::bearer_token) {
+            Some(token) => token,
+            None => return Err(reject(StatusCode::UNAUTHORIZED, "invalid token")),
+        };
 
-                token_str[7..].to_string()
+        match tokens.decode_claims(token) {
+            Some(claims) => {
+                user_id = claims.member.id().to_string();
+                user_type = claims.member.user_type();
+                user_name = claims.member.user_name().to_string();
+                locales.extend(claims.locale);
             }
-            Err(_) => return Err(ErrorResponse::new(Some("invalid token".to_string()))),
+            None => return Err(reject(StatusCode::UNAUTHORIZED, "invalid token")),
         };
 
-        let secret = DecodingKey::from_secret(b"aoquoquoeq");
-        let decode_res =
-            jsonwebtoken::decode::<Member>(token.as_str(), &secret, &Validation::default());
+        if let Some(accept_language) = headers.get(ACCEPT_LANGUAGE).and_then(|value| value.to_str().ok()) {
+            locales.extend(i18n::parse_accept_language(accept_language));
+        }
 
-        match decode_res {
-            Ok(token_data) => {
-                user_id = token_data.claims.id().to_string();
-                user_type = token_data.claims.user_type();
-                user_name = token_data.claims.user_name().to_string();
+        let offered = headers.get(SEC_WEBSOCKET_PROTOCOL).and_then(|value| value.to_str().ok());
+        match version::negotiate(offered) {
+            Negotiation::Legacy => {}
+            Negotiation::Selected(subprotocol) => {
+                protocol_version = subprotocol.version;
+                codec = subprotocol.codec;
+                match HeaderValue::from_str(&subprotocol.name()) {
+                    Ok(value) => response.headers_mut().insert(SEC_WEBSOCKET_PROTOCOL, value),
+                    Err(_) => return Err(reject(StatusCode::BAD_REQUEST, "unsupported protocol")),
+                };
             }
-            Err(_) => return Err(ErrorResponse::new(Some("invalid token".to_string()))),
+            Negotiation::Unsupported => return Err(reject(StatusCode::BAD_REQUEST, "unsupported protocol")),
         };
 
         Ok(response)
     };
 
-    let ws_stream = accept_hdr_async(stream, callback)
+    let ws_stream = accept_hdr_async_with_config(stream, callback, Some(config))
         .await
-        .map_err(|_| ErrorResponse::new(Some("WebSocket handshake failed".to_string())))?;
+        .map_err(|err| match err {
+            tungstenite::Error::Http(response) => response.map(|body| body.map(|body| String::from_utf8_lossy(&body).into_owned())),
+            _ => reject(StatusCode::BAD_REQUEST, "WebSocket handshake failed"),
+        })?;
 
     Ok(ConnWrapper {
         stream: ws_stream,
         member: Member::new(user_type, user_id, user_name),
+        version: protocol_version,
+        codec,
+        locales,
+        permit,
     })
 }
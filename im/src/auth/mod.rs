@@ -1,8 +1,11 @@
+pub mod audit;
 pub mod errors;
 mod handshake;
 mod session;
+pub mod token;
 
 pub use handshake::handshake;
+pub use handshake::ConnWrapper;
 pub use session::Member;
 pub use session::RoomId;
 pub use session::UserType;
@@ -6,6 +6,27 @@ use serde::{Deserialize, Serialize};
 pub enum UserType {
     CustomerService,
     Customer,
+    Supervisor,
+}
+
+impl UserType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UserType::CustomerService => "CustomerService",
+            UserType::Customer => "Customer",
+            UserType::Supervisor => "Supervisor",
+        }
+    }
+
+    /// inverse of as_str.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "CustomerService" => Some(UserType::CustomerService),
+            "Customer" => Some(UserType::Customer),
+            "Supervisor" => Some(UserType::Supervisor),
+            _ => None,
+        }
+    }
 }
 
 /// Member is a struct wrapper for connection identity.
@@ -24,11 +45,7 @@ impl Hash for Member {
 
 impl Member {
     pub fn new(user_type: UserType, id: String, user_name: String) -> Self {
-        Member {
-            user_name,
-            user_type,
-            id,
-        }
+        Member { user_name, user_type, id }
     }
 
     pub fn user_type(&self) -> UserType {
@@ -47,9 +64,22 @@ impl Member {
         self.user_type == UserType::Customer
     }
 
+    pub fn is_supervisor(&self) -> bool {
+        self.user_type == UserType::Supervisor
+    }
+
     pub fn user_name(&self) -> &str {
         &self.user_name
     }
+
+    /// name shown to other members. falls back to the id when no name is set.
+    pub fn display_name(&self) -> &str {
+        if self.user_name.is_empty() {
+            return &self.id;
+        }
+
+        &self.user_name
+    }
 }
 
 /// RoomId is a chat room identity.
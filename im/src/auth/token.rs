@@ -0,0 +1,45 @@
+use jsonwebtoken::{DecodingKey, Validation};
+use serde::Deserialize;
+
+use super::Member;
+
+/// extract the token from an `Authorization: Bearer <token>` header value.
+pub fn bearer_token(header: &str) -> Option<&str> {
+    header.strip_prefix("Bearer ")
+}
+
+/// claims carried by the jwt.
+#[derive(Deserialize, Debug)]
+pub struct Claims {
+    #[serde(flatten)]
+    pub member: Member,
+
+    /// preferred locale of the user, takes priority over `Accept-Language`.
+    #[serde(default)]
+    pub locale: Option<String>,
+}
+
+/// verifies jwts signed with the configured secret.
+pub struct TokenVerifier {
+    key: DecodingKey,
+}
+
+impl TokenVerifier {
+    pub fn new(secret: &[u8]) -> Self {
+        TokenVerifier {
+            key: DecodingKey::from_secret(secret),
+        }
+    }
+
+    /// decode and verify a jwt, returns its claims.
+    pub fn decode_claims(&self, token: &str) -> Option<Claims> {
+        jsonwebtoken::decode::<Claims>(token, &self.key, &Validation::default())
+            .ok()
+            .map(|token_data| token_data.claims)
+    }
+
+    /// decode and verify a jwt, returns the member it identifies.
+    pub fn decode_member(&self, token: &str) -> Option<Member> {
+        self.decode_claims(token).map(|claims| claims.member)
+    }
+}
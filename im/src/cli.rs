@@ -0,0 +1,116 @@
+use std::{fs, net::SocketAddr, path::PathBuf};
+
+use anyhow::{Context, Result};
+use clap::{Args, Parser, Subcommand};
+
+use crate::{
+    config::Config,
+    i18n::I18n,
+    store::{MessageStore, SqliteStore},
+    transcript::{self, Format, TranscriptFilter},
+};
+
+/// customer service chat server.
+#[derive(Parser, Debug)]
+#[command(version)]
+pub struct Cli {
+    #[command(flatten)]
+    pub serve: ServeArgs,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// flags of the server, they take priority over the config file and the environment.
+#[derive(Args, Debug)]
+pub struct ServeArgs {
+    /// toml config file. defaults to $IM_CONFIG, then ./im.toml if it exists.
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// websocket listener address.
+    #[arg(long)]
+    pub bind: Option<SocketAddr>,
+
+    /// attachments api listener address.
+    #[arg(long)]
+    pub http_bind: Option<SocketAddr>,
+
+    /// health probes, metrics and admin api listener address.
+    #[arg(long)]
+    pub admin_bind: Option<SocketAddr>,
+
+    /// directory of the database, journal and attachments.
+    #[arg(long, global = true)]
+    pub data_dir: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// write room transcripts from the database of the config and exit.
+    Export(ExportArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct ExportArgs {
+    #[arg(long, value_enum, default_value_t = Format::Json)]
+    pub format: Format,
+
+    /// write here instead of stdout.
+    #[arg(long, short)]
+    pub output: Option<PathBuf>,
+
+    #[arg(long)]
+    pub room_id: Option<String>,
+
+    /// id of a customer service member of the room.
+    #[arg(long)]
+    pub agent: Option<String>,
+
+    /// id of a customer member of the room.
+    #[arg(long)]
+    pub customer: Option<String>,
+
+    /// rooms created at or after, as unix millis, rfc 3339 or YYYY-MM-DD.
+    #[arg(long)]
+    pub from: Option<String>,
+
+    /// rooms created before, as unix millis, rfc 3339 or YYYY-MM-DD. a date includes that day.
+    #[arg(long)]
+    pub to: Option<String>,
+
+    /// locale of system tips.
+    #[arg(long)]
+    pub locale: Option<String>,
+}
+
+pub fn export(config: &Config, args: ExportArgs) -> Result<()> {
+    let filter = TranscriptFilter {
+        room_id: args.room_id,
+        agent: args.agent,
+        customer: args.customer,
+        from: transcript::parse_bound(args.from.as_deref(), false)?,
+        to: transcript::parse_bound(args.to.as_deref(), true)?,
+    };
+
+    let db_path = config.server.db_path();
+    if !db_path.is_file() {
+        anyhow::bail!("no database at {}", db_path.display());
+    }
+    let store = SqliteStore::open(&db_path).with_context(|| format!("open {}", db_path.display()))?;
+    let transcripts = store.transcripts(&filter)?;
+
+    let mut i18n = I18n::builtin();
+    if config.server.locales_dir.is_dir() {
+        i18n.load_dir(&config.server.locales_dir)?;
+    }
+    let locale = i18n.negotiate(&args.locale.into_iter().collect::<Vec<_>>());
+    let body = transcript::render(args.format, &transcripts, &i18n, &locale)?;
+
+    match args.output {
+        Some(path) => fs::write(&path, body).with_context(|| format!("write {}", path.display()))?,
+        None => print!("{}", body),
+    }
+
+    Ok(())
+}
@@ -0,0 +1,827 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use ipnet::IpNet;
+use serde::Deserialize;
+use tungstenite::protocol::WebSocketConfig;
+
+use crate::{auth::UserType, cli::ServeArgs, logging, message::protocol::MessageType, moderation, retention::RetentionPolicy};
+
+/// file read when neither `--config` nor `IM_CONFIG` names one.
+const DEFAULT_CONFIG_FILE: &str = "im.toml";
+
+/// environment variables `IM_<SECTION>_<KEY>` override `key` of `[section]`.
+const ENV_PREFIX: &str = "IM_";
+
+/// sections that can be overridden from the environment.
+const SECTIONS: [&str; 12] = [
+    "server",
+    "tls",
+    "admission",
+    "auth",
+    "attachment",
+    "dispatch",
+    "room",
+    "conn",
+    "moderation",
+    "notify",
+    "log",
+    "retention",
+];
+
+/// longest reason a websocket close frame can carry.
+const MAX_CLOSE_REASON: usize = 123;
+
+/// keys that have been published and must never sign anything.
+const PUBLIC_KEYS: [&str; 2] = ["aoquoquoeq", "eiqhfnvaqzp"];
+
+/// server configuration. every field but the auth keys has a default.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    /// without it the websocket listener speaks plain tcp.
+    pub tls: Option<TlsConfig>,
+    pub admission: AdmissionConfig,
+    pub auth: AuthConfig,
+    pub attachment: AttachmentConfig,
+    pub dispatch: DispatchConfig,
+    pub room: RoomConfig,
+    pub conn: ConnConfig,
+    pub moderation: ModerationConfig,
+    pub notify: NotifyConfig,
+    pub log: LogConfig,
+    /// without a policy history is kept forever.
+    pub retention: Option<RetentionPolicy>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// websocket listener.
+    pub bind: SocketAddr,
+    /// attachments api listener, for clients.
+    pub http_bind: SocketAddr,
+    /// health probes, metrics and admin api listener, for operators.
+    pub admin_bind: SocketAddr,
+    /// database, journal and attachments live here.
+    pub data_dir: PathBuf,
+    /// custom catalogs here override and extend the builtin ones.
+    pub locales_dir: PathBuf,
+    /// on SIGTERM, how long clients get to leave before the process exits anyway.
+    pub shutdown_deadline_secs: u64,
+    /// close reason of conns closed by a shutdown, e.g. the address of another node.
+    pub reconnect_hint: Option<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind: SocketAddr::from(([0, 0, 0, 0], 9001)),
+            http_bind: SocketAddr::from(([0, 0, 0, 0], 9002)),
+            admin_bind: SocketAddr::from(([0, 0, 0, 0], 9003)),
+            data_dir: PathBuf::from("data"),
+            locales_dir: PathBuf::from("locales"),
+            shutdown_deadline_secs: 30,
+            reconnect_hint: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn db_path(&self) -> PathBuf {
+        self.data_dir.join("im.db")
+    }
+
+    pub fn journal_dir(&self) -> PathBuf {
+        self.data_dir.join("journal")
+    }
+
+    pub fn attachments_dir(&self) -> PathBuf {
+        self.data_dir.join("attachments")
+    }
+
+    pub fn shutdown_deadline(&self) -> Duration {
+        Duration::from_secs(self.shutdown_deadline_secs)
+    }
+}
+
+/// wss termination on the websocket listener. the files are read again on SIGHUP and when they change.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    /// pem certificate chain, leaf first.
+    pub cert_path: PathBuf,
+    /// pem private key of the certificate.
+    pub key_path: PathBuf,
+    /// pem roots client certificates are verified against, e.g. the ca of internal bots.
+    pub client_ca_path: Option<PathBuf>,
+    /// turn away clients without a certificate. without it a certificate is only checked when presented.
+    #[serde(default)]
+    pub require_client_cert: bool,
+}
+
+/// which tcp conns get as far as the websocket handshake.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdmissionConfig {
+    /// open conns, handshaking or not.
+    pub max_conns: usize,
+    /// open conns from one ip.
+    pub max_conns_per_ip: usize,
+    /// conns one ip may open per minute.
+    pub handshakes_per_ip_per_minute: u32,
+    /// tls and websocket handshakes not done by then are dropped.
+    pub handshake_timeout_secs: u64,
+    /// when not empty, only peers in these networks are admitted.
+    pub allow: Vec<IpNet>,
+    /// peers in these networks are never admitted, even when allowed.
+    pub deny: Vec<IpNet>,
+}
+
+impl Default for AdmissionConfig {
+    fn default() -> Self {
+        AdmissionConfig {
+            max_conns: 10_000,
+            max_conns_per_ip: 100,
+            handshakes_per_ip_per_minute: 120,
+            handshake_timeout_secs: 10,
+            allow: Vec::new(),
+            deny: Vec::new(),
+        }
+    }
+}
+
+impl AdmissionConfig {
+    pub fn handshake_timeout(&self) -> Duration {
+        Duration::from_secs(self.handshake_timeout_secs)
+    }
+}
+
+/// there are no default keys, both must be configured.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// hmac secret of the jwts members connect with.
+    pub jwt_secret: String,
+    /// hmac key of signed attachment urls.
+    pub attachment_signing_key: String,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct AttachmentConfig {
+    /// largest file a client may upload, in bytes.
+    pub max_upload_size: usize,
+    /// mime types a client may upload.
+    pub allowed_mime_types: Vec<String>,
+    /// how long a signed download url stays valid.
+    pub download_url_ttl_secs: u64,
+}
+
+impl Default for AttachmentConfig {
+    fn default() -> Self {
+        AttachmentConfig {
+            max_upload_size: 10 * 1024 * 1024,
+            allowed_mime_types: ["image/png", "image/jpeg", "image/gif", "image/webp", "application/pdf", "text/plain"]
+                .map(String::from)
+                .to_vec(),
+            download_url_ttl_secs: 15 * 60,
+        }
+    }
+}
+
+impl AttachmentConfig {
+    pub fn download_url_ttl(&self) -> Duration {
+        Duration::from_secs(self.download_url_ttl_secs)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct DispatchConfig {
+    /// capacity of each dispatch mailbox.
+    pub channel_capacity: usize,
+    /// how often a waiting customer is handed to a customer service.
+    pub auto_dispatch_interval_secs: u64,
+    /// customers beyond this are turned away instead of waiting.
+    pub max_waiting_queue_size: usize,
+    /// how long the rooms of a disconnected customer stay open for it to come back.
+    pub customer_grace_secs: u64,
+}
+
+impl Default for DispatchConfig {
+    fn default() -> Self {
+        DispatchConfig {
+            channel_capacity: 100,
+            auto_dispatch_interval_secs: 10,
+            max_waiting_queue_size: 1000,
+            customer_grace_secs: 10 * 60,
+        }
+    }
+}
+
+impl DispatchConfig {
+    pub fn auto_dispatch_interval(&self) -> Duration {
+        Duration::from_secs(self.auto_dispatch_interval_secs)
+    }
+
+    pub fn customer_grace(&self) -> Duration {
+        Duration::from_secs(self.customer_grace_secs)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoomConfig {
+    /// capacity of each room mailbox.
+    pub channel_capacity: usize,
+    /// how long a sender may recall or edit a message.
+    pub edit_window_secs: u64,
+    /// messages sent to a member when it joins.
+    pub history_on_join: u32,
+    /// messages kept per offline member. older ones are dropped but still counted as unread.
+    pub inbox_size: usize,
+}
+
+impl Default for RoomConfig {
+    fn default() -> Self {
+        RoomConfig {
+            channel_capacity: 100,
+            edit_window_secs: 120,
+            history_on_join: 20,
+            inbox_size: 200,
+        }
+    }
+}
+
+impl RoomConfig {
+    pub fn edit_window(&self) -> Duration {
+        Duration::from_secs(self.edit_window_secs)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConnConfig {
+    /// capacity of each conn mailbox.
+    pub channel_capacity: usize,
+    /// largest websocket frame a client may send, in bytes.
+    pub max_frame_size: usize,
+    /// largest message a client may send, in bytes, over all of its frames.
+    pub max_message_size: usize,
+    /// longest body of a client message, in characters. file names, card fields and button labels too.
+    pub max_body_len: usize,
+    /// max_body_len by message type, e.g. `Chat = 2000`.
+    pub body_limits: HashMap<MessageType, usize>,
+    /// limits on what a client may send.
+    pub rate_limit: RateLimitConfig,
+}
+
+impl Default for ConnConfig {
+    fn default() -> Self {
+        ConnConfig {
+            channel_capacity: 100,
+            max_frame_size: 64 * 1024,
+            max_message_size: 64 * 1024,
+            max_body_len: 4000,
+            body_limits: HashMap::new(),
+            rate_limit: RateLimitConfig::default(),
+        }
+    }
+}
+
+impl ConnConfig {
+    pub fn max_body_len(&self, msg_type: MessageType) -> usize {
+        self.body_limits.get(&msg_type).copied().unwrap_or(self.max_body_len)
+    }
+
+    pub fn websocket(&self) -> WebSocketConfig {
+        WebSocketConfig {
+            max_frame_size: Some(self.max_frame_size),
+            max_message_size: Some(self.max_message_size),
+            ..WebSocketConfig::default()
+        }
+    }
+}
+
+/// what happens to a message over the limits.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Penalty {
+    /// drop it and answer with an error frame.
+    #[default]
+    Reject,
+    /// drop it and every message of the conn for mute_secs.
+    Mute,
+    /// close the conn.
+    Disconnect,
+}
+
+impl Penalty {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Penalty::Reject => "reject",
+            Penalty::Mute => "mute",
+            Penalty::Disconnect => "disconnect",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub penalty: Penalty,
+    /// how long the mute penalty lasts.
+    pub mute_secs: u64,
+    /// limits of each conn.
+    pub conn: BucketConfig,
+    /// limits shared by every conn of a member.
+    pub member: BucketConfig,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            penalty: Penalty::default(),
+            mute_secs: 30,
+            conn: BucketConfig {
+                messages_per_sec: 10.0,
+                messages_burst: 20.0,
+                bytes_per_sec: 64.0 * 1024.0,
+                bytes_burst: 256.0 * 1024.0,
+            },
+            member: BucketConfig {
+                messages_per_sec: 20.0,
+                messages_burst: 40.0,
+                bytes_per_sec: 128.0 * 1024.0,
+                bytes_burst: 512.0 * 1024.0,
+            },
+        }
+    }
+}
+
+impl RateLimitConfig {
+    pub fn mute(&self) -> Duration {
+        Duration::from_secs(self.mute_secs)
+    }
+}
+
+/// token buckets refilled at a steady rate, up to a burst.
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(deny_unknown_fields)]
+pub struct BucketConfig {
+    pub messages_per_sec: f64,
+    pub messages_burst: f64,
+    pub bytes_per_sec: f64,
+    pub bytes_burst: f64,
+}
+
+/// what a moderation filter does with a message it matches.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    /// replace the matched text with stars.
+    Mask,
+    /// deliver it and ask online supervisors to review it.
+    Flag,
+    /// drop it and tell the sender.
+    Block,
+}
+
+impl Action {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::Mask => "mask",
+            Action::Flag => "flag",
+            Action::Block => "block",
+        }
+    }
+}
+
+/// filters run on the text of every message, its body, file names, card fields and button labels, in the order pii, word lists, rules.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModerationConfig {
+    /// senders whose messages are moderated.
+    pub user_types: Vec<UserType>,
+    /// what happens to card numbers and numbers written like phone numbers. without it they are left alone.
+    pub pii: Option<Action>,
+    pub word_lists: Vec<WordListConfig>,
+    pub rules: Vec<RuleConfig>,
+}
+
+impl Default for ModerationConfig {
+    fn default() -> Self {
+        ModerationConfig {
+            user_types: vec![UserType::Customer, UserType::CustomerService],
+            pii: Some(Action::Mask),
+            word_lists: Vec::new(),
+            rules: Vec::new(),
+        }
+    }
+}
+
+/// words matched anywhere in a body, ignoring ascii case.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct WordListConfig {
+    /// reported to supervisors and in the audit log.
+    pub name: String,
+    pub action: Action,
+    #[serde(default)]
+    pub words: Vec<String>,
+    /// more words, one per line. lines starting with # are skipped.
+    pub path: Option<PathBuf>,
+}
+
+/// a regular expression matched against a body.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RuleConfig {
+    /// reported to supervisors and in the audit log.
+    pub name: String,
+    pub action: Action,
+    pub pattern: String,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotifyConfig {
+    /// offline members are notified of their first unread message here.
+    pub webhook_url: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// one json object per line, with the fields of the enclosing spans.
+    #[default]
+    Json,
+    Text,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub format: LogFormat,
+    /// level of modules not listed in modules.
+    pub level: String,
+    /// level per module, e.g. `"im::session" = "debug"`.
+    pub modules: BTreeMap<String, String>,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            format: LogFormat::default(),
+            level: "info".to_string(),
+            modules: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// defaults, overridden by the config file, then the environment, then command line flags.
+    /// nothing is validated, commands check what they need, e.g. the server with validate.
+    pub fn load(args: &ServeArgs) -> Result<Self> {
+        let path = args
+            .config
+            .clone()
+            .or_else(|| std::env::var_os("IM_CONFIG").map(PathBuf::from))
+            .or_else(|| Path::new(DEFAULT_CONFIG_FILE).is_file().then(|| PathBuf::from(DEFAULT_CONFIG_FILE)));
+
+        let mut table = match &path {
+            Some(path) => {
+                let raw = fs::read_to_string(path).with_context(|| format!("read config {}", path.display()))?;
+                toml::from_str(&raw).with_context(|| format!("parse config {}", path.display()))?
+            }
+            None => toml::Table::new(),
+        };
+
+        apply_env(&mut table, std::env::vars())?;
+
+        let mut config: Config = toml::Value::Table(table).try_into().context("invalid config")?;
+
+        if let Some(bind) = args.bind {
+            config.server.bind = bind;
+        }
+        if let Some(http_bind) = args.http_bind {
+            config.server.http_bind = http_bind;
+        }
+        if let Some(admin_bind) = args.admin_bind {
+            config.server.admin_bind = admin_bind;
+        }
+        if let Some(data_dir) = &args.data_dir {
+            config.server.data_dir = data_dir.clone();
+        }
+
+        Ok(config)
+    }
+
+    /// reject values the server can't run with.
+    pub fn validate(&self) -> Result<()> {
+        let positive = [
+            ("admission.max_conns", self.admission.max_conns as u64),
+            ("admission.max_conns_per_ip", self.admission.max_conns_per_ip as u64),
+            (
+                "admission.handshakes_per_ip_per_minute",
+                self.admission.handshakes_per_ip_per_minute as u64,
+            ),
+            ("admission.handshake_timeout_secs", self.admission.handshake_timeout_secs),
+            ("dispatch.channel_capacity", self.dispatch.channel_capacity as u64),
+            ("dispatch.auto_dispatch_interval_secs", self.dispatch.auto_dispatch_interval_secs),
+            ("dispatch.max_waiting_queue_size", self.dispatch.max_waiting_queue_size as u64),
+            ("room.channel_capacity", self.room.channel_capacity as u64),
+            ("room.inbox_size", self.room.inbox_size as u64),
+            ("conn.channel_capacity", self.conn.channel_capacity as u64),
+            ("conn.max_frame_size", self.conn.max_frame_size as u64),
+            ("conn.max_message_size", self.conn.max_message_size as u64),
+            ("attachment.max_upload_size", self.attachment.max_upload_size as u64),
+            ("attachment.download_url_ttl_secs", self.attachment.download_url_ttl_secs),
+        ];
+        for (key, value) in positive {
+            if value == 0 {
+                bail!("{} must be greater than 0", key);
+            }
+        }
+
+        if self.conn.max_frame_size > self.conn.max_message_size {
+            bail!("conn.max_frame_size must not exceed conn.max_message_size");
+        }
+
+        let limits = &self.conn.rate_limit;
+        for (scope, bucket) in [("conn", &limits.conn), ("member", &limits.member)] {
+            let rates = [
+                ("messages_per_sec", bucket.messages_per_sec),
+                ("messages_burst", bucket.messages_burst),
+                ("bytes_per_sec", bucket.bytes_per_sec),
+                ("bytes_burst", bucket.bytes_burst),
+            ];
+            for (key, value) in rates {
+                if !(value.is_finite() && value > 0.0) {
+                    bail!("conn.rate_limit.{}.{} must be greater than 0", scope, key);
+                }
+            }
+        }
+
+        let binds = [
+            ("server.bind", self.server.bind),
+            ("server.http_bind", self.server.http_bind),
+            ("server.admin_bind", self.server.admin_bind),
+        ];
+        for (i, (key, addr)) in binds.iter().enumerate() {
+            if let Some((other, _)) = binds[i + 1..].iter().find(|(_, other)| other == addr) {
+                bail!("{} and {} must differ, both are {}", key, other, addr);
+            }
+        }
+
+        if let Some(hint) = &self.server.reconnect_hint {
+            if hint.len() > MAX_CLOSE_REASON {
+                bail!(
+                    "server.reconnect_hint must be at most {} bytes, it is sent as a close reason",
+                    MAX_CLOSE_REASON
+                );
+            }
+        }
+
+        if let Some(tls) = &self.tls {
+            if tls.require_client_cert && tls.client_ca_path.is_none() {
+                bail!("tls.require_client_cert needs tls.client_ca_path");
+            }
+        }
+
+        for (key, value) in [
+            ("auth.jwt_secret", &self.auth.jwt_secret),
+            ("auth.attachment_signing_key", &self.auth.attachment_signing_key),
+        ] {
+            if value.is_empty() {
+                bail!("{} must be set", key);
+            }
+            if PUBLIC_KEYS.contains(&value.as_str()) {
+                bail!("{} is a published key, set a secret one", key);
+            }
+        }
+
+        if self.attachment.allowed_mime_types.is_empty() {
+            bail!("attachment.allowed_mime_types must not be empty");
+        }
+        for mime in &self.attachment.allowed_mime_types {
+            if !mime.split_once('/').is_some_and(|(kind, sub)| !kind.is_empty() && !sub.is_empty()) {
+                bail!("attachment.allowed_mime_types: {:?} is not a mime type", mime);
+            }
+        }
+
+        if let Some(url) = &self.notify.webhook_url {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                bail!("notify.webhook_url must be an http or https url, got {:?}", url);
+            }
+        }
+
+        logging::filter(&self.log)?;
+        moderation::Moderation::new(&self.moderation)?;
+
+        if let Some(retention) = &self.retention {
+            if retention.interval_secs == 0 {
+                bail!("retention.interval_secs must be greater than 0");
+            }
+
+            let mut named = HashSet::new();
+            for rule in &retention.rules {
+                let types = rule.msg_type.map_or("the types no other rule names", |msg_type| msg_type.as_str());
+                if rule.days == 0 {
+                    bail!("retention.rules for {}: days must be greater than 0", types);
+                }
+                if !named.insert(rule.msg_type) {
+                    bail!("retention.rules: more than one rule for {}", types);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// set `IM_<SECTION>_<KEY>` variables into table. values are read as toml, falling back to a string,
+/// so a string that reads as a number needs quotes.
+fn apply_env(table: &mut toml::Table, vars: impl Iterator<Item = (String, String)>) -> Result<()> {
+    for (name, raw) in vars {
+        let rest = match name.strip_prefix(ENV_PREFIX) {
+            Some(rest) => rest.to_lowercase(),
+            None => continue,
+        };
+
+        let found = SECTIONS
+            .iter()
+            .find_map(|section| Some((*section, rest.strip_prefix(section)?.strip_prefix('_')?)));
+        let (section, key) = match found {
+            Some(found) => found,
+            None => continue,
+        };
+        if key.is_empty() {
+            bail!("{} names no key of [{}]", name, section);
+        }
+
+        let value = toml::from_str::<toml::Table>(&format!("value = {}", raw))
+            .ok()
+            .and_then(|mut parsed| parsed.remove("value"))
+            .unwrap_or(toml::Value::String(raw));
+
+        match table.entry(section).or_insert_with(|| toml::Value::Table(toml::Table::new())) {
+            toml::Value::Table(section) => {
+                section.insert(key.to_string(), value);
+            }
+            _ => bail!("{} overrides [{}], which is not a table in the config file", name, section),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// config parsed from raw, with secret keys unless raw sets its own [auth].
+    fn parse(raw: &str) -> Result<Config> {
+        let mut table: toml::Table = toml::from_str(raw)?;
+        if !table.contains_key("auth") {
+            table.insert(
+                "auth".to_string(),
+                toml::from_str::<toml::Table>("jwt_secret = \"s1\"\nattachment_signing_key = \"s2\"")?.into(),
+            );
+        }
+
+        let config: Config = toml::Value::Table(table).try_into()?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn error(raw: &str) -> String {
+        format!("{:#}", parse(raw).unwrap_err())
+    }
+
+    #[test]
+    fn retention_rules_are_checked() {
+        let config = parse("[retention]\n[[retention.rules]]\nmsg_type = \"Image\"\ndays = 30\naction = \"delete\"\n[[retention.rules]]\ndays = 365\naction = \"anonymize\"").unwrap();
+        assert_eq!(config.retention.unwrap().rules.len(), 2);
+
+        assert!(error("[retention]\n[[retention.rules]]\ndays = 0\naction = \"delete\"").contains("days must be greater than 0"));
+        assert!(error("[retention]\ninterval_secs = 0").contains("retention.interval_secs"));
+
+        let duplicate = "[retention]\n[[retention.rules]]\nmsg_type = \"Chat\"\ndays = 30\naction = \"delete\"\n[[retention.rules]]\nmsg_type = \"Chat\"\ndays = 60\naction = \"delete\"";
+        assert!(error(duplicate).contains("more than one rule for Chat"));
+        let duplicate = "[retention]\n[[retention.rules]]\ndays = 30\naction = \"delete\"\n[[retention.rules]]\ndays = 60\naction = \"delete\"";
+        assert!(error(duplicate).contains("more than one rule for the types"));
+
+        assert!(error("[retention]\nintervl_secs = 60").contains("unknown field"));
+        assert!(error("[retention]\n[[retention.rules]]\ndays = 30\naction = \"delete\"\nmsgtype = \"Chat\"").contains("unknown field"));
+    }
+
+    fn env(raw: &str, vars: &[(&str, &str)]) -> Result<toml::Table> {
+        let mut table: toml::Table = toml::from_str(raw)?;
+        apply_env(&mut table, vars.iter().map(|(name, value)| (name.to_string(), value.to_string())))?;
+
+        Ok(table)
+    }
+
+    #[test]
+    fn env_overrides_keys_of_sections() {
+        let table = env(
+            "[dispatch]\nchannel_capacity = 10\nmax_waiting_queue_size = 20",
+            &[
+                ("IM_DISPATCH_MAX_WAITING_QUEUE_SIZE", "50"),
+                ("IM_AUTH_JWT_SECRET", "s1"),
+                ("IM_ATTACHMENT_ALLOWED_MIME_TYPES", "[\"image/png\"]"),
+                ("IM_SERVER_RECONNECT_HINT", "wss://im-2.example.com"),
+                ("IM_SERVER_LOCALES_DIR", "\"123\""),
+                ("IM_CONFIG", "im.toml"),
+                ("HOME", "/root"),
+            ],
+        )
+        .unwrap();
+
+        let config: Config = toml::Value::Table(table).try_into().unwrap();
+        assert_eq!(config.dispatch.channel_capacity, 10);
+        assert_eq!(config.dispatch.max_waiting_queue_size, 50);
+        assert_eq!(config.auth.jwt_secret, "s1");
+        assert_eq!(config.attachment.allowed_mime_types, ["image/png"]);
+        assert_eq!(config.server.reconnect_hint.as_deref(), Some("wss://im-2.example.com"));
+        assert_eq!(config.server.locales_dir, PathBuf::from("123"));
+    }
+
+    #[test]
+    fn env_names_a_key_of_a_table() {
+        assert!(format!("{:#}", env("", &[("IM_DISPATCH_", "1")]).unwrap_err()).contains("names no key of [dispatch]"));
+        assert!(format!("{:#}", env("dispatch = 1", &[("IM_DISPATCH_CHANNEL_CAPACITY", "1")]).unwrap_err()).contains("not a table"));
+
+        // unknown sections are left alone, unknown keys are refused with the rest of the file.
+        assert!(env("", &[("IM_NOPE_KEY", "1")]).unwrap().is_empty());
+        let table = env("", &[("IM_DISPATCH_NOPE", "1")]).unwrap();
+        assert!(toml::Value::Table(table).try_into::<Config>().is_err());
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        let config = parse("").unwrap();
+        assert_eq!(config.server.bind, SocketAddr::from(([0, 0, 0, 0], 9001)));
+        assert_eq!(config.attachment.max_upload_size, 10 * 1024 * 1024);
+    }
+
+    #[test]
+    fn secret_keys_must_be_set_and_unpublished() {
+        assert!(error("[auth]\nattachment_signing_key = \"s2\"").contains("auth.jwt_secret must be set"));
+        assert!(error("[auth]\njwt_secret = \"s1\"").contains("auth.attachment_signing_key must be set"));
+        assert!(error(&format!("[auth]\njwt_secret = \"{}\"\nattachment_signing_key = \"s2\"", PUBLIC_KEYS[0])).contains("published key"));
+        assert!(error(&format!("[auth]\njwt_secret = \"s1\"\nattachment_signing_key = \"{}\"", PUBLIC_KEYS[1])).contains("published key"));
+    }
+
+    #[test]
+    fn values_the_server_cant_run_with_are_rejected() {
+        assert!(error("[dispatch]\nchannel_capacity = 0").contains("dispatch.channel_capacity must be greater than 0"));
+        assert!(error("[attachment]\ndownload_url_ttl_secs = 0").contains("attachment.download_url_ttl_secs"));
+        assert!(error("[attachment]\nallowed_mime_types = []").contains("must not be empty"));
+        assert!(error("[attachment]\nallowed_mime_types = [\"png\"]").contains("is not a mime type"));
+        assert!(error("[conn]\nmax_frame_size = 2048\nmax_message_size = 1024").contains("must not exceed"));
+        assert!(
+            error("[conn.rate_limit.conn]\nmessages_per_sec = 0.0\nmessages_burst = 1.0\nbytes_per_sec = 1.0\nbytes_burst = 1.0")
+                .contains("conn.rate_limit.conn.messages_per_sec")
+        );
+        assert!(error("[server]\nhttp_bind = \"0.0.0.0:9001\"").contains("server.bind and server.http_bind must differ"));
+        assert!(error(&format!("[server]\nreconnect_hint = \"{}\"", "a".repeat(MAX_CLOSE_REASON + 1))).contains("server.reconnect_hint"));
+        assert!(error("[tls]\ncert_path = \"c.pem\"\nkey_path = \"k.pem\"\nrequire_client_cert = true").contains("needs tls.client_ca_path"));
+        assert!(error("[notify]\nwebhook_url = \"ftp://example.com\"").contains("notify.webhook_url"));
+        assert!(error("[log]\nlevel = \"loud\"").contains("loud"));
+        assert!(error("[server]\nbnd = \"0.0.0.0:9001\"").contains("unknown field"));
+    }
+
+    #[test]
+    fn load_leaves_validation_to_the_command() {
+        let dir = std::env::temp_dir().join(format!("im-config-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("im.toml");
+        // what export needs, without the keys and with binds only the server would mind.
+        fs::write(&path, "[server]\ndata_dir = \"/var/lib/im\"\nhttp_bind = \"0.0.0.0:9001\"").unwrap();
+
+        let args = ServeArgs {
+            config: Some(path),
+            bind: None,
+            http_bind: None,
+            admin_bind: Some("127.0.0.1:9103".parse().unwrap()),
+            data_dir: None,
+        };
+        let config = Config::load(&args).unwrap();
+        assert_eq!(config.server.data_dir, PathBuf::from("/var/lib/im"));
+        assert_eq!(config.server.admin_bind, args.admin_bind.unwrap());
+        assert!(config.validate().is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,74 @@
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::auth::{Member, RoomId};
+
+/// an online customer service and the rooms it serves.
+#[derive(Serialize, Debug, Clone)]
+pub struct AgentInfo {
+    pub conn_id: String,
+    pub member: Member,
+    /// open rooms of the agent.
+    pub load: usize,
+    pub rooms: Vec<RoomId>,
+}
+
+/// a member online in a room.
+#[derive(Serialize, Debug, Clone)]
+pub struct ConnInfo {
+    pub conn_id: String,
+    pub member: Member,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct RoomInfo {
+    pub room_id: RoomId,
+    pub online: Vec<ConnInfo>,
+    /// everyone who joined the room, online or not.
+    pub participants: Vec<Member>,
+    /// last sequence assigned to a message.
+    pub seq: u64,
+}
+
+/// a customer in the waiting queue.
+#[derive(Serialize, Debug, Clone)]
+pub struct WaitingInfo {
+    pub conn_id: String,
+    pub member: Member,
+    /// 0 is dispatched next.
+    pub position: usize,
+    pub waiting_secs: u64,
+}
+
+/// why a customer couldn't be assigned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssignError {
+    CustomerNotWaiting,
+    AgentNotOnline,
+}
+
+impl fmt::Display for AssignError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssignError::CustomerNotWaiting => write!(f, "customer is not waiting"),
+            AssignError::AgentNotOnline => write!(f, "agent is not online"),
+        }
+    }
+}
+
+/// why a supervisor couldn't join a room.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SuperviseError {
+    RoomNotFound,
+    SupervisorNotOnline,
+}
+
+impl fmt::Display for SuperviseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SuperviseError::RoomNotFound => write!(f, "room is not open"),
+            SuperviseError::SupervisorNotOnline => write!(f, "supervisor is not online"),
+        }
+    }
+}
@@ -19,8 +19,12 @@ impl<T> Cursor<T> {
         Some(item)
     }
 
-    pub fn len(&self) -> usize {
-        self.items.len()
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.items.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut T> {
+        self.items.iter_mut()
     }
 
     pub fn is_empty(&self) -> bool {
@@ -30,4 +34,13 @@ impl<T> Cursor<T> {
     pub fn push(&mut self, item: T) {
         self.items.push(item);
     }
+
+    /// keep the items matching f. the cursor stays within bounds.
+    pub fn retain(&mut self, f: impl FnMut(&T) -> bool) {
+        self.items.retain(f);
+
+        if self.cursor >= self.items.len() {
+            self.cursor = 0;
+        }
+    }
 }
@@ -1,19 +1,27 @@
 use std::{
-    collections::{HashMap, VecDeque},
-    time::Duration,
+    collections::{HashMap, HashSet, VecDeque},
+    time::Instant,
 };
 
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, error, info, info_span, warn, Instrument};
+use tungstenite::protocol::frame::coding::CloseCode;
 
 use crate::{
-    auth::RoomId,
-    message::internal::{ConnMessage, DispatchMessage, SessionMessage},
+    auth::{audit, Member, RoomId},
+    config::{Config, DispatchConfig, RoomConfig},
+    journal::{JournalEntry, JournalHandle, Recovered},
+    message::internal::{AdminMessage, ConnMessage, DispatchMessage, RoomMessage, SessionMessage},
+    metrics::{self, metrics},
+    notify::Webhook,
     session::{conn::ConnHandle, room::RoomHandle},
+    store::StoreHandle,
 };
 
-use super::collection::Cursor;
-
-const MAX_WAITING_QUEUE_SIZE: usize = 100;
+use super::{
+    admin::{AgentInfo, AssignError, RoomInfo, SuperviseError, WaitingInfo},
+    collection::Cursor,
+};
 
 pub struct Manager {
     /// rooms
@@ -25,6 +33,48 @@ pub struct Manager {
     /// waiting queue.  no dispatch conns
     waiting_queue: VecDeque<ConnHandle>,
 
+    /// every online conn by conn id.
+    conns: HashMap<String, ConnHandle>,
+
+    /// open rooms of every member. used to rejoin after reconnecting.
+    memberships: HashMap<Member, HashSet<RoomId>>,
+
+    /// rooms record their history here.
+    store: StoreHandle,
+
+    /// rooms and the waiting queue are journaled, so they survive a restart.
+    journal: JournalHandle,
+
+    /// queue positions of customers that were waiting before a restart.
+    recovered_turns: HashMap<Member, usize>,
+
+    /// when the turns were recovered. those not taken up within the customer grace are dropped.
+    recovered_at: Instant,
+
+    /// ids of customers kept out, their conns are turned away.
+    held_customers: HashSet<String>,
+
+    /// customers with open rooms but no conn, since when.
+    offline_customers: HashMap<Member, Instant>,
+
+    /// rooms notify offline members here.
+    webhook: Option<Webhook>,
+
+    /// queue limit, auto dispatch interval and customer grace.
+    config: DispatchConfig,
+
+    /// rooms are created with this.
+    room_config: RoomConfig,
+
+    /// the node is shutting down, conns are closed as they come.
+    draining: bool,
+
+    /// close reason of conns closed by the drain.
+    reconnect_hint: Option<String>,
+
+    /// drain requests answered once the last conn leaves.
+    drained: Vec<oneshot::Sender<()>>,
+
     /// receive message from session
     mailbox_session: mpsc::Receiver<SessionMessage>,
 
@@ -36,6 +86,10 @@ impl Manager {
     pub fn new(
         mailbox_session: mpsc::Receiver<SessionMessage>,
         mailbox_conn: mpsc::Receiver<ConnMessage>,
+        store: StoreHandle,
+        journal: JournalHandle,
+        webhook: Option<Webhook>,
+        config: &Config,
     ) -> Self {
         Manager {
             rooms: HashMap::new(),
@@ -43,7 +97,42 @@ impl Manager {
             mailbox_session,
             mailbox_conn,
             waiting_queue: VecDeque::new(),
+            conns: HashMap::new(),
+            memberships: HashMap::new(),
+            store,
+            journal,
+            recovered_turns: HashMap::new(),
+            recovered_at: Instant::now(),
+            held_customers: HashSet::new(),
+            offline_customers: HashMap::new(),
+            webhook,
+            config: config.dispatch.clone(),
+            room_config: config.room.clone(),
+            draining: false,
+            reconnect_hint: None,
+            drained: Vec::new(),
+        }
+    }
+
+    /// reopen the rooms and queue found in the journal. members go back to them as they reconnect.
+    fn restore(&mut self, recovered: Recovered) {
+        for room in recovered.rooms {
+            let room_id = room.room_id.clone();
+
+            for member in room.members.iter() {
+                self.memberships.entry(member.clone()).or_default().insert(room_id.clone());
+
+                // nobody is connected yet, customers get the usual time to come back.
+                if member.is_customer() {
+                    self.offline_customers.insert(member.clone(), Instant::now());
+                }
+            }
+
+            let room_handle = RoomHandle::restore(room, &self.room_config, self.store.clone(), self.journal.clone(), self.webhook.clone());
+            self.rooms.insert(room_id, room_handle);
         }
+
+        self.recovered_turns = recovered.waiting.into_iter().enumerate().map(|(turn, member)| (member, turn)).collect();
     }
 
     /// handle received message from session.
@@ -52,33 +141,281 @@ impl Manager {
             SessionMessage::OnAccept { conn } => {
                 self.add_session(conn).await;
             }
+            SessionMessage::IsRoomMember { room_id, member, respond_to } => {
+                let room_handle = match self.rooms.get(&room_id) {
+                    Some(room_handle) => room_handle.clone(),
+                    None => {
+                        let _ = respond_to.send(false);
+                        return;
+                    }
+                };
+
+                // ask the room without blocking the manager loop.
+                tokio::spawn(async move {
+                    let _ = respond_to.send(room_handle.is_member(member).await);
+                });
+            }
+            SessionMessage::HoldCustomer { member_id, respond_to } => {
+                let is_customer = |member: &Member| member.is_customer() && member.id() == member_id;
+
+                let active = self.memberships.keys().any(is_customer)
+                    || self.conns.values().any(|conn| is_customer(conn.identity()))
+                    || self.recovered_turns.keys().any(is_customer);
+
+                if !active {
+                    self.held_customers.insert(member_id);
+                }
+                let _ = respond_to.send(!active);
+            }
+            SessionMessage::ReleaseCustomer { member_id } => {
+                self.held_customers.remove(&member_id);
+            }
+            SessionMessage::Ping { respond_to } => {
+                let _ = respond_to.send(());
+            }
+            SessionMessage::Admin(msg) => {
+                self.handle_admin_message(msg).await;
+            }
+            SessionMessage::Drain { hint, respond_to } => {
+                info!(conns = self.conns.len(), "draining conns");
+                self.draining = true;
+                self.reconnect_hint = hint;
+                for conn in self.conns.values() {
+                    conn.send_message(self.restarting()).await;
+                }
+
+                self.drained.push(respond_to);
+                self.notify_drained();
+            }
+        }
+    }
+
+    /// tells a client the node is going away, and where to reconnect.
+    fn restarting(&self) -> RoomMessage {
+        RoomMessage::Disconnect {
+            code: CloseCode::Restart,
+            key: "tips.server_restarting".to_string(),
+            hint: self.reconnect_hint.clone(),
+        }
+    }
+
+    /// answer drain requests once no conn is left.
+    fn notify_drained(&mut self) {
+        if self.draining && self.conns.is_empty() {
+            for respond_to in self.drained.drain(..) {
+                let _ = respond_to.send(());
+            }
+        }
+    }
+
+    /// handle requests of the admin api.
+    async fn handle_admin_message(&mut self, msg: AdminMessage) {
+        match msg {
+            AdminMessage::ListAgents { respond_to } => {
+                let agents = self
+                    .customer_services
+                    .iter()
+                    .map(|conn| {
+                        let mut rooms: Vec<RoomId> = self.memberships.get(conn.identity()).into_iter().flatten().cloned().collect();
+                        rooms.sort();
+
+                        AgentInfo {
+                            conn_id: conn.conn_id().to_string(),
+                            member: conn.identity().clone(),
+                            load: rooms.len(),
+                            rooms,
+                        }
+                    })
+                    .collect();
+
+                let _ = respond_to.send(agents);
+            }
+            AdminMessage::ListRooms { respond_to } => {
+                let room_handles: Vec<RoomHandle> = self.rooms.values().cloned().collect();
+
+                // ask the rooms without blocking the manager loop.
+                tokio::spawn(async move {
+                    let mut rooms = Vec::new();
+                    for room_handle in room_handles {
+                        rooms.extend(room_handle.describe().await);
+                    }
+                    rooms.sort_by(|a, b| a.room_id.cmp(&b.room_id));
+
+                    let _ = respond_to.send(rooms);
+                });
+            }
+            AdminMessage::ListQueue { respond_to } => {
+                let waiting = self
+                    .waiting_queue
+                    .iter()
+                    .enumerate()
+                    .map(|(position, conn)| WaitingInfo {
+                        conn_id: conn.conn_id().to_string(),
+                        member: conn.identity().clone(),
+                        position,
+                        waiting_secs: conn.connected_at().elapsed().as_secs(),
+                    })
+                    .collect();
+
+                let _ = respond_to.send(waiting);
+            }
+            AdminMessage::Assign {
+                customer_id,
+                agent_id,
+                respond_to,
+            } => {
+                let _ = respond_to.send(self.assign(&customer_id, &agent_id).await);
+            }
+            AdminMessage::Supervise {
+                room_id,
+                supervisor,
+                respond_to,
+            } => {
+                let _ = respond_to.send(self.supervise(&room_id, &supervisor).await);
+            }
+            AdminMessage::CloseRoom { room_id, respond_to } => {
+                let open = self.rooms.contains_key(&room_id);
+                if open {
+                    self.close_room(&room_id).await;
+                }
+
+                let _ = respond_to.send(open);
+            }
+            AdminMessage::Kick { conn_id, respond_to } => {
+                let conn = match self.conns.get(&conn_id) {
+                    Some(conn) => conn.clone(),
+                    None => {
+                        let _ = respond_to.send(false);
+                        return;
+                    }
+                };
+
+                // the conn leaves like any other once it has closed.
+                let message = RoomMessage::Disconnect {
+                    code: CloseCode::Policy,
+                    key: "tips.kicked".to_string(),
+                    hint: None,
+                };
+                conn.send_message(message).await;
+
+                let _ = respond_to.send(true);
+            }
         }
     }
 
+    /// take a waiting customer out of the queue and open a room with agent, regardless of turns.
+    async fn assign(&mut self, customer_id: &str, agent_id: &str) -> Result<RoomId, AssignError> {
+        let agent = self
+            .customer_services
+            .iter()
+            .find(|conn| conn.identity().id() == agent_id)
+            .cloned()
+            .ok_or(AssignError::AgentNotOnline)?;
+
+        let index = self
+            .waiting_queue
+            .iter()
+            .position(|conn| conn.identity().is_customer() && conn.identity().id() == customer_id)
+            .ok_or(AssignError::CustomerNotWaiting)?;
+        let customer = self.waiting_queue.remove(index).unwrap(); // the index was just found.
+
+        self.recovered_turns.remove(customer.identity());
+        self.journal
+            .append(JournalEntry::Dequeued {
+                member: customer.identity().clone(),
+            })
+            .await;
+
+        Ok(self.create_room(customer, agent).await)
+    }
+
+    /// join the online conns of supervisor to an open room. its later conns join too, until the room closes.
+    async fn supervise(&mut self, room_id: &RoomId, supervisor: &Member) -> Result<(), SuperviseError> {
+        let room_handle = self.rooms.get(room_id).cloned().ok_or(SuperviseError::RoomNotFound)?;
+
+        let conns = self.conns_of(supervisor);
+        if conns.is_empty() {
+            return Err(SuperviseError::SupervisorNotOnline);
+        }
+
+        if self.memberships.entry(supervisor.clone()).or_default().insert(room_id.clone()) {
+            room_handle.join(conns).await;
+        }
+
+        Ok(())
+    }
+
     /// handle received message from conn.
     async fn handle_conn_message(&mut self, msg: ConnMessage) {
         match msg {
-            ConnMessage::OnLeave { member } => {
-                println!("member leave: {:?}", member);
+            ConnMessage::OnLeave { member, conn_id } => {
+                info!(member_id = member.id(), user_type = ?member.user_type(), %conn_id, "member leave");
+                self.conns.remove(&conn_id);
+                self.remove_session(member, conn_id).await;
+                self.notify_drained();
             }
-            ConnMessage::OnNewMessage { member, message } => {
+            ConnMessage::OnNewMessage {
+                member,
+                conn_id,
+                message,
+                span,
+            } => {
                 let room_id = message.room_id();
-                if let Some(room_handle) = self.rooms.get(room_id) {
-                    let dispatch_message = DispatchMessage::OnNewMessage { member, message };
-                    room_handle.new_message(dispatch_message).await;
+                let room_handle = match self.rooms.get(room_id) {
+                    Some(room_handle) => room_handle,
+                    None => {
+                        span.in_scope(|| audit::reject_message(&member, room_id, "room not found"));
+                        return;
+                    }
+                };
+
+                // the room verifies the sender is a member before broadcasting.
+                let dispatch_message = DispatchMessage::OnNewMessage {
+                    member,
+                    conn_id,
+                    message,
+                    span: span.clone(),
+                };
+                room_handle.new_message(dispatch_message).instrument(span).await;
+            }
+            ConnMessage::OnFlagged { member, message, reason } => {
+                let notice = RoomMessage::OnFlagged {
+                    room_id: message.room_id().clone(),
+                    member,
+                    content: message,
+                    reason,
+                };
+
+                for conn in self.conns.values().filter(|conn| conn.identity().is_supervisor()) {
+                    conn.send_message(notice.clone()).await;
                 }
             }
         }
     }
 
     /// create room and add conn to room.
-    async fn create_room(&mut self, c: ConnHandle, cs: ConnHandle) {
+    async fn create_room(&mut self, c: ConnHandle, cs: ConnHandle) -> RoomId {
         let room_id = format!("{}-{}", c.identity().id(), cs.identity().id());
+        metrics().first_assignment.observe(c.connected_at().elapsed().as_secs_f64());
+
+        let room_handle = RoomHandle::new(
+            room_id.clone(),
+            &self.room_config,
+            self.store.clone(),
+            self.journal.clone(),
+            self.webhook.clone(),
+        );
+        self.rooms.insert(room_id.clone(), room_handle.clone());
+
+        for member in [c.identity(), cs.identity()] {
+            self.memberships.entry(member.clone()).or_default().insert(room_id.clone());
+        }
 
-        let room_handle = RoomHandle::new(room_id.clone());
-        self.rooms.insert(room_id, room_handle.clone());
+        // every open conn of both members, not only those that were dispatched.
+        let conns = self.conns_of(c.identity()).into_iter().chain(self.conns_of(cs.identity())).collect();
+        room_handle.join(conns).await;
 
-        room_handle.join(vec![c, cs]).await;
+        room_id
     }
 
     /// dispatch customer to customer service.
@@ -89,12 +426,13 @@ impl Manager {
         }
 
         if self.customer_services.is_empty() {
-            self.waiting_queue.push_back(customer);
+            self.enqueue(customer).await;
             return;
         }
 
         let customer_service = self.customer_services.next().unwrap().clone(); // the unwrap forever safe.
-        self.create_room(customer, customer_service).await
+        self.recovered_turns.remove(customer.identity());
+        self.create_room(customer, customer_service).await;
     }
 
     // auto dispatch customer to customer service.
@@ -104,40 +442,238 @@ impl Manager {
         }
 
         if let Some(cs) = self.waiting_queue.pop_front() {
+            self.journal
+                .append(JournalEntry::Dequeued {
+                    member: cs.identity().clone(),
+                })
+                .await;
             self.dispatch(cs).await;
         }
     }
 
+    /// put customer in the waiting queue. customers that were waiting before a restart
+    /// keep their turn ahead of those who came after it. a full queue turns customers away.
+    async fn enqueue(&mut self, customer: ConnHandle) {
+        let member = customer.identity().clone();
+
+        if self.waiting_queue.len() >= self.config.max_waiting_queue_size && !self.recovered_turns.contains_key(&member) {
+            warn!(
+                member_id = member.id(),
+                waiting = self.waiting_queue.len(),
+                "waiting queue full, reject customer"
+            );
+            let message = RoomMessage::Disconnect {
+                code: CloseCode::Again,
+                key: "tips.queue_full".to_string(),
+                hint: None,
+            };
+            customer.send_message(message).await;
+            return;
+        }
+
+        match self.recovered_turns.get(&member) {
+            Some(turn) => {
+                let index = self
+                    .waiting_queue
+                    .iter()
+                    .position(|conn| self.recovered_turns.get(conn.identity()).is_none_or(|other| other > turn))
+                    .unwrap_or(self.waiting_queue.len());
+                self.waiting_queue.insert(index, customer);
+            }
+            None => self.waiting_queue.push_back(customer),
+        }
+
+        self.journal.append(JournalEntry::Enqueued { member }).await;
+    }
+
     /// add session. if conn is customer service, add to customer_services.
+    /// supervisors are never dispatched, they only speak in the rooms they supervise.
     /// else dispatch to customer service.
     async fn add_session(&mut self, conn: ConnHandle) {
+        // a handshake that finished after the drain began.
+        if self.draining {
+            conn.send_message(self.restarting()).await;
+            return;
+        }
+
+        if conn.identity().is_customer() && self.held_customers.contains(conn.identity().id()) {
+            let message = RoomMessage::Disconnect {
+                code: CloseCode::Again,
+                key: "tips.try_again_later".to_string(),
+                hint: None,
+            };
+            conn.send_message(message).await;
+            return;
+        }
+
+        self.conns.insert(conn.conn_id().to_string(), conn.clone());
+
+        // another conn of a member already online, e.g. a second tab, only goes to the rooms of the member.
+        // the member is dispatched or waiting through its first conn.
+        let online = self
+            .conns
+            .values()
+            .any(|other| other.identity() == conn.identity() && other.conn_id() != conn.conn_id());
+        if online {
+            self.rejoin(&conn).await;
+            return;
+        }
+
         if conn.identity().is_customer_service() {
             self.customer_services.push(conn.clone());
+            self.rejoin(&conn).await;
+            return;
+        }
+
+        if conn.identity().is_supervisor() {
+            self.rejoin(&conn).await;
+            return;
+        }
+
+        // a customer with an open conversation goes back to it.
+        self.offline_customers.remove(conn.identity());
+        if self.rejoin(&conn).await {
             return;
         }
 
         // try dispatch customer to customer service.
         self.dispatch(conn).await;
     }
+
+    /// the online conns of member.
+    fn conns_of(&self, member: &Member) -> Vec<ConnHandle> {
+        self.conns.values().filter(|conn| conn.identity() == member).cloned().collect()
+    }
+
+    /// join conn to the open rooms of its member. returns whether there were any.
+    async fn rejoin(&mut self, conn: &ConnHandle) -> bool {
+        let room_ids = match self.memberships.get(conn.identity()) {
+            Some(room_ids) => room_ids,
+            None => return false,
+        };
+
+        let mut joined = false;
+        for room_id in room_ids {
+            if let Some(room_handle) = self.rooms.get(room_id) {
+                room_handle.join(vec![conn.clone()]).await;
+                joined = true;
+            }
+        }
+
+        joined
+    }
+
+    /// remove conn_id of member. the member is only gone once its last conn is.
+    /// rooms stay open so the member can come back to them,
+    /// those of a customer that doesn't come back within the customer grace are closed.
+    async fn remove_session(&mut self, member: Member, conn_id: String) {
+        let room_ids = self.memberships.get(&member).cloned().unwrap_or_default();
+        for room_id in room_ids {
+            if let Some(room_handle) = self.rooms.get(&room_id) {
+                room_handle.leave(member.clone(), conn_id.clone()).await;
+            }
+        }
+
+        // the member is dispatched and waits through one conn, another one takes its place.
+        if let Some(other) = self.conns.values().find(|conn| conn.identity() == &member).cloned() {
+            for conn in self.customer_services.iter_mut().chain(self.waiting_queue.iter_mut()) {
+                if conn.conn_id() == conn_id {
+                    *conn = other.clone();
+                }
+            }
+            return;
+        }
+
+        self.customer_services.retain(|conn| conn.identity() != &member);
+
+        // customers sent away by a drain keep their turn in the journal for after the restart.
+        let waiting = self.waiting_queue.len();
+        self.waiting_queue.retain(|conn| conn.identity() != &member);
+        if self.waiting_queue.len() != waiting && !self.draining {
+            self.recovered_turns.remove(&member);
+            self.journal.append(JournalEntry::Dequeued { member: member.clone() }).await;
+        }
+
+        if member.is_customer() && self.memberships.contains_key(&member) {
+            self.offline_customers.insert(member, Instant::now());
+        }
+    }
+
+    /// close the rooms of customers offline for longer than the customer grace.
+    async fn close_abandoned(&mut self) {
+        let now = Instant::now();
+        let grace = self.config.customer_grace();
+        let abandoned: Vec<Member> = self
+            .offline_customers
+            .iter()
+            .filter(|(_, since)| now.duration_since(**since) >= grace)
+            .map(|(member, _)| member.clone())
+            .collect();
+
+        for member in abandoned {
+            self.offline_customers.remove(&member);
+
+            let room_ids = self.memberships.get(&member).cloned().unwrap_or_default();
+            for room_id in room_ids {
+                self.close_room(&room_id).await;
+            }
+        }
+    }
+
+    /// drop the recovered turns of customers that didn't come back within the customer grace,
+    /// so they aren't carried from snapshot to snapshot forever.
+    async fn expire_turns(&mut self) {
+        if self.recovered_turns.is_empty() || self.recovered_at.elapsed() < self.config.customer_grace() {
+            return;
+        }
+
+        info!(turns = self.recovered_turns.len(), "recovered turns expired");
+        for (member, _) in std::mem::take(&mut self.recovered_turns) {
+            // those back in the queue keep their place there.
+            if !self.waiting_queue.iter().any(|conn| conn.identity() == &member) {
+                self.journal.append(JournalEntry::Dequeued { member }).await;
+            }
+        }
+    }
+
+    /// close room and forget it.
+    async fn close_room(&mut self, room_id: &RoomId) {
+        if let Some(room_handle) = self.rooms.remove(room_id) {
+            room_handle.close().await;
+        }
+
+        self.memberships.retain(|_, room_ids| {
+            room_ids.remove(room_id);
+            !room_ids.is_empty()
+        });
+    }
 }
 
 async fn listener(mut dispatch: Manager) {
-    let mut interval = tokio::time::interval(Duration::from_secs(10));
+    let mailbox = metrics().mailbox(metrics::MANAGER);
+    let waiting = metrics().waiting.with_label_values(&[metrics::DEFAULT_QUEUE]);
+    let mut interval = tokio::time::interval(dispatch.config.auto_dispatch_interval());
 
     loop {
         tokio::select! {
             _ = interval.tick() => {
-                println!("auto dispatch");
+                debug!("auto dispatch");
                 dispatch.auto_dispatch().await;
+                dispatch.close_abandoned().await;
+                dispatch.expire_turns().await;
             }
 
             Some(msg) = dispatch.mailbox_session.recv() => {
+                mailbox.dec();
                 dispatch.handle_session_message(msg).await;
             }
             Some(msg) = dispatch.mailbox_conn.recv() => {
+                mailbox.dec();
                 dispatch.handle_conn_message(msg).await;
             }
         }
+
+        waiting.set(dispatch.waiting_queue.len() as i64);
     }
 }
 
@@ -148,29 +684,277 @@ pub struct DispatchHandle {
 }
 
 impl DispatchHandle {
-    pub fn new() -> Self {
-        let (sender_session, mailbox_session) = mpsc::channel(100);
-        let (sender_conn, mailbox_conn) = mpsc::channel(100);
+    pub fn new(config: &Config, store: StoreHandle, journal: JournalHandle, recovered: Recovered, webhook: Option<Webhook>) -> Self {
+        let (sender_session, mailbox_session) = mpsc::channel(config.dispatch.channel_capacity);
+        let (sender_conn, mailbox_conn) = mpsc::channel(config.dispatch.channel_capacity);
 
-        let dispatch = Manager::new(mailbox_session, mailbox_conn);
+        let mut dispatch = Manager::new(mailbox_session, mailbox_conn, store, journal, webhook, config);
+        dispatch.restore(recovered);
 
-        tokio::spawn(listener(dispatch));
+        tokio::spawn(listener(dispatch).instrument(info_span!(parent: None, "dispatch")));
 
-        DispatchHandle {
-            sender_session,
-            sender_conn,
-        }
+        DispatchHandle { sender_session, sender_conn }
     }
 
     /// Session calls this method to send a message to Dispatch
     pub async fn send_message(&self, message: SessionMessage) {
+        let mailbox = metrics().mailbox(metrics::MANAGER);
+        mailbox.inc();
         if let Err(err) = self.sender_session.send(message).await {
-            println!("send message error: {:?}", err);
+            mailbox.dec();
+            error!(error = %err, "send message to dispatch error");
         }
     }
 
+    /// return whether member joined room_id.
+    pub async fn is_room_member(&self, room_id: RoomId, member: Member) -> bool {
+        let (tx, rx) = oneshot::channel();
+
+        let message = SessionMessage::IsRoomMember {
+            room_id,
+            member,
+            respond_to: tx,
+        };
+
+        self.send_message(message).await;
+
+        rx.await.unwrap_or(false)
+    }
+
+    /// keep the customer with member_id from connecting until released.
+    /// returns false, holding nothing, when it is online, waiting or has an open room.
+    pub async fn hold_customer(&self, member_id: String) -> bool {
+        let (tx, rx) = oneshot::channel();
+
+        self.send_message(SessionMessage::HoldCustomer { member_id, respond_to: tx }).await;
+
+        rx.await.unwrap_or(false)
+    }
+
+    /// let a held customer connect again.
+    pub async fn release_customer(&self, member_id: String) {
+        self.send_message(SessionMessage::ReleaseCustomer { member_id }).await;
+    }
+
+    /// whether the manager loop answers.
+    pub async fn ping(&self) -> bool {
+        let (tx, rx) = oneshot::channel();
+
+        self.send_message(SessionMessage::Ping { respond_to: tx }).await;
+
+        rx.await.is_ok()
+    }
+
+    /// send an admin request and wait for its response. None when the manager is gone.
+    async fn admin<T>(&self, request: impl FnOnce(oneshot::Sender<T>) -> AdminMessage) -> Option<T> {
+        let (tx, rx) = oneshot::channel();
+
+        self.send_message(SessionMessage::Admin(request(tx))).await;
+
+        rx.await.ok()
+    }
+
+    /// online customer services and their rooms.
+    pub async fn list_agents(&self) -> Option<Vec<AgentInfo>> {
+        self.admin(|respond_to| AdminMessage::ListAgents { respond_to }).await
+    }
+
+    /// open rooms and who is in them.
+    pub async fn list_rooms(&self) -> Option<Vec<RoomInfo>> {
+        self.admin(|respond_to| AdminMessage::ListRooms { respond_to }).await
+    }
+
+    /// customers in the waiting queue, next first.
+    pub async fn list_queue(&self) -> Option<Vec<WaitingInfo>> {
+        self.admin(|respond_to| AdminMessage::ListQueue { respond_to }).await
+    }
+
+    /// open a room between a waiting customer and an online agent.
+    pub async fn assign(&self, customer_id: String, agent_id: String) -> Option<Result<RoomId, AssignError>> {
+        self.admin(|respond_to| AdminMessage::Assign {
+            customer_id,
+            agent_id,
+            respond_to,
+        })
+        .await
+    }
+
+    /// join supervisor to an open room.
+    pub async fn supervise(&self, room_id: RoomId, supervisor: Member) -> Option<Result<(), SuperviseError>> {
+        self.admin(|respond_to| AdminMessage::Supervise {
+            room_id,
+            supervisor,
+            respond_to,
+        })
+        .await
+    }
+
+    /// returns whether the room was open.
+    pub async fn close_room(&self, room_id: RoomId) -> Option<bool> {
+        self.admin(|respond_to| AdminMessage::CloseRoom { room_id, respond_to }).await
+    }
+
+    /// disconnect a conn. returns whether it was online.
+    pub async fn kick(&self, conn_id: String) -> Option<bool> {
+        self.admin(|respond_to| AdminMessage::Kick { conn_id, respond_to }).await
+    }
+
+    /// close every conn, with hint as the close reason, and refuse new ones.
+    /// returns once they are all gone, or None if the manager stopped.
+    pub async fn drain(&self, hint: Option<String>) -> Option<()> {
+        let (tx, rx) = oneshot::channel();
+
+        self.send_message(SessionMessage::Drain { hint, respond_to: tx }).await;
+
+        rx.await.ok()
+    }
+
     /// Conn calls this method to send a message to Dispatch
     pub async fn send_conn_message(&self, message: ConnMessage) {
-        let _ = self.sender_conn.send(message).await;
+        let mailbox = metrics().mailbox(metrics::MANAGER);
+        mailbox.inc();
+        if self.sender_conn.send(message).await.is_err() {
+            mailbox.dec();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, sync::Arc, time::Duration};
+
+    use tokio::time::timeout;
+    use tracing::Span;
+
+    use crate::{auth::UserType, journal::Journal, message::protocol::ClientProtocol, store::SqliteStore};
+
+    use super::*;
+
+    /// a manager with nobody online, and the dir of its store and journal.
+    fn dispatch() -> (DispatchHandle, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("im-dispatch-{}", uuid::Uuid::new_v4()));
+        let store = StoreHandle::new(Arc::new(SqliteStore::open(dir.join("im.db")).unwrap()));
+        let journal = Journal::open(dir.join("journal")).unwrap().start(true).unwrap();
+
+        (DispatchHandle::new(&Config::default(), store, journal, Recovered::default(), None), dir)
+    }
+
+    fn member(user_type: UserType, id: &str) -> Member {
+        Member::new(user_type, id.to_string(), id.to_string())
+    }
+
+    async fn accept(dispatch: &DispatchHandle, member: &Member) -> mpsc::Receiver<RoomMessage> {
+        let (conn, rx) = ConnHandle::detached(member.clone(), member.id());
+        dispatch.send_message(SessionMessage::OnAccept { conn }).await;
+        rx
+    }
+
+    /// whether a chat message with body reaches rx.
+    async fn receives(rx: &mut mpsc::Receiver<RoomMessage>, body: &str) -> bool {
+        while let Ok(Some(message)) = timeout(Duration::from_millis(200), rx.recv()).await {
+            if matches!(message, RoomMessage::OnNewMessage { content, .. } if content.body() == body) {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    #[tokio::test]
+    async fn supervisors_speak_in_the_rooms_they_supervise() {
+        let (dispatch, dir) = dispatch();
+
+        let (agent, customer, supervisor) = (
+            member(UserType::CustomerService, "a1"),
+            member(UserType::Customer, "c1"),
+            member(UserType::Supervisor, "s1"),
+        );
+        let _agent_rx = accept(&dispatch, &agent).await;
+        let mut customer_rx = accept(&dispatch, &customer).await;
+        let _supervisor_rx = accept(&dispatch, &supervisor).await;
+
+        let say = |body: &str| {
+            let message: ClientProtocol = serde_json::from_value(serde_json::json!({"msg_type": "Chat", "body": body, "room_id": "c1-a1"})).unwrap();
+            ConnMessage::OnNewMessage {
+                member: supervisor.clone(),
+                conn_id: supervisor.id().to_string(),
+                message,
+                span: Span::none(),
+            }
+        };
+
+        dispatch.send_conn_message(say("before supervising")).await;
+        assert!(!receives(&mut customer_rx, "before supervising").await);
+
+        assert_eq!(
+            dispatch.supervise("c2-a1".to_string(), supervisor.clone()).await,
+            Some(Err(SuperviseError::RoomNotFound))
+        );
+        let offline = member(UserType::Supervisor, "s2");
+        assert_eq!(
+            dispatch.supervise("c1-a1".to_string(), offline).await,
+            Some(Err(SuperviseError::SupervisorNotOnline))
+        );
+        assert_eq!(dispatch.supervise("c1-a1".to_string(), supervisor.clone()).await, Some(Ok(())));
+
+        dispatch.send_conn_message(say("after supervising")).await;
+        assert!(receives(&mut customer_rx, "after supervising").await);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn held_customers_are_turned_away() {
+        let (dispatch, dir) = dispatch();
+        let customer = member(UserType::Customer, "c1");
+
+        assert!(dispatch.hold_customer("c1".to_string()).await);
+        let mut rx = accept(&dispatch, &customer).await;
+        let turned_away = timeout(Duration::from_secs(1), rx.recv()).await.unwrap();
+        assert!(matches!(turned_away, Some(RoomMessage::Disconnect { code: CloseCode::Again, .. })));
+
+        dispatch.release_customer("c1".to_string()).await;
+        let _rx = accept(&dispatch, &customer).await;
+        // online, and waiting for an agent.
+        assert!(!dispatch.hold_customer("c1".to_string()).await);
+        assert_eq!(dispatch.list_queue().await.unwrap().len(), 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn recovered_turns_expire_after_the_customer_grace() {
+        let dir = std::env::temp_dir().join(format!("im-dispatch-{}", uuid::Uuid::new_v4()));
+        let store = StoreHandle::new(Arc::new(SqliteStore::open(dir.join("im.db")).unwrap()));
+        let journal = Journal::open(dir.join("journal")).unwrap().start(true).unwrap();
+        let (gone, back) = (member(UserType::Customer, "c1"), member(UserType::Customer, "c2"));
+        for member in [&gone, &back] {
+            journal.append(JournalEntry::Enqueued { member: member.clone() }).await;
+        }
+
+        let (_, mailbox_session) = mpsc::channel(1);
+        let (_, mailbox_conn) = mpsc::channel(1);
+        let mut config = Config::default();
+        config.dispatch.customer_grace_secs = 60;
+        let mut manager = Manager::new(mailbox_session, mailbox_conn, store, journal.clone(), None, &config);
+        manager.restore(Recovered {
+            rooms: Vec::new(),
+            waiting: vec![gone.clone(), back.clone()],
+        });
+        let (conn, _rx) = ConnHandle::detached(back.clone(), "c2");
+        manager.add_session(conn).await;
+
+        manager.expire_turns().await;
+        assert_eq!(manager.recovered_turns.len(), 2);
+
+        manager.recovered_at -= manager.config.customer_grace();
+        manager.expire_turns().await;
+        assert!(manager.recovered_turns.is_empty());
+        assert_eq!(manager.waiting_queue.len(), 1);
+
+        journal.flush().await;
+        assert_eq!(Journal::open(dir.join("journal")).unwrap().recover().waiting, [back]);
+
+        std::fs::remove_dir_all(dir).unwrap();
     }
 }
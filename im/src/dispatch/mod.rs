@@ -1,5 +1,7 @@
-mod dispatch;
+pub mod admin;
 mod collection;
+#[allow(clippy::module_inception)]
+mod dispatch;
 
 pub use dispatch::DispatchHandle;
 pub use dispatch::Manager;
@@ -0,0 +1,29 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// process state reported by the readiness probe.
+#[derive(Debug, Default)]
+pub struct Health {
+    /// the websocket listener is bound.
+    listening: AtomicBool,
+
+    /// shutting down, no new conns should be sent here.
+    draining: AtomicBool,
+}
+
+impl Health {
+    pub fn set_listening(&self) {
+        self.listening.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_listening(&self) -> bool {
+        self.listening.load(Ordering::SeqCst)
+    }
+
+    pub fn start_draining(&self) {
+        self.draining.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+}
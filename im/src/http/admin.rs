@@ -0,0 +1,132 @@
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{
+    auth::RoomId,
+    dispatch::admin::{AgentInfo, AssignError, RoomInfo, SuperviseError, WaitingInfo},
+};
+
+use super::{authenticate_supervisor, ApiError, AppState};
+
+#[derive(Deserialize)]
+pub struct AssignRequest {
+    agent_id: String,
+}
+
+#[derive(Serialize)]
+pub struct AssignResponse {
+    room_id: RoomId,
+}
+
+fn unavailable() -> ApiError {
+    ApiError::internal("dispatch unavailable")
+}
+
+/// GET /admin/agents
+/// online customer services and their open rooms. supervisors only.
+pub async fn list_agents(State(state): State<AppState>, headers: HeaderMap) -> Result<Json<Vec<AgentInfo>>, ApiError> {
+    authenticate_supervisor(&state, &headers)?;
+
+    let agents = state.dispatch.list_agents().await.ok_or_else(unavailable)?;
+
+    Ok(Json(agents))
+}
+
+/// GET /admin/rooms
+/// open rooms with their online members and participants. supervisors only.
+pub async fn list_rooms(State(state): State<AppState>, headers: HeaderMap) -> Result<Json<Vec<RoomInfo>>, ApiError> {
+    authenticate_supervisor(&state, &headers)?;
+
+    let rooms = state.dispatch.list_rooms().await.ok_or_else(unavailable)?;
+
+    Ok(Json(rooms))
+}
+
+/// GET /admin/queue
+/// customers waiting for a customer service, next first. supervisors only.
+pub async fn list_queue(State(state): State<AppState>, headers: HeaderMap) -> Result<Json<Vec<WaitingInfo>>, ApiError> {
+    authenticate_supervisor(&state, &headers)?;
+
+    let waiting = state.dispatch.list_queue().await.ok_or_else(unavailable)?;
+
+    Ok(Json(waiting))
+}
+
+/// POST /admin/queue/{customer_id}/assign
+/// json body with the `agent_id` to open a room with. supervisors only.
+pub async fn assign(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(customer_id): Path<String>,
+    Json(request): Json<AssignRequest>,
+) -> Result<Json<AssignResponse>, ApiError> {
+    let supervisor = authenticate_supervisor(&state, &headers)?;
+
+    let assigned = state
+        .dispatch
+        .assign(customer_id.clone(), request.agent_id.clone())
+        .await
+        .ok_or_else(unavailable)?;
+    let room_id = assigned.map_err(|err: AssignError| ApiError::new(StatusCode::NOT_FOUND, err.to_string()))?;
+
+    info!(
+        target: "audit",
+        customer_id,
+        agent_id = request.agent_id,
+        room_id,
+        supervisor_id = supervisor.id(),
+        "customer assigned"
+    );
+
+    Ok(Json(AssignResponse { room_id }))
+}
+
+/// POST /admin/rooms/{room_id}/supervise
+/// join the online conns of the calling supervisor to the room, so it can speak there. supervisors only.
+pub async fn supervise(State(state): State<AppState>, headers: HeaderMap, Path(room_id): Path<RoomId>) -> Result<StatusCode, ApiError> {
+    let supervisor = authenticate_supervisor(&state, &headers)?;
+
+    let joined = state
+        .dispatch
+        .supervise(room_id.clone(), supervisor.clone())
+        .await
+        .ok_or_else(unavailable)?;
+    joined.map_err(|err: SuperviseError| ApiError::new(StatusCode::NOT_FOUND, err.to_string()))?;
+
+    info!(target: "audit", room_id, supervisor_id = supervisor.id(), "room supervised");
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// DELETE /admin/rooms/{room_id}
+/// end a conversation. supervisors only.
+pub async fn close_room(State(state): State<AppState>, headers: HeaderMap, Path(room_id): Path<RoomId>) -> Result<StatusCode, ApiError> {
+    let supervisor = authenticate_supervisor(&state, &headers)?;
+
+    if !state.dispatch.close_room(room_id.clone()).await.ok_or_else(unavailable)? {
+        return Err(ApiError::not_found());
+    }
+
+    info!(target: "audit", room_id, supervisor_id = supervisor.id(), "room closed");
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// DELETE /admin/conns/{conn_id}
+/// disconnect a conn. its member may connect again. supervisors only.
+pub async fn kick(State(state): State<AppState>, headers: HeaderMap, Path(conn_id): Path<String>) -> Result<StatusCode, ApiError> {
+    let supervisor = authenticate_supervisor(&state, &headers)?;
+
+    if !state.dispatch.kick(conn_id.clone()).await.ok_or_else(unavailable)? {
+        return Err(ApiError::not_found());
+    }
+
+    info!(target: "audit", conn_id, supervisor_id = supervisor.id(), "conn kicked");
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,219 @@
+use axum::{
+    extract::{Multipart, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    attachment::{self, Attachment},
+    auth::{Member, RoomId},
+    clock,
+    config::AttachmentConfig,
+};
+
+use super::{authenticate, ApiError, AppState};
+
+/// room for multipart boundaries and the other form fields, on top of the file.
+const MULTIPART_OVERHEAD: usize = 64 * 1024;
+
+#[derive(Serialize)]
+pub struct UploadResponse {
+    id: String,
+    name: String,
+    mime: String,
+    size: u64,
+    url: String,
+    expires_at: u64,
+}
+
+#[derive(Deserialize)]
+pub struct SignedQuery {
+    expires: u64,
+    sig: String,
+}
+
+#[derive(Serialize)]
+pub struct UrlResponse {
+    url: String,
+    expires_at: u64,
+}
+
+/// supervisors may upload to every room, everyone else must be a member of the open room.
+async fn check_upload(state: &AppState, member: &Member, room_id: &RoomId) -> Result<(), ApiError> {
+    if member.is_supervisor() || state.dispatch.is_room_member(room_id.clone(), member.clone()).await {
+        return Ok(());
+    }
+
+    Err(ApiError::forbidden())
+}
+
+/// supervisors may read every room, everyone else must have taken part in it,
+/// so members who come back later and readers of closed rooms are let in.
+async fn check_read(state: &AppState, member: &Member, room_id: &RoomId) -> Result<(), ApiError> {
+    if member.is_supervisor() || state.store.is_participant(room_id.clone(), member.clone()).await {
+        return Ok(());
+    }
+
+    Err(ApiError::forbidden())
+}
+
+async fn load_metadata(state: &AppState, id: String) -> Result<Attachment, ApiError> {
+    let storage = state.storage.clone();
+
+    tokio::task::spawn_blocking(move || storage.metadata(&id))
+        .await
+        .map_err(ApiError::internal)?
+        .map_err(ApiError::internal)?
+        .ok_or_else(ApiError::not_found)
+}
+
+fn signed_url(state: &AppState, id: &str) -> (String, u64) {
+    let expires_at = clock::now_secs() + state.attachment.download_url_ttl_secs;
+    (state.signer.url(id, expires_at), expires_at)
+}
+
+/// largest upload request, the file included.
+pub fn body_limit(config: &AttachmentConfig) -> usize {
+    config.max_upload_size + MULTIPART_OVERHEAD
+}
+
+fn too_large() -> ApiError {
+    ApiError::new(StatusCode::PAYLOAD_TOO_LARGE, "file size not allowed")
+}
+
+/// POST /attachments
+/// multipart form with a `room_id` field and a `file` field.
+pub async fn upload(State(state): State<AppState>, headers: HeaderMap, mut multipart: Multipart) -> Result<impl IntoResponse, ApiError> {
+    let member = authenticate(&state, &headers)?;
+
+    // a declared length over the limit is refused before anything is read. without one the file is counted as it streams in.
+    let declared = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+    if declared.is_some_and(|declared| declared > body_limit(&state.attachment) as u64) {
+        return Err(too_large());
+    }
+
+    let mut room_id = None;
+    let mut file = None;
+
+    while let Some(mut field) = multipart
+        .next_field()
+        .await
+        .map_err(|err| ApiError::new(StatusCode::BAD_REQUEST, err.body_text()))?
+    {
+        match field.name() {
+            Some("room_id") => {
+                let value = field
+                    .text()
+                    .await
+                    .map_err(|err| ApiError::new(StatusCode::BAD_REQUEST, err.body_text()))?;
+                room_id = Some(value);
+            }
+            Some("file") => {
+                let name = field.file_name().unwrap_or("file").to_string();
+                let mime = field.content_type().unwrap_or("application/octet-stream").to_string();
+                let mut data = Vec::new();
+                while let Some(chunk) = field.chunk().await.map_err(|err| ApiError::new(err.status(), err.body_text()))? {
+                    if data.len() + chunk.len() > state.attachment.max_upload_size {
+                        return Err(too_large());
+                    }
+                    data.extend_from_slice(&chunk);
+                }
+                file = Some((name, mime, data));
+            }
+            _ => {}
+        }
+    }
+
+    let room_id = room_id.ok_or_else(|| ApiError::new(StatusCode::BAD_REQUEST, "missing room_id"))?;
+    let (name, mime, data) = file.ok_or_else(|| ApiError::new(StatusCode::BAD_REQUEST, "missing file"))?;
+
+    if data.is_empty() {
+        return Err(too_large());
+    }
+
+    if !attachment::check_content(&state.attachment.allowed_mime_types, &mime, &data) {
+        return Err(ApiError::new(StatusCode::UNSUPPORTED_MEDIA_TYPE, "file type not allowed"));
+    }
+
+    check_upload(&state, &member, &room_id).await?;
+
+    let attachment = Attachment {
+        id: uuid::Uuid::new_v4().to_string(),
+        room_id,
+        uploader: member.id().to_string(),
+        name,
+        mime,
+        size: data.len() as u64,
+        created_at: clock::now_secs(),
+    };
+
+    let storage = state.storage.clone();
+    let stored = attachment.clone();
+    tokio::task::spawn_blocking(move || storage.put(&stored, &data))
+        .await
+        .map_err(ApiError::internal)?
+        .map_err(ApiError::internal)?;
+
+    let (url, expires_at) = signed_url(&state, &attachment.id);
+
+    let response = UploadResponse {
+        id: attachment.id,
+        name: attachment.name,
+        mime: attachment.mime,
+        size: attachment.size,
+        url,
+        expires_at,
+    };
+
+    Ok((StatusCode::CREATED, Json(response)))
+}
+
+/// GET /attachments/{id}/url
+/// issue a fresh signed download url to a participant of the room.
+pub async fn download_url(State(state): State<AppState>, headers: HeaderMap, Path(id): Path<String>) -> Result<Json<UrlResponse>, ApiError> {
+    let member = authenticate(&state, &headers)?;
+    let attachment = load_metadata(&state, id).await?;
+
+    check_read(&state, &member, &attachment.room_id).await?;
+
+    let (url, expires_at) = signed_url(&state, &attachment.id);
+
+    Ok(Json(UrlResponse { url, expires_at }))
+}
+
+/// GET /attachments/{id}?expires=..&sig=..
+/// the signature is the credential, access was checked when the url was issued. so it works in `<img src>`.
+pub async fn download(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+    Query(query): Query<SignedQuery>,
+) -> Result<impl IntoResponse, ApiError> {
+    if !state.signer.verify(&id, query.expires, &query.sig, clock::now_secs()) {
+        return Err(ApiError::forbidden());
+    }
+
+    let attachment = load_metadata(&state, id).await?;
+
+    let storage = state.storage.clone();
+    let id = attachment.id.clone();
+    let data = tokio::task::spawn_blocking(move || storage.read(&id))
+        .await
+        .map_err(ApiError::internal)?
+        .map_err(ApiError::internal)?
+        .ok_or_else(ApiError::not_found)?;
+
+    let disposition = format!("attachment; filename=\"{}\"", attachment.name.replace(['"', '\\', '\r', '\n'], "_"));
+    // browsers must not sniff an uploaded text file into html and run it on our origin.
+    let headers = [
+        (header::CONTENT_TYPE, attachment.mime),
+        (header::CONTENT_DISPOSITION, disposition),
+        (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+    ];
+
+    Ok((headers, data))
+}
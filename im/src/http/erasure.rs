@@ -0,0 +1,93 @@
+use std::collections::HashSet;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use serde::Deserialize;
+use tracing::info;
+
+use crate::retention::{Erased, ErasureMode};
+
+use super::{authenticate_supervisor, ApiError, AppState};
+
+#[derive(Deserialize)]
+pub struct EraseQuery {
+    #[serde(default)]
+    mode: ErasureMode,
+}
+
+/// erase a customer from history, journal and attachments. supervisors only.
+/// customers online, waiting or in an open conversation can't be erased until it ends,
+/// and can't connect while they are erased.
+pub async fn erase_customer(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(member_id): Path<String>,
+    Query(query): Query<EraseQuery>,
+) -> Result<Json<Erased>, ApiError> {
+    let supervisor = authenticate_supervisor(&state, &headers)?;
+
+    if !state.dispatch.hold_customer(member_id.clone()).await {
+        return Err(ApiError::new(StatusCode::CONFLICT, "customer is online or has an open conversation"));
+    }
+
+    let erased = erase(&state, &member_id, query.mode).await;
+    state.dispatch.release_customer(member_id.clone()).await;
+    let erased = erased?;
+
+    info!(
+        target: "audit",
+        member_id,
+        supervisor_id = supervisor.id(),
+        mode = ?query.mode,
+        rooms = erased.rooms.len(),
+        messages = erased.messages,
+        attachments = erased.attachments,
+        "customer erased"
+    );
+
+    Ok(Json(erased))
+}
+
+async fn erase(state: &AppState, member_id: &str, mode: ErasureMode) -> Result<Erased, ApiError> {
+    let mut erased = state
+        .store
+        .erase_customer(member_id.to_string(), mode)
+        .await
+        .ok_or_else(|| ApiError::internal("erase unavailable"))?;
+
+    let room_ids: HashSet<_> = erased.rooms.iter().map(|room| room.room_id.clone()).collect();
+    state.journal.erase(member_id.to_string(), room_ids).await;
+
+    // purged rooms lose every attachment, redacted ones what the customer uploaded.
+    // uploads need membership, so the customer's uploads are all in its rooms.
+    let storage = state.storage.clone();
+    let rooms = erased.rooms.clone();
+    let customer_id = member_id.to_string();
+    erased.attachments = tokio::task::spawn_blocking(move || -> anyhow::Result<u64> {
+        let mut count = 0;
+
+        for room in rooms {
+            for attachment in storage.list_room(&room.room_id)? {
+                if mode == ErasureMode::Purge || attachment.uploader == customer_id {
+                    storage.delete(&attachment.id)?;
+                    count += 1;
+                }
+            }
+
+            // the rest follows the room to its new id, which the agents still participate in.
+            if let Some(renamed_to) = &room.renamed_to {
+                storage.move_room(&room.room_id, renamed_to)?;
+            }
+        }
+
+        Ok(count)
+    })
+    .await
+    .map_err(ApiError::internal)?
+    .map_err(ApiError::internal)?;
+
+    Ok(erased)
+}
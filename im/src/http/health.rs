@@ -0,0 +1,53 @@
+use std::time::Duration;
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use tokio::time::timeout;
+
+use super::AppState;
+
+/// how long the dispatch actor and the store get to answer a probe.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Serialize)]
+pub struct Readiness {
+    ready: bool,
+    listening: bool,
+    storage: bool,
+    draining: bool,
+}
+
+/// GET /healthz
+/// live while the dispatch actor answers a ping.
+pub async fn healthz(State(state): State<AppState>) -> Response {
+    match timeout(PROBE_TIMEOUT, state.dispatch.ping()).await {
+        Ok(true) => (StatusCode::OK, "ok").into_response(),
+        _ => (StatusCode::SERVICE_UNAVAILABLE, "dispatch not responding").into_response(),
+    }
+}
+
+/// GET /readyz
+/// ready while the listener is bound, the store answers and the server isn't draining.
+pub async fn readyz(State(state): State<AppState>) -> (StatusCode, Json<Readiness>) {
+    let listening = state.health.is_listening();
+    let storage = timeout(PROBE_TIMEOUT, state.store.ping()).await.unwrap_or(false);
+    let draining = state.health.is_draining();
+
+    let ready = listening && storage && !draining;
+    let status = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+
+    (
+        status,
+        Json(Readiness {
+            ready,
+            listening,
+            storage,
+            draining,
+        }),
+    )
+}
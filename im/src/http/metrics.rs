@@ -0,0 +1,13 @@
+use axum::{http::header, response::IntoResponse};
+
+use crate::metrics;
+
+use super::ApiError;
+
+/// GET /metrics
+/// prometheus scrape endpoint.
+pub async fn export() -> Result<impl IntoResponse, ApiError> {
+    let body = metrics::metrics().render().map_err(ApiError::internal)?;
+
+    Ok(([(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)], body))
+}
@@ -0,0 +1,164 @@
+mod admin;
+mod attachment;
+mod erasure;
+mod health;
+mod metrics;
+mod search;
+mod transcript;
+
+use std::{net::SocketAddr, sync::Arc};
+
+use axum::{
+    extract::DefaultBodyLimit,
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+    Router,
+};
+use tokio::net::TcpListener;
+use tracing::{error, info};
+
+use crate::{
+    attachment::{AttachmentStorage, UrlSigner},
+    auth::{
+        token::{self, TokenVerifier},
+        Member,
+    },
+    config::AttachmentConfig,
+    dispatch::DispatchHandle,
+    health::Health,
+    i18n::I18n,
+    journal::JournalHandle,
+    store::StoreHandle,
+    transcript::InvalidTime,
+};
+
+/// shared state of http handlers.
+#[derive(Clone)]
+pub struct AppState {
+    pub dispatch: DispatchHandle,
+    pub storage: Arc<dyn AttachmentStorage>,
+    pub signer: Arc<UrlSigner>,
+    pub attachment: AttachmentConfig,
+    pub tokens: Arc<TokenVerifier>,
+    pub store: StoreHandle,
+    pub journal: JournalHandle,
+    pub i18n: Arc<I18n>,
+    pub health: Arc<Health>,
+}
+
+/// error returned by http handlers, rendered as status code and plain text.
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        ApiError {
+            status,
+            message: message.into(),
+        }
+    }
+
+    pub fn unauthorized() -> Self {
+        Self::new(StatusCode::UNAUTHORIZED, "invalid token")
+    }
+
+    pub fn forbidden() -> Self {
+        Self::new(StatusCode::FORBIDDEN, "forbidden")
+    }
+
+    pub fn not_found() -> Self {
+        Self::new(StatusCode::NOT_FOUND, "not found")
+    }
+
+    pub fn internal(err: impl std::fmt::Debug) -> Self {
+        error!(error = ?err, "http internal error");
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, "internal error")
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, self.message).into_response()
+    }
+}
+
+impl From<InvalidTime> for ApiError {
+    fn from(err: InvalidTime) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, err.to_string())
+    }
+}
+
+/// resolve the member from the bearer token of the request.
+fn authenticate(state: &AppState, headers: &HeaderMap) -> Result<Member, ApiError> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(token::bearer_token)
+        .and_then(|token| state.tokens.decode_member(token))
+        .ok_or_else(ApiError::unauthorized)
+}
+
+/// like authenticate, for endpoints only supervisors may use.
+fn authenticate_supervisor(state: &AppState, headers: &HeaderMap) -> Result<Member, ApiError> {
+    let member = authenticate(state, headers)?;
+    if !member.is_supervisor() {
+        return Err(ApiError::forbidden());
+    }
+
+    Ok(member)
+}
+
+/// the api clients use, on the public listener.
+fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/attachments", post(attachment::upload))
+        .route("/attachments/{id}", get(attachment::download))
+        .route("/attachments/{id}/url", get(attachment::download_url))
+        .layer(DefaultBodyLimit::max(attachment::body_limit(&state.attachment)))
+        .with_state(state)
+}
+
+/// serve the client api on addr.
+pub async fn serve(addr: SocketAddr, state: AppState) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+
+    info!(addr = %listener.local_addr()?, "http listening");
+
+    axum::serve(listener, router(state)).await?;
+
+    Ok(())
+}
+
+/// probes, metrics and the admin api, for operators only. kept off the public listener.
+fn admin_router(state: AppState) -> Router {
+    Router::new()
+        .route("/healthz", get(health::healthz))
+        .route("/readyz", get(health::readyz))
+        .route("/metrics", get(metrics::export))
+        .route("/admin/transcripts", get(transcript::export))
+        .route("/admin/search", get(search::search))
+        .route("/admin/customers/{id}", delete(erasure::erase_customer))
+        .route("/admin/agents", get(admin::list_agents))
+        .route("/admin/rooms", get(admin::list_rooms))
+        .route("/admin/rooms/{id}", delete(admin::close_room))
+        .route("/admin/rooms/{id}/supervise", post(admin::supervise))
+        .route("/admin/queue", get(admin::list_queue))
+        .route("/admin/queue/{id}/assign", post(admin::assign))
+        .route("/admin/conns/{id}", delete(admin::kick))
+        .with_state(state)
+}
+
+/// serve the admin endpoints on addr.
+pub async fn serve_admin(addr: SocketAddr, state: AppState) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+
+    info!(addr = %listener.local_addr()?, "admin listening");
+
+    axum::serve(listener, admin_router(state)).await?;
+
+    Ok(())
+}
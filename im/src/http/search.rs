@@ -0,0 +1,65 @@
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use serde::Deserialize;
+
+use crate::{
+    search::{self, RoomStatus, SearchQuery, SearchResult, DEFAULT_SEARCH_LIMIT, MAX_SEARCH_LIMIT},
+    transcript,
+};
+
+use super::{authenticate_supervisor, ApiError, AppState};
+
+#[derive(Deserialize)]
+pub struct SearchParams {
+    q: String,
+    agent: Option<String>,
+    customer: Option<String>,
+    /// unix millis, rfc 3339 or `YYYY-MM-DD`.
+    from: Option<String>,
+    to: Option<String>,
+    status: Option<RoomStatus>,
+    limit: Option<u32>,
+    #[serde(default)]
+    offset: u32,
+}
+
+impl SearchParams {
+    fn query(self) -> Result<SearchQuery, ApiError> {
+        let terms = search::parse_terms(&self.q);
+        if terms.is_empty() {
+            return Err(ApiError::new(StatusCode::BAD_REQUEST, "empty query"));
+        }
+
+        let limit = self.limit.unwrap_or(DEFAULT_SEARCH_LIMIT);
+        if limit == 0 || limit > MAX_SEARCH_LIMIT {
+            return Err(ApiError::new(
+                StatusCode::BAD_REQUEST,
+                format!("limit must be between 1 and {}", MAX_SEARCH_LIMIT),
+            ));
+        }
+
+        Ok(SearchQuery {
+            from: transcript::parse_bound(self.from.as_deref(), false)?,
+            to: transcript::parse_bound(self.to.as_deref(), true)?,
+            terms,
+            agent: self.agent,
+            customer: self.customer,
+            status: self.status,
+            limit,
+            offset: self.offset,
+        })
+    }
+}
+
+/// search stored messages. supervisors only.
+pub async fn search(State(state): State<AppState>, headers: HeaderMap, Query(params): Query<SearchParams>) -> Result<Json<SearchResult>, ApiError> {
+    authenticate_supervisor(&state, &headers)?;
+
+    let query = params.query()?;
+    let result = state.store.search(query).await.ok_or_else(|| ApiError::internal("search unavailable"))?;
+
+    Ok(Json(result))
+}
@@ -0,0 +1,65 @@
+use axum::{
+    extract::{Query, State},
+    http::{header, HeaderMap},
+    response::IntoResponse,
+};
+use serde::Deserialize;
+
+use crate::{
+    auth::RoomId,
+    i18n::DEFAULT_LOCALE,
+    transcript::{self, Format, TranscriptFilter},
+};
+
+use super::{authenticate_supervisor, ApiError, AppState};
+
+#[derive(Deserialize)]
+pub struct ExportQuery {
+    #[serde(default)]
+    format: Format,
+    room_id: Option<RoomId>,
+    agent: Option<String>,
+    customer: Option<String>,
+    /// unix millis, rfc 3339 or `YYYY-MM-DD`.
+    from: Option<String>,
+    to: Option<String>,
+    locale: Option<String>,
+}
+
+impl ExportQuery {
+    fn filter(&self) -> Result<TranscriptFilter, ApiError> {
+        Ok(TranscriptFilter {
+            room_id: self.room_id.clone(),
+            agent: self.agent.clone(),
+            customer: self.customer.clone(),
+            from: transcript::parse_bound(self.from.as_deref(), false)?,
+            to: transcript::parse_bound(self.to.as_deref(), true)?,
+        })
+    }
+}
+
+/// export transcripts of the rooms matching the query. supervisors only.
+pub async fn export(State(state): State<AppState>, headers: HeaderMap, Query(query): Query<ExportQuery>) -> Result<impl IntoResponse, ApiError> {
+    authenticate_supervisor(&state, &headers)?;
+
+    let filter = query.filter()?;
+    let transcripts = state
+        .store
+        .transcripts(filter)
+        .await
+        .ok_or_else(|| ApiError::internal("transcripts unavailable"))?;
+
+    let locale = query.locale.as_deref().unwrap_or(DEFAULT_LOCALE);
+    let locale = state.i18n.negotiate(&[locale.to_string()]);
+    let body = transcript::render(query.format, &transcripts, &state.i18n, &locale).map_err(ApiError::internal)?;
+
+    let disposition = format!("attachment; filename=\"transcripts.{}\"", query.format.extension());
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, query.format.content_type().to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    ))
+}
@@ -0,0 +1,176 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use anyhow::{Context, Result};
+
+/// catalogs compiled into the binary.
+const BUILTIN_CATALOGS: &[(&str, &str)] = &[
+    ("zh-CN", include_str!("../../locales/zh-CN.json")),
+    ("en", include_str!("../../locales/en.json")),
+];
+
+/// locale used when nothing the client asked for is available.
+pub const DEFAULT_LOCALE: &str = "zh-CN";
+
+/// message templates of one locale, keyed by template key.
+/// templates reference parameters as `{name}`.
+pub type Catalog = HashMap<String, String>;
+
+/// I18n resolves system notice templates per locale.
+pub struct I18n {
+    catalogs: HashMap<String, Catalog>,
+}
+
+impl Default for I18n {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
+
+impl I18n {
+    /// load the catalogs shipped with the server.
+    pub fn builtin() -> Self {
+        let catalogs = BUILTIN_CATALOGS
+            .iter()
+            .map(|(locale, raw)| {
+                let catalog: Catalog = serde_json::from_str(raw).expect("builtin catalog is valid json");
+                (locale.to_string(), catalog)
+            })
+            .collect();
+
+        I18n { catalogs }
+    }
+
+    /// load `<locale>.json` files from dir. keys override the builtin templates,
+    /// unknown locales are added.
+    pub fn load_dir(&mut self, dir: impl AsRef<Path>) -> Result<()> {
+        for entry in fs::read_dir(dir.as_ref())? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+
+            let locale = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(locale) => locale.to_string(),
+                None => continue,
+            };
+
+            let raw = fs::read_to_string(&path).with_context(|| format!("read catalog {}", path.display()))?;
+            let catalog: Catalog = serde_json::from_str(&raw).with_context(|| format!("parse catalog {}", path.display()))?;
+
+            self.catalogs.entry(locale).or_default().extend(catalog);
+        }
+
+        Ok(())
+    }
+
+    /// pick the first available locale from preferences, ordered by priority.
+    /// `en-US` falls back to `en`, and a bare `zh` matches `zh-CN`.
+    pub fn negotiate(&self, preferences: &[String]) -> String {
+        for preference in preferences {
+            if let Some(locale) = self.catalogs.keys().find(|locale| locale.eq_ignore_ascii_case(preference)) {
+                return locale.clone();
+            }
+
+            let language = preference.split('-').next().unwrap_or_default();
+            if let Some(locale) = self
+                .catalogs
+                .keys()
+                .find(|locale| locale.eq_ignore_ascii_case(language) || locale.split('-').next().is_some_and(|l| l.eq_ignore_ascii_case(language)))
+            {
+                return locale.clone();
+            }
+        }
+
+        DEFAULT_LOCALE.to_string()
+    }
+
+    /// render template key in locale. missing keys fall back to the default locale, then to the key itself.
+    pub fn text(&self, locale: &str, key: &str, params: &[(&str, &str)]) -> String {
+        let template = self
+            .catalogs
+            .get(locale)
+            .and_then(|catalog| catalog.get(key))
+            .or_else(|| self.catalogs.get(DEFAULT_LOCALE).and_then(|catalog| catalog.get(key)));
+
+        match template {
+            Some(template) => render(template, params).trim_end().to_string(),
+            None => key.to_string(),
+        }
+    }
+}
+
+/// fill the `{name}` placeholders of template in a single pass, so values are never expanded themselves.
+/// placeholders without a param are kept as they are.
+fn render(template: &str, params: &[(&str, &str)]) -> String {
+    let mut text = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(open) = rest.find('{') {
+        text.push_str(&rest[..open]);
+        rest = &rest[open..];
+
+        let value = rest.find('}').and_then(|close| {
+            let name = &rest[1..close];
+            params.iter().find(|(param, _)| *param == name).map(|(_, value)| (close, value))
+        });
+        match value {
+            Some((close, value)) => {
+                text.push_str(value);
+                rest = &rest[close + 1..];
+            }
+            None => {
+                text.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    text.push_str(rest);
+
+    text
+}
+
+/// parse an `Accept-Language` header into languages ordered by quality.
+pub fn parse_accept_language(header: &str) -> Vec<String> {
+    let mut languages: Vec<(String, f32)> = header
+        .split(',')
+        .filter_map(|part| {
+            let mut pieces = part.trim().split(';');
+            let language = pieces.next()?.trim();
+            if language.is_empty() || language == "*" {
+                return None;
+            }
+
+            let quality = pieces
+                .find_map(|piece| piece.trim().strip_prefix("q="))
+                .and_then(|q| q.parse().ok())
+                .unwrap_or(1.0);
+
+            Some((language.to_string(), quality))
+        })
+        .collect();
+
+    // stable, so equal qualities keep the client's order.
+    languages.sort_by(|a, b| b.1.total_cmp(&a.1));
+    languages.into_iter().map(|(language, _)| language).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_fills_placeholders() {
+        assert_eq!(
+            render("{name} joined, {reason}", &[("name", "Alice"), ("reason", "busy")]),
+            "Alice joined, busy"
+        );
+        assert_eq!(render("{missing} {", &[("name", "Alice")]), "{missing} {");
+    }
+
+    #[test]
+    fn render_does_not_expand_values() {
+        let text = render("{name}: {body}", &[("name", "{body}"), ("body", "{name}")]);
+
+        assert_eq!(text, "{body}: {name}");
+    }
+}
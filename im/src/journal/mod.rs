@@ -0,0 +1,661 @@
+mod segment;
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use std::time::Duration;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{error, info, info_span, warn, Instrument};
+
+use crate::{
+    auth::{Member, RoomId},
+    clock,
+    message::protocol::ClientProtocol,
+    store::{RoomEvent, StoreHandle},
+};
+
+use segment::SegmentWriter;
+
+/// segments roll over after this many bytes.
+const SEGMENT_SIZE: u64 = 8 * 1024 * 1024;
+
+/// how often the journal is checked for a checkpoint.
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// recent messages kept per recovered room, so they can still be recalled or edited.
+const RECENT_LIMIT: usize = 50;
+
+/// a record of the journal.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum JournalEntry {
+    Room(RoomEvent),
+    /// customer put into the waiting queue.
+    Enqueued {
+        member: Member,
+    },
+    /// customer left the waiting queue, dispatched or gone.
+    Dequeued {
+        member: Member,
+    },
+    /// last sequence of a room, written when the journal is compacted.
+    Sequence {
+        room_id: RoomId,
+        seq: u64,
+    },
+    /// the store had applied every room event before this. they are replayed into the state only.
+    Checkpoint,
+}
+
+impl JournalEntry {
+    /// whether the entry is about customer member_id or one of rooms.
+    fn mentions(&self, member_id: &str, rooms: &HashSet<RoomId>) -> bool {
+        let is_customer = |member: &Member| member.is_customer() && member.id() == member_id;
+
+        match self {
+            JournalEntry::Room(event) => match event {
+                RoomEvent::Created { room_id, .. }
+                | RoomEvent::Recalled { room_id, .. }
+                | RoomEvent::Edited { room_id, .. }
+                | RoomEvent::Closed { room_id, .. } => rooms.contains(room_id),
+                RoomEvent::Joined { room_id, member, .. } | RoomEvent::Left { room_id, member, .. } | RoomEvent::Message { room_id, member, .. } => {
+                    rooms.contains(room_id) || is_customer(member)
+                }
+            },
+            JournalEntry::Enqueued { member } | JournalEntry::Dequeued { member } => is_customer(member),
+            JournalEntry::Sequence { room_id, .. } => rooms.contains(room_id),
+            JournalEntry::Checkpoint => false,
+        }
+    }
+}
+
+/// an open room rebuilt from the journal.
+#[derive(Debug, Clone)]
+pub struct RecoveredRoom {
+    pub room_id: RoomId,
+    /// everyone who joined the room, online or not.
+    pub members: Vec<Member>,
+    pub last_seq: u64,
+    /// latest messages with their senders, oldest first.
+    pub recent: Vec<(Member, ClientProtocol)>,
+}
+
+/// state of the manager before the process stopped.
+#[derive(Debug, Default)]
+pub struct Recovered {
+    pub rooms: Vec<RecoveredRoom>,
+    /// customers that were waiting, in queue order.
+    pub waiting: Vec<Member>,
+}
+
+/// the rooms and queue described by the entries applied so far.
+#[derive(Debug, Default, Clone)]
+struct State {
+    /// open rooms in the order they were created.
+    order: Vec<RoomId>,
+    rooms: HashMap<RoomId, RecoveredRoom>,
+    waiting: Vec<Member>,
+}
+
+impl State {
+    fn replay(entries: &[JournalEntry]) -> Self {
+        let mut state = State::default();
+        for entry in entries {
+            state.apply(entry);
+        }
+
+        state
+    }
+
+    fn apply(&mut self, entry: &JournalEntry) {
+        let State { order, rooms, waiting } = self;
+
+        match entry {
+            JournalEntry::Room(RoomEvent::Created { room_id, .. }) => {
+                if !rooms.contains_key(room_id) {
+                    order.push(room_id.clone());
+                }
+
+                rooms.entry(room_id.clone()).or_insert_with(|| RecoveredRoom {
+                    room_id: room_id.clone(),
+                    members: Vec::new(),
+                    last_seq: 0,
+                    recent: Vec::new(),
+                });
+            }
+            JournalEntry::Room(RoomEvent::Joined { room_id, member, .. }) => {
+                if let Some(room) = rooms.get_mut(room_id) {
+                    if !room.members.contains(member) {
+                        room.members.push(member.clone());
+                    }
+                }
+            }
+            JournalEntry::Room(RoomEvent::Left { .. }) => {}
+            JournalEntry::Room(RoomEvent::Message { room_id, member, content }) => {
+                if let Some(room) = rooms.get_mut(room_id) {
+                    room.last_seq = room.last_seq.max(content.seq().unwrap_or_default());
+                    room.recent.push((member.clone(), content.clone()));
+                    if room.recent.len() > RECENT_LIMIT {
+                        room.recent.remove(0);
+                    }
+                }
+            }
+            JournalEntry::Room(RoomEvent::Recalled { room_id, seq, .. }) => {
+                if let Some(room) = rooms.get_mut(room_id) {
+                    room.recent.retain(|(_, content)| content.seq() != Some(*seq));
+                }
+            }
+            JournalEntry::Room(RoomEvent::Edited { room_id, seq, body, .. }) => {
+                if let Some(room) = rooms.get_mut(room_id) {
+                    for (_, content) in room.recent.iter_mut().filter(|(_, content)| content.seq() == Some(*seq)) {
+                        content.set_body(body.clone());
+                    }
+                }
+            }
+            JournalEntry::Room(RoomEvent::Closed { room_id, .. }) => {
+                rooms.remove(room_id);
+                order.retain(|id| id != room_id);
+            }
+            JournalEntry::Sequence { room_id, seq } => {
+                if let Some(room) = rooms.get_mut(room_id) {
+                    room.last_seq = room.last_seq.max(*seq);
+                }
+            }
+            JournalEntry::Enqueued { member } => {
+                if !waiting.contains(member) {
+                    waiting.push(member.clone());
+                }
+            }
+            JournalEntry::Dequeued { member } => {
+                waiting.retain(|waiting_member| waiting_member != member);
+            }
+            JournalEntry::Checkpoint => {}
+        }
+    }
+
+    fn recovered(&self) -> Recovered {
+        Recovered {
+            rooms: self.order.iter().filter_map(|room_id| self.rooms.get(room_id)).cloned().collect(),
+            waiting: self.waiting.clone(),
+        }
+    }
+
+    /// the smallest set of entries that replays to this state, ending with a checkpoint.
+    /// call once the store has applied every room event so far.
+    fn snapshot(&self) -> Vec<JournalEntry> {
+        let at = clock::now_millis();
+        let mut entries = Vec::new();
+
+        for room in self.order.iter().filter_map(|room_id| self.rooms.get(room_id)) {
+            entries.push(JournalEntry::Room(RoomEvent::Created {
+                room_id: room.room_id.clone(),
+                at,
+            }));
+
+            for member in room.members.iter() {
+                entries.push(JournalEntry::Room(RoomEvent::Joined {
+                    room_id: room.room_id.clone(),
+                    member: member.clone(),
+                    at,
+                }));
+            }
+
+            entries.push(JournalEntry::Sequence {
+                room_id: room.room_id.clone(),
+                seq: room.last_seq,
+            });
+
+            for (member, content) in room.recent.iter() {
+                entries.push(JournalEntry::Room(RoomEvent::Message {
+                    room_id: room.room_id.clone(),
+                    member: member.clone(),
+                    content: content.clone(),
+                }));
+            }
+        }
+
+        for member in self.waiting.iter() {
+            entries.push(JournalEntry::Enqueued { member: member.clone() });
+        }
+
+        entries.push(JournalEntry::Checkpoint);
+
+        entries
+    }
+}
+
+/// Journal is an append-only log of room and queue events, split into segment files.
+/// it is read once at startup to rebuild the manager, then compacted and appended to.
+/// while running, segments the store has applied are replaced by a snapshot at checkpoints.
+pub struct Journal {
+    dir: PathBuf,
+    segments: Vec<u64>,
+    entries: Vec<JournalEntry>,
+}
+
+impl Journal {
+    /// read every segment in dir.
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let segments = segment::list_segments(&dir)?;
+        let mut entries = Vec::new();
+        for index in segments.iter() {
+            entries.extend(segment::read_segment(&segment::segment_path(&dir, *index))?);
+        }
+
+        Ok(Journal { dir, segments, entries })
+    }
+
+    /// room events after the last checkpoint, to be applied again to the message store.
+    /// writes the store had not flushed before a crash are recovered this way.
+    /// those before it are in the store already, and may have been expired or erased there since.
+    pub fn room_events(&self) -> impl Iterator<Item = &RoomEvent> {
+        let applied = self
+            .entries
+            .iter()
+            .rposition(|entry| matches!(entry, JournalEntry::Checkpoint))
+            .map_or(0, |at| at + 1);
+
+        self.entries[applied..].iter().filter_map(|entry| match entry {
+            JournalEntry::Room(event) => Some(event),
+            _ => None,
+        })
+    }
+
+    pub fn recover(&self) -> Recovered {
+        State::replay(&self.entries).recovered()
+    }
+
+    /// start appending after the read segments. with compact, they are replaced by a snapshot first,
+    /// only pass it once the room events are safely in the message store.
+    /// otherwise they are kept until a later checkpoint finds the store caught up.
+    pub fn start(self, compact: bool) -> Result<JournalHandle> {
+        let next_index = self.segments.last().map_or(0, |index| index + 1);
+        let state = State::replay(&self.entries);
+
+        let mut writer = SegmentWriter::create(&self.dir, next_index, SEGMENT_SIZE)?;
+        if compact {
+            for entry in state.snapshot().iter() {
+                writer.append(entry)?;
+            }
+            writer.sync()?;
+
+            for index in self.segments.iter() {
+                fs::remove_file(segment::segment_path(&self.dir, *index))?;
+            }
+
+            info!(
+                segments = self.segments.len(),
+                segment = writer.index(),
+                rooms = state.order.len(),
+                "journal: compacted"
+            );
+        }
+
+        let journal_writer = JournalWriter {
+            writer,
+            state,
+            written: 0,
+            marked: None,
+        };
+        let (sender, mailbox) = mpsc::channel(10_000);
+        std::thread::Builder::new()
+            .name("journal".to_string())
+            .spawn(move || writer_loop(journal_writer, mailbox))?;
+
+        Ok(JournalHandle { sender })
+    }
+}
+
+enum JournalCommand {
+    Append(JournalEntry),
+    /// drop every entry about a customer and its closed rooms from the segments on disk.
+    Erase {
+        member_id: String,
+        rooms: HashSet<RoomId>,
+        respond_to: oneshot::Sender<()>,
+    },
+    /// answered once every entry appended before is on disk.
+    Flush {
+        respond_to: oneshot::Sender<()>,
+    },
+    /// start a new segment and remember the state at this point. answered whether it did,
+    /// which it only does when forced or once a segment's worth was written since the last checkpoint.
+    Mark {
+        force: bool,
+        respond_to: oneshot::Sender<bool>,
+    },
+    /// the store applied every room event before the mark. the segments before it are
+    /// replaced by a snapshot of the state at the mark.
+    Checkpoint {
+        respond_to: oneshot::Sender<()>,
+    },
+}
+
+/// the segment writer, and the state of the rooms and queue as appended so far.
+struct JournalWriter {
+    writer: SegmentWriter,
+    state: State,
+    /// bytes appended since the last mark.
+    written: u64,
+    /// first segment after the last mark, and the state at the mark.
+    marked: Option<(u64, State)>,
+}
+
+impl JournalWriter {
+    fn append(&mut self, entry: &JournalEntry) -> Result<()> {
+        self.written += self.writer.append(entry)?;
+        self.state.apply(entry);
+
+        Ok(())
+    }
+
+    fn erase(&mut self, member_id: &str, rooms: &HashSet<RoomId>) -> Result<()> {
+        let writer = &mut self.writer;
+        writer.sync()?;
+
+        for index in segment::list_segments(writer.dir())? {
+            segment::rewrite_segment(&segment::segment_path(writer.dir(), index), |entry| !entry.mentions(member_id, rooms))?;
+        }
+
+        writer.reopen()?;
+        Ok(())
+    }
+
+    fn mark(&mut self, force: bool) -> Result<bool> {
+        if !force && self.written < SEGMENT_SIZE {
+            return Ok(false);
+        }
+
+        self.writer.roll()?;
+        self.written = 0;
+        self.marked = Some((self.writer.index(), self.state.clone()));
+
+        Ok(true)
+    }
+
+    /// the segment just before the mark becomes the snapshot, every older one is removed.
+    /// a crash in between leaves a journal that still replays to the same state.
+    fn checkpoint(&mut self) -> Result<()> {
+        let (marked, state) = match self.marked.take() {
+            Some(marked) => marked,
+            None => return Ok(()),
+        };
+
+        let dir = self.writer.dir().to_path_buf();
+        let segments: Vec<u64> = segment::list_segments(&dir)?.into_iter().filter(|index| *index < marked).collect();
+        let snapshot = match segments.last() {
+            Some(snapshot) => *snapshot,
+            None => return Ok(()),
+        };
+
+        segment::write_segment(&segment::segment_path(&dir, snapshot), &state.snapshot())?;
+        for index in segments.iter().filter(|index| **index < snapshot) {
+            fs::remove_file(segment::segment_path(&dir, *index))?;
+        }
+
+        info!(
+            removed = segments.len() - 1,
+            segment = snapshot,
+            rooms = state.order.len(),
+            "journal: checkpoint"
+        );
+        Ok(())
+    }
+}
+
+/// the writer runs on its own thread, every batch is fsynced before the next is read.
+fn writer_loop(mut journal: JournalWriter, mut mailbox: mpsc::Receiver<JournalCommand>) {
+    while let Some(command) = mailbox.blocking_recv() {
+        let mut batch = vec![command];
+        while let Ok(command) = mailbox.try_recv() {
+            batch.push(command);
+        }
+
+        for command in batch {
+            match command {
+                JournalCommand::Append(entry) => {
+                    if let Err(err) = journal.append(&entry) {
+                        error!(error = ?err, "journal append error");
+                    }
+                }
+                JournalCommand::Erase {
+                    member_id,
+                    rooms,
+                    respond_to,
+                } => {
+                    if let Err(err) = journal.erase(&member_id, &rooms) {
+                        error!(member_id, error = ?err, "journal erase error");
+                    }
+                    let _ = respond_to.send(());
+                }
+                JournalCommand::Flush { respond_to } => {
+                    if let Err(err) = journal.writer.sync() {
+                        error!(error = ?err, "journal sync error");
+                    }
+                    let _ = respond_to.send(());
+                }
+                JournalCommand::Mark { force, respond_to } => {
+                    let marked = journal.mark(force).unwrap_or_else(|err| {
+                        error!(error = ?err, "journal mark error");
+                        false
+                    });
+                    let _ = respond_to.send(marked);
+                }
+                JournalCommand::Checkpoint { respond_to } => {
+                    if let Err(err) = journal.checkpoint() {
+                        error!(error = ?err, "journal checkpoint error");
+                    }
+                    let _ = respond_to.send(());
+                }
+            }
+        }
+
+        if let Err(err) = journal.writer.sync() {
+            error!(error = ?err, "journal sync error");
+        }
+    }
+}
+
+/// journal writer handle.
+#[derive(Clone)]
+pub struct JournalHandle {
+    sender: mpsc::Sender<JournalCommand>,
+}
+
+impl std::fmt::Debug for JournalHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JournalHandle").finish()
+    }
+}
+
+impl JournalHandle {
+    async fn send_command(&self, command: JournalCommand) {
+        if self.sender.send(command).await.is_err() {
+            error!("send journal command error: writer stopped");
+        }
+    }
+
+    pub async fn append(&self, entry: JournalEntry) {
+        self.send_command(JournalCommand::Append(entry)).await;
+    }
+
+    /// wait until every appended entry is on disk.
+    pub async fn flush(&self) {
+        let (tx, rx) = oneshot::channel();
+
+        self.send_command(JournalCommand::Flush { respond_to: tx }).await;
+
+        let _ = rx.await;
+    }
+
+    /// drop the segments the store has applied, keeping a snapshot of the open rooms and queue.
+    /// unless forced, only once a segment's worth was written since the last checkpoint.
+    /// returns whether it did.
+    pub async fn checkpoint(&self, store: &StoreHandle, force: bool) -> bool {
+        let (tx, rx) = oneshot::channel();
+        self.send_command(JournalCommand::Mark { force, respond_to: tx }).await;
+        if !rx.await.unwrap_or_default() {
+            return false;
+        }
+
+        // rooms queue their events for the store before journaling them,
+        // so this covers every room event before the mark.
+        if !store.sync().await {
+            warn!("journal: store writes failed, keeping every segment for the replay at the next start");
+            return false;
+        }
+
+        let (tx, rx) = oneshot::channel();
+        self.send_command(JournalCommand::Checkpoint { respond_to: tx }).await;
+
+        rx.await.is_ok()
+    }
+
+    /// remove customer member_id and its closed rooms from the journal.
+    pub async fn erase(&self, member_id: String, rooms: HashSet<RoomId>) {
+        let (tx, rx) = oneshot::channel();
+
+        self.send_command(JournalCommand::Erase {
+            member_id,
+            rooms,
+            respond_to: tx,
+        })
+        .await;
+
+        let _ = rx.await;
+    }
+}
+
+/// checkpoint the journal in the background, so it doesn't grow without bound.
+pub fn spawn_checkpoints(journal: JournalHandle, store: StoreHandle) {
+    tokio::spawn(
+        async move {
+            let mut interval = tokio::time::interval(CHECKPOINT_INTERVAL);
+            interval.tick().await;
+
+            loop {
+                interval.tick().await;
+                journal.checkpoint(&store, false).await;
+            }
+        }
+        .instrument(info_span!(parent: None, "journal")),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::UserType;
+
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("im-journal-{}", uuid::Uuid::new_v4()))
+    }
+
+    fn customer(id: &str) -> Member {
+        Member::new(UserType::Customer, id.to_string(), id.to_string())
+    }
+
+    fn created(room_id: &str) -> JournalEntry {
+        JournalEntry::Room(RoomEvent::Created {
+            room_id: room_id.to_string(),
+            at: 0,
+        })
+    }
+
+    fn writer(dir: &Path) -> JournalWriter {
+        let journal = Journal::open(dir).unwrap();
+        let state = State::replay(&journal.entries);
+
+        JournalWriter {
+            writer: SegmentWriter::create(dir, 0, SEGMENT_SIZE).unwrap(),
+            state,
+            written: 0,
+            marked: None,
+        }
+    }
+
+    #[test]
+    fn checkpoint_keeps_state_and_replays_only_later_events() {
+        let dir = temp_dir();
+        let mut journal = writer(&dir);
+
+        journal.append(&created("r1")).unwrap();
+        journal.append(&created("r2")).unwrap();
+        journal.append(&JournalEntry::Enqueued { member: customer("c1") }).unwrap();
+        journal
+            .append(&JournalEntry::Room(RoomEvent::Closed {
+                room_id: "r1".to_string(),
+                at: 0,
+            }))
+            .unwrap();
+
+        assert!(!journal.mark(false).unwrap());
+        assert!(journal.mark(true).unwrap());
+        journal.append(&created("r3")).unwrap();
+        journal.checkpoint().unwrap();
+        journal.writer.roll().unwrap();
+        assert_eq!(segment::list_segments(&dir).unwrap(), [0, 1, 2]);
+
+        // a second checkpoint removes the segments before the first one's.
+        assert!(journal.mark(true).unwrap());
+        journal.checkpoint().unwrap();
+        assert_eq!(segment::list_segments(&dir).unwrap(), [2, 3]);
+
+        let reopened = Journal::open(&dir).unwrap();
+        let recovered = reopened.recover();
+        let rooms: Vec<&str> = recovered.rooms.iter().map(|room| room.room_id.as_str()).collect();
+        assert_eq!(rooms, ["r2", "r3"]);
+        assert_eq!(recovered.waiting, [customer("c1")]);
+        assert_eq!(reopened.room_events().count(), 0);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn events_after_the_checkpoint_are_replayed() {
+        let dir = temp_dir();
+        let mut journal = writer(&dir);
+
+        journal.append(&created("r1")).unwrap();
+        assert!(journal.mark(true).unwrap());
+        journal.checkpoint().unwrap();
+        journal.append(&created("r2")).unwrap();
+        journal.writer.sync().unwrap();
+
+        let reopened = Journal::open(&dir).unwrap();
+        let replayed: Vec<&RoomEvent> = reopened.room_events().collect();
+        assert!(matches!(replayed.as_slice(), [RoomEvent::Created { room_id, .. }] if room_id == "r2"));
+        assert_eq!(reopened.recover().rooms.len(), 2);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn segments_are_kept_when_not_compacted() {
+        let dir = temp_dir();
+        let mut journal = writer(&dir);
+        journal.append(&created("r1")).unwrap();
+        journal.writer.sync().unwrap();
+        drop(journal);
+
+        let handle = Journal::open(&dir).unwrap().start(false).unwrap();
+        drop(handle);
+        let reopened = Journal::open(&dir).unwrap();
+        assert_eq!(reopened.room_events().count(), 1);
+
+        let handle = reopened.start(true).unwrap();
+        drop(handle);
+        assert_eq!(Journal::open(&dir).unwrap().room_events().count(), 0);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,269 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use tracing::warn;
+
+use super::JournalEntry;
+
+/// a record is `len: u32 le | crc32: u32 le | json`.
+const HEADER_LEN: usize = 8;
+
+const SEGMENT_EXT: &str = "seg";
+
+pub fn segment_path(dir: &Path, index: u64) -> PathBuf {
+    dir.join(format!("{:020}.{}", index, SEGMENT_EXT))
+}
+
+/// indexes of the segments in dir, oldest first.
+pub fn list_segments(dir: &Path) -> Result<Vec<u64>> {
+    let mut indexes = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some(SEGMENT_EXT) {
+            continue;
+        }
+
+        if let Some(index) = path.file_stem().and_then(|stem| stem.to_str()).and_then(|stem| stem.parse().ok()) {
+            indexes.push(index);
+        }
+    }
+
+    indexes.sort_unstable();
+    Ok(indexes)
+}
+
+/// read every intact record of a segment. reading stops at the first torn or corrupt record,
+/// which is what a crash in the middle of a write leaves behind.
+pub fn read_segment(path: &Path) -> Result<Vec<JournalEntry>> {
+    let data = fs::read(path)?;
+    let mut entries = Vec::new();
+    let mut offset = 0;
+
+    while offset + HEADER_LEN <= data.len() {
+        let len = u32::from_le_bytes(data[offset..offset + 4].try_into()?) as usize;
+        let crc = u32::from_le_bytes(data[offset + 4..offset + 8].try_into()?);
+        let start = offset + HEADER_LEN;
+
+        if start + len > data.len() {
+            warn!(path = %path.display(), offset, "journal: torn record");
+            break;
+        }
+
+        let body = &data[start..start + len];
+        if crc32fast::hash(body) != crc {
+            warn!(path = %path.display(), offset, "journal: corrupt record");
+            break;
+        }
+
+        match serde_json::from_slice(body) {
+            Ok(entry) => entries.push(entry),
+            Err(err) => {
+                warn!(path = %path.display(), offset, error = ?err, "journal: unreadable record");
+                break;
+            }
+        }
+
+        offset = start + len;
+    }
+
+    Ok(entries)
+}
+
+fn write_record(out: &mut impl Write, entry: &JournalEntry) -> Result<u64> {
+    let body = serde_json::to_vec(entry)?;
+    out.write_all(&(body.len() as u32).to_le_bytes())?;
+    out.write_all(&crc32fast::hash(&body).to_le_bytes())?;
+    out.write_all(&body)?;
+
+    Ok((HEADER_LEN + body.len()) as u64)
+}
+
+/// write a segment with entries, replacing the file at path atomically.
+pub fn write_segment<'a>(path: &Path, entries: impl IntoIterator<Item = &'a JournalEntry>) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+
+    let mut out = BufWriter::new(File::create(&tmp)?);
+    for entry in entries {
+        write_record(&mut out, entry)?;
+    }
+    out.flush()?;
+    out.get_ref().sync_data()?;
+
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// rewrite a segment with only the entries matching keep.
+pub fn rewrite_segment(path: &Path, keep: impl Fn(&JournalEntry) -> bool) -> Result<()> {
+    let entries = read_segment(path)?;
+
+    write_segment(path, entries.iter().filter(|entry| keep(entry)))
+}
+
+/// the segment being appended to.
+pub struct SegmentWriter {
+    dir: PathBuf,
+    index: u64,
+    size: u64,
+    max_size: u64,
+    file: BufWriter<File>,
+}
+
+impl SegmentWriter {
+    /// start a new segment with index in dir.
+    pub fn create(dir: &Path, index: u64, max_size: u64) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(segment_path(dir, index))?;
+        let size = file.metadata()?.len();
+
+        Ok(SegmentWriter {
+            dir: dir.to_path_buf(),
+            index,
+            size,
+            max_size,
+            file: BufWriter::new(file),
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// open the current segment again, after it was rewritten.
+    pub fn reopen(&mut self) -> io::Result<()> {
+        self.sync()?;
+        *self = SegmentWriter::create(&self.dir, self.index, self.max_size)?;
+
+        Ok(())
+    }
+
+    pub fn index(&self) -> u64 {
+        self.index
+    }
+
+    /// sync the current segment and move to the next one.
+    pub fn roll(&mut self) -> io::Result<()> {
+        self.sync()?;
+        *self = SegmentWriter::create(&self.dir, self.index + 1, self.max_size)?;
+
+        Ok(())
+    }
+
+    /// buffer entry, moving to a new segment once the current one is full. returns the bytes written.
+    pub fn append(&mut self, entry: &JournalEntry) -> Result<u64> {
+        if self.size >= self.max_size {
+            self.roll()?;
+        }
+
+        let written = write_record(&mut self.file, entry)?;
+        self.size += written;
+
+        Ok(written)
+    }
+
+    /// write buffered records and fsync them.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.file.get_ref().sync_data()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::{Member, UserType};
+
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("im-segment-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn enqueued(id: &str) -> JournalEntry {
+        JournalEntry::Enqueued {
+            member: Member::new(UserType::Customer, id.to_string(), id.to_string()),
+        }
+    }
+
+    fn ids(entries: &[JournalEntry]) -> Vec<String> {
+        entries
+            .iter()
+            .map(|entry| match entry {
+                JournalEntry::Enqueued { member } => member.id().to_string(),
+                other => panic!("unexpected entry {:?}", other),
+            })
+            .collect()
+    }
+
+    fn write(dir: &Path, entries: &[JournalEntry]) -> PathBuf {
+        let mut writer = SegmentWriter::create(dir, 0, u64::MAX).unwrap();
+        for entry in entries {
+            writer.append(entry).unwrap();
+        }
+        writer.sync().unwrap();
+
+        segment_path(dir, 0)
+    }
+
+    #[test]
+    fn reads_what_was_appended() {
+        let dir = temp_dir();
+        let path = write(&dir, &[enqueued("a"), enqueued("b"), enqueued("c")]);
+
+        assert_eq!(ids(&read_segment(&path).unwrap()), ["a", "b", "c"]);
+        assert_eq!(list_segments(&dir).unwrap(), [0]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn stops_at_torn_tail() {
+        let dir = temp_dir();
+        let path = write(&dir, &[enqueued("a"), enqueued("b")]);
+
+        // a crash in the middle of the last record, and in the middle of a header.
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new().write(true).open(&path).unwrap().set_len(len - 3).unwrap();
+        assert_eq!(ids(&read_segment(&path).unwrap()), ["a"]);
+
+        let first = (HEADER_LEN + serde_json::to_vec(&enqueued("a")).unwrap().len()) as u64;
+        OpenOptions::new().write(true).open(&path).unwrap().set_len(first + 5).unwrap();
+        assert_eq!(ids(&read_segment(&path).unwrap()), ["a"]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn stops_at_corrupt_record() {
+        let dir = temp_dir();
+        let path = write(&dir, &[enqueued("a"), enqueued("b"), enqueued("c")]);
+
+        let mut data = fs::read(&path).unwrap();
+        let second = HEADER_LEN + serde_json::to_vec(&enqueued("a")).unwrap().len();
+        data[second + HEADER_LEN + 2] ^= 0xff;
+        fs::write(&path, data).unwrap();
+
+        assert_eq!(ids(&read_segment(&path).unwrap()), ["a"]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rolls_over_when_full() {
+        let dir = temp_dir();
+        let mut writer = SegmentWriter::create(&dir, 0, 1).unwrap();
+        writer.append(&enqueued("a")).unwrap();
+        writer.append(&enqueued("b")).unwrap();
+        writer.sync().unwrap();
+
+        assert_eq!(list_segments(&dir).unwrap(), [0, 1]);
+        assert_eq!(ids(&read_segment(&segment_path(&dir, 1)).unwrap()), ["b"]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
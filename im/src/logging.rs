@@ -0,0 +1,35 @@
+use anyhow::{anyhow, Context, Result};
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::EnvFilter;
+
+use crate::config::{LogConfig, LogFormat};
+
+/// the filter of config: its level, then the level of each listed module.
+pub fn filter(config: &LogConfig) -> Result<EnvFilter> {
+    let level: LevelFilter = config
+        .level
+        .parse()
+        .with_context(|| format!("log.level: invalid level {:?}", config.level))?;
+
+    let mut filter = EnvFilter::default().add_directive(level.into());
+    for (module, level) in config.modules.iter() {
+        let directive = format!("{}={}", module, level)
+            .parse()
+            .map_err(|err| anyhow!("log.modules.{:?}: invalid level {:?}: {}", module, level, err))?;
+        filter = filter.add_directive(directive);
+    }
+
+    Ok(filter)
+}
+
+/// install the global subscriber. events carry the fields of the spans they happen in.
+pub fn init(config: &LogConfig) -> Result<()> {
+    let builder = tracing_subscriber::fmt().with_env_filter(filter(config)?);
+
+    let installed = match config.format {
+        LogFormat::Json => builder.json().with_current_span(true).with_span_list(true).try_init(),
+        LogFormat::Text => builder.try_init(),
+    };
+
+    installed.map_err(|err| anyhow!("install logger: {}", err))
+}
@@ -1,38 +1,328 @@
+pub mod admission;
+pub mod attachment;
 pub mod auth;
+pub mod cli;
+pub mod clock;
+pub mod config;
 pub mod dispatch;
+pub mod health;
+pub mod http;
+pub mod i18n;
+pub mod journal;
+pub mod logging;
 pub mod message;
+pub mod metrics;
+pub mod moderation;
+pub mod notify;
+pub mod retention;
+pub mod search;
 pub mod session;
+pub mod store;
+pub mod tls;
+pub mod transcript;
 
-use tokio::net::TcpListener;
+use std::{sync::Arc, time::Duration};
 
-use crate::{message::internal::SessionMessage, session::conn::ConnHandle};
+use clap::Parser;
+use tokio::{
+    net::{TcpListener, TcpStream},
+    signal::unix::{signal, SignalKind},
+    time::{timeout, timeout_at, Instant},
+};
+use tracing::{debug, error, field, info, info_span, warn, Instrument};
+use uuid::Uuid;
+
+use crate::{
+    admission::{Admission, Permit, Rejection},
+    attachment::{LocalDiskStorage, UrlSigner},
+    auth::{token::TokenVerifier, ConnWrapper},
+    cli::{Cli, Command},
+    config::{Config, ConnConfig},
+    dispatch::DispatchHandle,
+    health::Health,
+    i18n::I18n,
+    journal::{Journal, JournalHandle},
+    message::internal::SessionMessage,
+    moderation::Moderation,
+    notify::Webhook,
+    session::{conn::ConnHandle, limit::MemberLimits},
+    store::{MessageStore, SqliteStore, StoreHandle},
+    tls::{Acceptor, ClientStream},
+};
 
 #[tokio::main]
 async fn main() {
-    let socket = TcpListener::bind("0.0.0.0:9001").await;
+    let cli = Cli::parse();
+
+    let config = match Config::load(&cli.serve) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("invalid configuration: {:#}", err);
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(Command::Export(args)) = cli.command {
+        if let Err(err) = cli::export(&config, args) {
+            eprintln!("export failed: {:?}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Err(err) = config.validate() {
+        eprintln!("invalid configuration: {:#}", err);
+        std::process::exit(1);
+    }
+
+    if let Err(err) = logging::init(&config.log) {
+        eprintln!("{:#}", err);
+        std::process::exit(1);
+    }
+
+    let socket = TcpListener::bind(config.server.bind).await;
     let listener = socket.expect("failed to bind");
 
-    println!("Listening on: {}", listener.local_addr().unwrap());
+    // without tls the listener speaks plain tcp, e.g. behind a terminating proxy.
+    let tls = match config.tls.clone().map(Acceptor::new).transpose() {
+        Ok(tls) => tls,
+        Err(err) => {
+            error!(error = ?err, "failed to load tls certificates");
+            std::process::exit(1);
+        }
+    };
+    if let Some(acceptor) = &tls {
+        if let Err(err) = tls::spawn_reload(acceptor.clone()) {
+            warn!(error = ?err, "failed to listen for SIGHUP, tls certificates are not reloaded");
+        }
+    }
+
+    info!(addr = %listener.local_addr().unwrap(), tls = tls.is_some(), "listening");
 
-    let dispatch_handle = dispatch::DispatchHandle::new();
-    while let Ok((stream, _)) = listener.accept().await {
-        let handle = dispatch_handle.clone();
+    let health = Arc::new(Health::default());
+    health.set_listening();
+
+    let store: Arc<dyn MessageStore> = match SqliteStore::open(config.server.db_path()) {
+        Ok(store) => Arc::new(store),
+        Err(err) => {
+            error!(error = ?err, "failed to open sqlite store");
+            std::process::exit(1);
+        }
+    };
+    let store_handle = StoreHandle::new(store);
+
+    // the journal may hold events the store had not written when the process stopped.
+    let journal = Journal::open(config.server.journal_dir()).expect("failed to open journal");
+    for event in journal.room_events() {
+        store_handle.record(event.clone()).await;
+    }
+    let synced = store_handle.sync().await;
+    if !synced {
+        warn!("store failed to write the journaled events, journal kept uncompacted");
+    }
+
+    let recovered = journal.recover();
+    let journal_handle = journal.start(synced).expect("failed to compact journal");
+    journal::spawn_checkpoints(journal_handle.clone(), store_handle.clone());
+
+    // offline members can be reached through a webhook when one is configured.
+    let webhook = config
+        .notify
+        .webhook_url
+        .clone()
+        .map(|url| Webhook::new(url).expect("failed to create webhook client"));
+
+    let dispatch_handle = dispatch::DispatchHandle::new(&config, store_handle.clone(), journal_handle.clone(), recovered, webhook);
+
+    if let Some(policy) = config.retention.clone() {
+        retention::spawn(policy, store_handle.clone(), journal_handle.clone());
+    }
+
+    let mut i18n = I18n::builtin();
+    if config.server.locales_dir.is_dir() {
+        i18n.load_dir(&config.server.locales_dir).expect("failed to load locales");
+    }
+    let i18n = Arc::new(i18n);
+
+    let tokens = Arc::new(TokenVerifier::new(config.auth.jwt_secret.as_bytes()));
+    let member_limits = Arc::new(MemberLimits::new(config.conn.rate_limit.member));
+    let admission = Admission::new(config.admission.clone());
+    let moderation = Arc::new(Moderation::new(&config.moderation).expect("moderation is validated with the config"));
+
+    let storage = LocalDiskStorage::new(config.server.attachments_dir()).expect("failed to open attachment storage");
+    let http_state = http::AppState {
+        dispatch: dispatch_handle.clone(),
+        storage: Arc::new(storage),
+        signer: Arc::new(UrlSigner::new(config.auth.attachment_signing_key.as_bytes().to_vec())),
+        attachment: config.attachment.clone(),
+        tokens: tokens.clone(),
+        store: store_handle.clone(),
+        journal: journal_handle.clone(),
+        i18n: i18n.clone(),
+        health: health.clone(),
+    };
+
+    let admin_bind = config.server.admin_bind;
+    let admin_state = http_state.clone();
+    tokio::spawn(async move {
+        if let Err(err) = http::serve_admin(admin_bind, admin_state).await {
+            error!(error = ?err, "admin server error");
+        }
+    });
 
-        tokio::spawn(async move {
-            let conn_wrapper = match auth::handshake(stream).await {
-                Ok(conn_wrapper) => conn_wrapper,
+    let http_bind = config.server.http_bind;
+    tokio::spawn(async move {
+        if let Err(err) = http::serve(http_bind, http_state).await {
+            error!(error = ?err, "http server error");
+        }
+    });
+
+    let mut terminate = signal(SignalKind::terminate()).expect("failed to listen for SIGTERM");
+
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
                 Err(err) => {
-                    println!("handshake error: {:?}", err);
-                    return;
+                    error!(error = ?err, "accept error");
+                    break;
                 }
-            };
+            },
+            _ = terminate.recv() => {
+                info!("SIGTERM received, shutting down");
+                break;
+            }
+            _ = tokio::signal::ctrl_c() => {
+                info!("interrupted, shutting down");
+                break;
+            }
+        };
 
-            let conn_handle =
-                ConnHandle::new(conn_wrapper.member, conn_wrapper.stream, handle.clone());
+        // turned away on its address alone, with a status telling a ban from an overload.
+        // over tls only overloads are answered, bans and floods don't get a handshake out of us.
+        let permit = match admission.admit(peer.ip()) {
+            Ok(permit) => permit,
+            Err(rejection) => {
+                debug!(%peer, reason = rejection.reason(), "conn rejected");
+                metrics::metrics().handshake_failures.with_label_values(&[rejection.reason()]).inc();
+
+                if tls.is_some() && !rejection.answered_over_tls() {
+                    drop(stream);
+                    continue;
+                }
+
+                let tls = tls.clone();
+                let handshake_timeout = config.admission.handshake_timeout();
+                tokio::spawn(async move {
+                    if let Ok(Err(err)) = timeout(handshake_timeout, refuse(stream, tls.as_ref(), rejection)).await {
+                        debug!(%peer, error = %err, "failed to refuse conn");
+                    }
+                });
+                continue;
+            }
+        };
+
+        let handle = dispatch_handle.clone();
+        let i18n = i18n.clone();
+        let tokens = tokens.clone();
+        let conn_config = config.conn.clone();
+        let tls = tls.clone();
+        let member_limits = member_limits.clone();
+        let moderation = moderation.clone();
+        let handshake_timeout = config.admission.handshake_timeout();
 
-            let message = SessionMessage::OnAccept { conn: conn_handle };
+        // everything logged on behalf of this conn carries its id, and its member once known.
+        let conn_id = Uuid::new_v4().to_string();
+        let span = info_span!("conn", %conn_id, %peer, member_id = field::Empty);
 
-            let _ = handle.clone().send_message(message).await;
-        });
+        tokio::spawn(
+            async move {
+                let accepted = timeout(handshake_timeout, handshake(stream, permit, tls.as_ref(), &tokens, &conn_config))
+                    .await
+                    .unwrap_or_else(|_| Err("handshake timeout".to_string()));
+                let conn_wrapper = match accepted {
+                    Ok(conn_wrapper) => conn_wrapper,
+                    Err(reason) => {
+                        warn!(reason, "handshake error");
+                        metrics::metrics().handshake_failures.with_label_values(&[&reason]).inc();
+                        return;
+                    }
+                };
+                tracing::Span::current().record("member_id", conn_wrapper.member.id());
+
+                let conn_handle = ConnHandle::new(conn_id, conn_wrapper, handle.clone(), i18n, &conn_config, &member_limits, moderation);
+
+                let message = SessionMessage::OnAccept { conn: conn_handle };
+
+                let _ = handle.clone().send_message(message).await;
+            }
+            .instrument(span),
+        );
     }
+
+    // stop accepting first, so clients told to reconnect don't land here again.
+    drop(listener);
+    health.start_draining();
+
+    shutdown(
+        config.server.shutdown_deadline(),
+        config.server.reconnect_hint.clone(),
+        dispatch_handle,
+        store_handle,
+        journal_handle,
+    )
+    .await;
+}
+
+/// tls and websocket handshakes of an admitted conn. fails with the reason the client was rejected.
+async fn handshake(
+    stream: TcpStream,
+    permit: Permit,
+    tls: Option<&Acceptor>,
+    tokens: &TokenVerifier,
+    config: &ConnConfig,
+) -> Result<ConnWrapper, String> {
+    let stream = match tls {
+        Some(acceptor) => acceptor.accept(stream).await.map_err(|err| {
+            debug!(error = %err, "tls handshake error");
+            "tls".to_string()
+        })?,
+        None => ClientStream::Plain(stream),
+    };
+
+    auth::handshake(stream, permit, tokens, config.websocket())
+        .await
+        .map_err(|err| err.body().clone().unwrap_or_default())
+}
+
+/// answer a conn turned away by admission, over tls when the listener speaks it.
+async fn refuse(stream: TcpStream, tls: Option<&Acceptor>, rejection: Rejection) -> std::io::Result<()> {
+    let stream = match tls {
+        Some(acceptor) => acceptor.accept(stream).await?,
+        None => ClientStream::Plain(stream),
+    };
+
+    admission::refuse(stream, rejection).await
+}
+
+/// close every conn, then flush the store and journal, all within deadline.
+/// anything the store had not written by then is still in the journal and replayed at the next start.
+async fn shutdown(deadline: Duration, reconnect_hint: Option<String>, dispatch: DispatchHandle, store: StoreHandle, journal: JournalHandle) {
+    let deadline = Instant::now() + deadline;
+
+    match timeout_at(deadline, dispatch.drain(reconnect_hint)).await {
+        Ok(_) => info!("every conn closed"),
+        Err(_) => warn!("shutdown deadline reached with conns still open"),
+    }
+
+    let flushed = timeout_at(deadline, async {
+        store.flush().await;
+        journal.flush().await;
+    })
+    .await;
+    if flushed.is_err() {
+        warn!("shutdown deadline reached before the store and journal were flushed");
+    }
+
+    info!("shut down");
 }
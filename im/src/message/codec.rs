@@ -0,0 +1,90 @@
+use anyhow::{anyhow, Result};
+use tungstenite::Message;
+
+use super::protocol::ClientProtocol;
+
+/// Codec turns websocket frames into protocol messages and back.
+/// one codec is negotiated per connection, json is the default.
+pub trait Codec: Send + Sync {
+    fn decode(&self, message: &Message) -> Result<ClientProtocol>;
+
+    fn encode(&self, frame: &ClientProtocol) -> Result<Message>;
+}
+
+/// codecs a client can negotiate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodecKind {
+    Json,
+    MessagePack,
+    Cbor,
+}
+
+impl CodecKind {
+    /// suffix appended to the versioned subprotocol name. json has none.
+    pub fn suffix(&self) -> Option<&'static str> {
+        match self {
+            CodecKind::Json => None,
+            CodecKind::MessagePack => Some("msgpack"),
+            CodecKind::Cbor => Some("cbor"),
+        }
+    }
+
+    pub fn codec(&self) -> Box<dyn Codec> {
+        match self {
+            CodecKind::Json => Box::new(JsonCodec),
+            CodecKind::MessagePack => Box::new(MessagePackCodec),
+            CodecKind::Cbor => Box::new(CborCodec),
+        }
+    }
+}
+
+/// json in text frames.
+pub struct JsonCodec;
+
+impl Codec for JsonCodec {
+    fn decode(&self, message: &Message) -> Result<ClientProtocol> {
+        match message {
+            Message::Text(text) => Ok(serde_json::from_str(text)?),
+            _ => Err(anyhow!("expected a text frame")),
+        }
+    }
+
+    fn encode(&self, frame: &ClientProtocol) -> Result<Message> {
+        Ok(Message::Text(serde_json::to_string(frame)?))
+    }
+}
+
+/// messagepack maps in binary frames. text frames are still read as json.
+pub struct MessagePackCodec;
+
+impl Codec for MessagePackCodec {
+    fn decode(&self, message: &Message) -> Result<ClientProtocol> {
+        match message {
+            Message::Binary(data) => Ok(rmp_serde::from_slice(data)?),
+            _ => JsonCodec.decode(message),
+        }
+    }
+
+    fn encode(&self, frame: &ClientProtocol) -> Result<Message> {
+        // named fields, optional fields are skipped so positions would not line up.
+        Ok(Message::Binary(rmp_serde::to_vec_named(frame)?))
+    }
+}
+
+/// cbor in binary frames. text frames are still read as json.
+pub struct CborCodec;
+
+impl Codec for CborCodec {
+    fn decode(&self, message: &Message) -> Result<ClientProtocol> {
+        match message {
+            Message::Binary(data) => Ok(ciborium::from_reader(data.as_slice())?),
+            _ => JsonCodec.decode(message),
+        }
+    }
+
+    fn encode(&self, frame: &ClientProtocol) -> Result<Message> {
+        let mut data = Vec::new();
+        ciborium::into_writer(frame, &mut data)?;
+        Ok(Message::Binary(data))
+    }
+}
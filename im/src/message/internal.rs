@@ -1,8 +1,10 @@
 use tokio::sync::oneshot;
-use tungstenite::Message;
+use tracing::Span;
+use tungstenite::protocol::frame::coding::CloseCode;
 
 use crate::{
     auth::{Member, RoomId},
+    dispatch::admin::{AgentInfo, AssignError, RoomInfo, SuperviseError, WaitingInfo},
     session::conn::ConnHandle,
 };
 
@@ -12,10 +14,21 @@ use super::protocol::ClientProtocol;
 pub enum ConnMessage {
     OnLeave {
         member: Member,
+        conn_id: String,
     },
     OnNewMessage {
         member: Member,
+        /// the conn it was sent on, acks and errors go back there.
+        conn_id: String,
         message: ClientProtocol,
+        /// the message is handled in this span by every actor it passes.
+        span: Span,
+    },
+    /// moderation let message through but asks supervisors to review it.
+    OnFlagged {
+        member: Member,
+        message: ClientProtocol,
+        reason: String,
     },
 }
 
@@ -32,7 +45,29 @@ pub enum RoomMessage {
     OnNewMessage {
         room_id: RoomId,
         member: Member,
-        content: Message,
+        content: ClientProtocol,
+    },
+    OnClose {
+        room_id: RoomId,
+    },
+    /// frame for this conn only, delivered even to the sender.
+    OnNotice {
+        room_id: RoomId,
+        content: ClientProtocol,
+    },
+    /// a message for supervisors to review, sent to every online supervisor.
+    OnFlagged {
+        room_id: RoomId,
+        member: Member,
+        content: ClientProtocol,
+        reason: String,
+    },
+    /// tell the client why with the tips of key, then close the conn.
+    /// the close frame carries hint as its reason, or key without one.
+    Disconnect {
+        code: CloseCode,
+        key: String,
+        hint: Option<String>,
     },
 }
 
@@ -40,15 +75,92 @@ pub enum DispatchMessage {
     OnJoin {
         conn_handle: ConnHandle,
     },
+    OnLeave {
+        member: Member,
+        conn_id: String,
+    },
+    Close,
     OnNewMessage {
         member: Member,
+        conn_id: String,
         message: ClientProtocol,
+        span: Span,
     },
     GetMemberCount {
         respond_to: oneshot::Sender<u32>,
     },
+    IsMember {
+        member: Member,
+        respond_to: oneshot::Sender<bool>,
+    },
+    Describe {
+        respond_to: oneshot::Sender<RoomInfo>,
+    },
 }
 
 pub enum SessionMessage {
-    OnAccept { conn: ConnHandle },
+    OnAccept {
+        conn: ConnHandle,
+    },
+    IsRoomMember {
+        room_id: RoomId,
+        member: Member,
+        respond_to: oneshot::Sender<bool>,
+    },
+    /// keep the customer with member_id out, e.g. while it is erased. responds false without holding it
+    /// when it is online, waiting or has an open room.
+    HoldCustomer {
+        member_id: String,
+        respond_to: oneshot::Sender<bool>,
+    },
+    /// let a held customer in again.
+    ReleaseCustomer {
+        member_id: String,
+    },
+    /// answered right away, shows the manager loop is alive.
+    Ping {
+        respond_to: oneshot::Sender<()>,
+    },
+    Admin(AdminMessage),
+    /// the node is shutting down: close every conn, with hint as the close reason, and turn new ones away.
+    /// answered once every conn has left.
+    Drain {
+        hint: Option<String>,
+        respond_to: oneshot::Sender<()>,
+    },
+}
+
+/// requests of the admin api.
+pub enum AdminMessage {
+    ListAgents {
+        respond_to: oneshot::Sender<Vec<AgentInfo>>,
+    },
+    ListRooms {
+        respond_to: oneshot::Sender<Vec<RoomInfo>>,
+    },
+    ListQueue {
+        respond_to: oneshot::Sender<Vec<WaitingInfo>>,
+    },
+    /// take customer out of the waiting queue and open a room with agent.
+    Assign {
+        customer_id: String,
+        agent_id: String,
+        respond_to: oneshot::Sender<Result<RoomId, AssignError>>,
+    },
+    /// join the online conns of supervisor to the room, so it can speak there.
+    Supervise {
+        room_id: RoomId,
+        supervisor: Member,
+        respond_to: oneshot::Sender<Result<(), SuperviseError>>,
+    },
+    /// responds whether the room was open.
+    CloseRoom {
+        room_id: RoomId,
+        respond_to: oneshot::Sender<bool>,
+    },
+    /// responds whether the conn was online.
+    Kick {
+        conn_id: String,
+        respond_to: oneshot::Sender<bool>,
+    },
 }
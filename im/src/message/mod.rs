@@ -1,2 +1,5 @@
+pub mod codec;
 pub mod internal;
+pub mod payload;
 pub mod protocol;
+pub mod version;
@@ -0,0 +1,491 @@
+use std::{collections::HashSet, fmt};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    protocol::{ClientProtocol, MessageType},
+    version::HelloPayload,
+};
+
+const MAX_IMAGE_SIZE: u64 = 10 * 1024 * 1024;
+const MAX_FILE_SIZE: u64 = 50 * 1024 * 1024;
+const MAX_QUICK_REPLY_BUTTONS: usize = 10;
+const MAX_BUTTON_LABEL_LEN: usize = 40;
+const MAX_ID_LEN: usize = 64;
+const MAX_CARD_TEXT_LEN: usize = 200;
+const MAX_URL_LEN: usize = 2048;
+const MAX_FILE_NAME_LEN: usize = 255;
+
+/// reference to an uploaded image.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ImagePayload {
+    pub url: String,
+    pub mime: String,
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail: Option<String>,
+}
+
+/// reference to an uploaded file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FilePayload {
+    pub url: String,
+    pub name: String,
+    pub mime: String,
+    pub size: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CardKind {
+    Product,
+    Order,
+}
+
+/// product or order card.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CardPayload {
+    pub kind: CardKind,
+    /// product id or order number.
+    pub id: String,
+    pub title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subtitle: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub price: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Button {
+    pub id: String,
+    pub label: String,
+}
+
+/// list of buttons the other side can click.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct QuickReplyPayload {
+    pub buttons: Vec<Button>,
+}
+
+/// response to a quick reply button.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ButtonClickPayload {
+    pub button_id: String,
+    pub label: String,
+}
+
+/// reference to a message previously sent in the room, by server sequence.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageRef {
+    pub seq: u64,
+}
+
+/// page of room history requested by a client. sequences are exclusive bounds.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HistoryQuery {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub before: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub after: Option<u64>,
+    pub limit: u32,
+}
+
+pub const MAX_HISTORY_LIMIT: u32 = 100;
+
+impl HistoryQuery {
+    /// the newest limit messages.
+    pub fn latest(limit: u32) -> Self {
+        HistoryQuery {
+            before: None,
+            after: None,
+            limit,
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.limit == 0 || self.limit > MAX_HISTORY_LIMIT {
+            return Err(ValidationError::new(
+                "payload.limit",
+                format!("must be between 1 and {}", MAX_HISTORY_LIMIT),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// page of room history, ordered by sequence.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HistoryPayload {
+    pub messages: Vec<ClientProtocol>,
+    /// whether older (or, when paging with `after`, newer) messages remain.
+    pub has_more: bool,
+}
+
+/// messages that arrived while the member was offline, ordered by sequence.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct InboxPayload {
+    /// how many messages were missed. larger than messages when the inbox overflowed.
+    pub unread: u32,
+    pub messages: Vec<ClientProtocol>,
+}
+
+/// typed payload of a non-text message.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum Payload {
+    Image(ImagePayload),
+    File(FilePayload),
+    Card(CardPayload),
+    QuickReply(QuickReplyPayload),
+    ButtonClick(ButtonClickPayload),
+    Recall(MessageRef),
+    Edit(MessageRef),
+    Hello(HelloPayload),
+    FetchHistory(HistoryQuery),
+    History(HistoryPayload),
+    Inbox(InboxPayload),
+}
+
+/// returned when a client message does not match the schema of its type.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationError {
+    field: &'static str,
+    reason: String,
+}
+
+impl ValidationError {
+    pub fn new(field: &'static str, reason: impl Into<String>) -> Self {
+        ValidationError {
+            field,
+            reason: reason.into(),
+        }
+    }
+
+    pub fn field(&self) -> &str {
+        self.field
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid {}: {}", self.field, self.reason)
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+type Result<T> = std::result::Result<T, ValidationError>;
+
+fn require(field: &'static str, value: &str) -> Result<()> {
+    if value.trim().is_empty() {
+        return Err(ValidationError::new(field, "must not be empty"));
+    }
+
+    Ok(())
+}
+
+fn check_len(field: &'static str, value: &str, max: usize) -> Result<()> {
+    if value.chars().count() > max {
+        return Err(ValidationError::new(field, format!("must be at most {} characters", max)));
+    }
+
+    Ok(())
+}
+
+fn check_text(field: &'static str, value: &str, max: usize) -> Result<()> {
+    require(field, value)?;
+    check_len(field, value, max)
+}
+
+fn check_optional_text(field: &'static str, value: &Option<String>, max: usize) -> Result<()> {
+    match value {
+        Some(value) => check_len(field, value, max),
+        None => Ok(()),
+    }
+}
+
+/// urls must be absolute http(s) urls with a host or paths of uploaded attachments.
+fn check_url(field: &'static str, url: &str) -> Result<()> {
+    check_text(field, url, MAX_URL_LEN)?;
+
+    if url.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return Err(ValidationError::new(field, "must not contain whitespace"));
+    }
+
+    let host = url.strip_prefix("https://").or_else(|| url.strip_prefix("http://"));
+    let valid = match host {
+        Some(rest) => !rest.is_empty() && !rest.starts_with(['/', '?', '#']),
+        None => url.strip_prefix("/attachments/").is_some_and(|id| !id.is_empty() && !id.starts_with('/')),
+    };
+
+    if !valid {
+        return Err(ValidationError::new(field, "must be an http(s) url or an attachment path"));
+    }
+
+    Ok(())
+}
+
+fn check_optional_url(field: &'static str, url: &Option<String>) -> Result<()> {
+    match url {
+        Some(url) => check_url(field, url),
+        None => Ok(()),
+    }
+}
+
+fn check_mime(field: &'static str, mime: &str) -> Result<()> {
+    match mime.split_once('/') {
+        Some((kind, sub)) if !kind.is_empty() && !sub.is_empty() => Ok(()),
+        _ => Err(ValidationError::new(field, "must be a mime type")),
+    }
+}
+
+fn check_size(field: &'static str, size: u64, max: u64) -> Result<()> {
+    if size == 0 || size > max {
+        return Err(ValidationError::new(field, format!("must be between 1 and {} bytes", max)));
+    }
+
+    Ok(())
+}
+
+impl ImagePayload {
+    fn validate(&self) -> Result<()> {
+        check_url("payload.url", &self.url)?;
+        check_mime("payload.mime", &self.mime)?;
+        if !self.mime.starts_with("image/") {
+            return Err(ValidationError::new("payload.mime", "must be an image type"));
+        }
+        check_size("payload.size", self.size, MAX_IMAGE_SIZE)?;
+        check_optional_url("payload.thumbnail", &self.thumbnail)
+    }
+}
+
+impl FilePayload {
+    fn validate(&self) -> Result<()> {
+        check_url("payload.url", &self.url)?;
+        check_text("payload.name", &self.name, MAX_FILE_NAME_LEN)?;
+        check_mime("payload.mime", &self.mime)?;
+        check_size("payload.size", self.size, MAX_FILE_SIZE)
+    }
+}
+
+impl CardPayload {
+    fn validate(&self) -> Result<()> {
+        check_text("payload.id", &self.id, MAX_ID_LEN)?;
+        check_text("payload.title", &self.title, MAX_CARD_TEXT_LEN)?;
+        check_optional_text("payload.subtitle", &self.subtitle, MAX_CARD_TEXT_LEN)?;
+        check_optional_text("payload.price", &self.price, MAX_CARD_TEXT_LEN)?;
+        check_optional_url("payload.image", &self.image)?;
+        check_optional_url("payload.link", &self.link)
+    }
+}
+
+impl QuickReplyPayload {
+    fn validate(&self) -> Result<()> {
+        if self.buttons.is_empty() || self.buttons.len() > MAX_QUICK_REPLY_BUTTONS {
+            return Err(ValidationError::new(
+                "payload.buttons",
+                format!("must contain 1 to {} buttons", MAX_QUICK_REPLY_BUTTONS),
+            ));
+        }
+
+        let mut ids = HashSet::new();
+        for button in self.buttons.iter() {
+            check_text("payload.buttons.id", &button.id, MAX_ID_LEN)?;
+            check_text("payload.buttons.label", &button.label, MAX_BUTTON_LABEL_LEN)?;
+
+            if !ids.insert(button.id.as_str()) {
+                return Err(ValidationError::new("payload.buttons.id", "must be unique"));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl ButtonClickPayload {
+    fn validate(&self) -> Result<()> {
+        check_text("payload.button_id", &self.button_id, MAX_ID_LEN)?;
+        check_text("payload.label", &self.label, MAX_BUTTON_LABEL_LEN)
+    }
+}
+
+impl Payload {
+    /// the message type this payload belongs to.
+    pub fn msg_type(&self) -> MessageType {
+        match self {
+            Payload::Image(_) => MessageType::Image,
+            Payload::File(_) => MessageType::File,
+            Payload::Card(_) => MessageType::Card,
+            Payload::QuickReply(_) => MessageType::QuickReply,
+            Payload::ButtonClick(_) => MessageType::ButtonClick,
+            Payload::Recall(_) => MessageType::Recall,
+            Payload::Edit(_) => MessageType::Edit,
+            Payload::Hello(_) => MessageType::Hello,
+            Payload::FetchHistory(_) => MessageType::FetchHistory,
+            Payload::History(_) => MessageType::History,
+            Payload::Inbox(_) => MessageType::Inbox,
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            Payload::Image(image) => image.validate(),
+            Payload::File(file) => file.validate(),
+            Payload::Card(card) => card.validate(),
+            Payload::QuickReply(quick_reply) => quick_reply.validate(),
+            Payload::ButtonClick(click) => click.validate(),
+            Payload::FetchHistory(query) => query.validate(),
+            Payload::Recall(_) | Payload::Edit(_) | Payload::Hello(_) | Payload::History(_) | Payload::Inbox(_) => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload(raw: &str) -> Payload {
+        serde_json::from_str(raw).unwrap()
+    }
+
+    fn field(raw: &str) -> Option<String> {
+        payload(raw).validate().err().map(|err| err.field().to_string())
+    }
+
+    #[test]
+    fn urls_are_http_or_attachment_paths() {
+        for url in ["https://cdn.example.com/a.png", "http://example.com", "/attachments/abc?expires=1&sig=2"] {
+            assert_eq!(check_url("url", url), Ok(()), "{}", url);
+        }
+
+        let long = format!("https://example.com/{}", "a".repeat(MAX_URL_LEN));
+        for url in [
+            "",
+            "a.png",
+            "/",
+            "/admin/rooms",
+            "//evil.example.com/a.png",
+            "/attachments/",
+            "/attachments//x",
+            "https://",
+            "https:///a",
+            "javascript:alert(1)",
+            "https://a b",
+            &long,
+        ] {
+            assert!(check_url("url", url).is_err(), "{}", url);
+        }
+    }
+
+    #[test]
+    fn images_and_files() {
+        assert_eq!(field(r#"{"Image": {"url": "/attachments/1", "mime": "image/png", "size": 1}}"#), None);
+        assert_eq!(
+            field(r#"{"Image": {"url": "/attachments/1", "mime": "text/plain", "size": 1}}"#).as_deref(),
+            Some("payload.mime")
+        );
+        assert_eq!(
+            field(r#"{"Image": {"url": "/attachments/1", "mime": "image/png", "size": 0}}"#).as_deref(),
+            Some("payload.size")
+        );
+        assert_eq!(
+            field(r#"{"Image": {"url": "/attachments/1", "mime": "image/png", "size": 1, "thumbnail": "t.png"}}"#).as_deref(),
+            Some("payload.thumbnail")
+        );
+
+        assert_eq!(
+            field(r#"{"File": {"url": "/attachments/1", "name": "a.pdf", "mime": "application/pdf", "size": 1}}"#),
+            None
+        );
+        assert_eq!(
+            field(r#"{"File": {"url": "/attachments/1", "name": " ", "mime": "application/pdf", "size": 1}}"#).as_deref(),
+            Some("payload.name")
+        );
+        let name = "a".repeat(MAX_FILE_NAME_LEN + 1);
+        let raw = format!(
+            r#"{{"File": {{"url": "/attachments/1", "name": "{}", "mime": "application/pdf", "size": 1}}}}"#,
+            name
+        );
+        assert_eq!(field(&raw).as_deref(), Some("payload.name"));
+    }
+
+    #[test]
+    fn card_fields_are_limited() {
+        assert_eq!(
+            field(r#"{"Card": {"kind": "Order", "id": "o1", "title": "t", "subtitle": "s", "price": "1", "link": "https://example.com/o1"}}"#),
+            None
+        );
+        assert_eq!(
+            field(r#"{"Card": {"kind": "Order", "id": "", "title": "t"}}"#).as_deref(),
+            Some("payload.id")
+        );
+        assert_eq!(
+            field(r#"{"Card": {"kind": "Order", "id": "o1", "title": ""}}"#).as_deref(),
+            Some("payload.title")
+        );
+        assert_eq!(
+            field(r#"{"Card": {"kind": "Order", "id": "o1", "title": "t", "link": "/admin"}}"#).as_deref(),
+            Some("payload.link")
+        );
+
+        let long = "你".repeat(MAX_CARD_TEXT_LEN + 1);
+        let raw = format!(r#"{{"Card": {{"kind": "Product", "id": "p1", "title": "{}"}}}}"#, long);
+        assert_eq!(field(&raw).as_deref(), Some("payload.title"));
+        let raw = format!(r#"{{"Card": {{"kind": "Product", "id": "p1", "title": "t", "subtitle": "{}"}}}}"#, long);
+        assert_eq!(field(&raw).as_deref(), Some("payload.subtitle"));
+        let raw = format!(r#"{{"Card": {{"kind": "Product", "id": "p1", "title": "t", "price": "{}"}}}}"#, long);
+        assert_eq!(field(&raw).as_deref(), Some("payload.price"));
+        let raw = format!(
+            r#"{{"Card": {{"kind": "Product", "id": "{}", "title": "t"}}}}"#,
+            "p".repeat(MAX_ID_LEN + 1)
+        );
+        assert_eq!(field(&raw).as_deref(), Some("payload.id"));
+    }
+
+    #[test]
+    fn quick_replies_and_clicks() {
+        assert_eq!(
+            field(r#"{"QuickReply": {"buttons": [{"id": "b1", "label": "yes"}, {"id": "b2", "label": "no"}]}}"#),
+            None
+        );
+        assert_eq!(field(r#"{"QuickReply": {"buttons": []}}"#).as_deref(), Some("payload.buttons"));
+        assert_eq!(
+            field(r#"{"QuickReply": {"buttons": [{"id": "b1", "label": "yes"}, {"id": "b1", "label": "no"}]}}"#).as_deref(),
+            Some("payload.buttons.id")
+        );
+        let raw = format!(
+            r#"{{"QuickReply": {{"buttons": [{{"id": "b1", "label": "{}"}}]}}}}"#,
+            "a".repeat(MAX_BUTTON_LABEL_LEN + 1)
+        );
+        assert_eq!(field(&raw).as_deref(), Some("payload.buttons.label"));
+
+        assert_eq!(field(r#"{"ButtonClick": {"button_id": "b1", "label": "yes"}}"#), None);
+        assert_eq!(
+            field(r#"{"ButtonClick": {"button_id": "", "label": "yes"}}"#).as_deref(),
+            Some("payload.button_id")
+        );
+        assert_eq!(
+            field(r#"{"ButtonClick": {"button_id": "b1", "label": " "}}"#).as_deref(),
+            Some("payload.label")
+        );
+        let raw = format!(
+            r#"{{"ButtonClick": {{"button_id": "b1", "label": "{}"}}}}"#,
+            "a".repeat(MAX_BUTTON_LABEL_LEN + 1)
+        );
+        assert_eq!(field(&raw).as_deref(), Some("payload.label"));
+    }
+
+    #[test]
+    fn history_limit() {
+        assert_eq!(field(r#"{"FetchHistory": {"limit": 1}}"#), None);
+        assert_eq!(field(r#"{"FetchHistory": {"limit": 0}}"#).as_deref(), Some("payload.limit"));
+        assert_eq!(field(r#"{"FetchHistory": {"limit": 101}}"#).as_deref(), Some("payload.limit"));
+    }
+}
@@ -2,27 +2,226 @@ use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use tungstenite::Message;
 
-use crate::auth::{Member, RoomId};
+use crate::{auth::RoomId, clock};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+use super::{
+    codec::{Codec, JsonCodec},
+    payload::{HistoryPayload, HistoryQuery, InboxPayload, MessageRef, Payload, ValidationError},
+    version::HelloPayload,
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum MessageType {
     Tips,
     Chat,
+    Image,
+    File,
+    Card,
+    QuickReply,
+    ButtonClick,
+    Recall,
+    Edit,
+    Ack,
+    Hello,
+    FetchHistory,
+    History,
+    /// messages that arrived while the member was offline.
+    Inbox,
+    Error,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+impl MessageType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MessageType::Tips => "Tips",
+            MessageType::Chat => "Chat",
+            MessageType::Image => "Image",
+            MessageType::File => "File",
+            MessageType::Card => "Card",
+            MessageType::QuickReply => "QuickReply",
+            MessageType::ButtonClick => "ButtonClick",
+            MessageType::Recall => "Recall",
+            MessageType::Edit => "Edit",
+            MessageType::Ack => "Ack",
+            MessageType::Hello => "Hello",
+            MessageType::FetchHistory => "FetchHistory",
+            MessageType::History => "History",
+            MessageType::Inbox => "Inbox",
+            MessageType::Error => "Error",
+        }
+    }
+
+    /// types only the server may send. clients sending these are rejected.
+    pub fn is_server_only(&self) -> bool {
+        matches!(
+            self,
+            MessageType::Tips | MessageType::Ack | MessageType::Hello | MessageType::History | MessageType::Inbox | MessageType::Error
+        )
+    }
+
+    /// commands that change an earlier message instead of adding a new one.
+    pub fn is_amendment(&self) -> bool {
+        matches!(self, MessageType::Recall | MessageType::Edit)
+    }
+
+    /// types that carry a typed payload instead of a plain body.
+    pub fn has_payload(&self) -> bool {
+        matches!(
+            self,
+            MessageType::Image
+                | MessageType::File
+                | MessageType::Card
+                | MessageType::QuickReply
+                | MessageType::ButtonClick
+                | MessageType::Recall
+                | MessageType::Edit
+                | MessageType::Hello
+                | MessageType::FetchHistory
+                | MessageType::History
+                | MessageType::Inbox
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct ClientProtocol {
+    #[serde(default)]
     body: String,
     msg_type: MessageType,
     room_id: RoomId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    payload: Option<Box<Payload>>,
+    /// sequence assigned by the room. identifies the message for recall and edit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    seq: Option<u64>,
+    /// member id of the sender, set by the room.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sender: Option<String>,
+    /// unix millis the room accepted the message.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sent_at: Option<u64>,
+    /// client generated id, echoed back in the ack.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    client_id: Option<String>,
+}
+
+const MAX_CLIENT_ID_LEN: usize = 64;
+
+fn is_stripped(c: char) -> bool {
+    c.is_control() && c != '\n' && c != '\t'
 }
 
 impl ClientProtocol {
-    pub fn new_tips(body: String, room_id: RoomId) -> Self {
+    fn new(msg_type: MessageType, body: String, room_id: RoomId) -> Self {
         ClientProtocol {
             body,
-            msg_type: MessageType::Tips,
+            msg_type,
             room_id,
+            payload: None,
+            seq: None,
+            sender: None,
+            sent_at: None,
+            client_id: None,
+        }
+    }
+
+    pub fn new_tips(body: String, room_id: RoomId) -> Self {
+        Self::new(MessageType::Tips, body, room_id)
+    }
+
+    pub fn new_error(body: String, room_id: RoomId) -> Self {
+        Self::new(MessageType::Error, body, room_id)
+    }
+
+    /// acknowledge a message to its sender with the sequence the room assigned.
+    pub fn new_ack(room_id: RoomId, seq: u64, client_id: Option<String>) -> Self {
+        let mut ack = Self::new(MessageType::Ack, String::new(), room_id);
+        ack.seq = Some(seq);
+        ack.client_id = client_id;
+        ack
+    }
+
+    /// first frame sent to a client after the handshake.
+    pub fn new_hello(hello: HelloPayload) -> Self {
+        let mut frame = Self::new(MessageType::Hello, String::new(), RoomId::new());
+        frame.payload = Some(Box::new(Payload::Hello(hello)));
+        frame
+    }
+
+    /// page of history sent to one member.
+    pub fn new_history(room_id: RoomId, history: HistoryPayload) -> Self {
+        let mut frame = Self::new(MessageType::History, String::new(), room_id);
+        frame.payload = Some(Box::new(Payload::History(history)));
+        frame
+    }
+
+    /// messages a member missed in room, sent when it comes back.
+    pub fn new_inbox(room_id: RoomId, inbox: InboxPayload) -> Self {
+        let mut frame = Self::new(MessageType::Inbox, String::new(), room_id);
+        frame.payload = Some(Box::new(Payload::Inbox(inbox)));
+        frame
+    }
+
+    /// recall or edit event broadcast to the room.
+    pub fn new_amendment(msg_type: MessageType, body: String, room_id: RoomId, target: MessageRef, sender: String) -> Self {
+        let mut event = Self::new(msg_type, body, room_id);
+        event.payload = match msg_type {
+            MessageType::Edit => Some(Box::new(Payload::Edit(target))),
+            _ => Some(Box::new(Payload::Recall(target))),
+        };
+        event.sender = Some(sender);
+        event.sent_at = Some(clock::now_millis());
+        event
+    }
+
+    /// set the server side fields. anything the client put there is overwritten.
+    pub fn stamp(&mut self, seq: u64, sender: String, sent_at: u64) {
+        self.seq = Some(seq);
+        self.sender = Some(sender);
+        self.sent_at = Some(sent_at);
+    }
+
+    pub fn set_body(&mut self, body: String) {
+        self.body = body;
+    }
+
+    pub fn set_room_id(&mut self, room_id: RoomId) {
+        self.room_id = room_id;
+    }
+
+    /// turn a stored message into the placeholder of a recalled one.
+    /// seq, sender and sent_at are kept, the content is dropped.
+    pub fn into_recalled(self) -> Self {
+        let target = MessageRef {
+            seq: self.seq.unwrap_or_default(),
+        };
+
+        ClientProtocol {
+            body: String::new(),
+            msg_type: MessageType::Recall,
+            payload: Some(Box::new(Payload::Recall(target))),
+            client_id: None,
+            ..self
+        }
+    }
+
+    /// turn a stored message into an anonymized one. type, seq and sent_at are kept,
+    /// the content and sender are dropped.
+    pub fn into_redacted(self) -> Self {
+        ClientProtocol {
+            body: String::new(),
+            payload: None,
+            sender: None,
+            client_id: None,
+            ..self
+        }
+    }
+
+    /// the page a history request asks for.
+    pub fn history_query(&self) -> Option<HistoryQuery> {
+        match self.payload() {
+            Some(Payload::FetchHistory(query)) => Some(*query),
+            _ => None,
         }
     }
 
@@ -38,22 +237,240 @@ impl ClientProtocol {
         &self.msg_type
     }
 
+    pub fn payload(&self) -> Option<&Payload> {
+        self.payload.as_deref()
+    }
+
+    pub fn seq(&self) -> Option<u64> {
+        self.seq
+    }
+
+    pub fn sender(&self) -> Option<&str> {
+        self.sender.as_deref()
+    }
+
+    pub fn sent_at(&self) -> Option<u64> {
+        self.sent_at
+    }
+
+    pub fn client_id(&self) -> Option<&str> {
+        self.client_id.as_deref()
+    }
+
+    /// the message a recall or edit refers to.
+    pub fn target(&self) -> Option<MessageRef> {
+        match self.payload() {
+            Some(Payload::Recall(target)) | Some(Payload::Edit(target)) => Some(*target),
+            _ => None,
+        }
+    }
+
+    /// the free text a client wrote and people read: the body, and the text fields of the payload.
+    /// ids, urls and mime types are left out.
+    pub fn texts(&self) -> Vec<&String> {
+        let mut texts = vec![&self.body];
+
+        match self.payload.as_deref() {
+            Some(Payload::File(file)) => texts.push(&file.name),
+            Some(Payload::Card(card)) => {
+                texts.push(&card.title);
+                texts.extend([&card.subtitle, &card.price, &card.link].into_iter().flatten());
+            }
+            Some(Payload::QuickReply(quick_reply)) => texts.extend(quick_reply.buttons.iter().map(|button| &button.label)),
+            Some(Payload::ButtonClick(click)) => texts.push(&click.label),
+            _ => {}
+        }
+
+        texts
+    }
+
+    /// texts, to be changed in place.
+    pub fn texts_mut(&mut self) -> Vec<&mut String> {
+        let mut texts = vec![&mut self.body];
+
+        match self.payload.as_deref_mut() {
+            Some(Payload::File(file)) => texts.push(&mut file.name),
+            Some(Payload::Card(card)) => {
+                texts.push(&mut card.title);
+                texts.extend([&mut card.subtitle, &mut card.price, &mut card.link].into_iter().flatten());
+            }
+            Some(Payload::QuickReply(quick_reply)) => texts.extend(quick_reply.buttons.iter_mut().map(|button| &mut button.label)),
+            Some(Payload::ButtonClick(click)) => texts.push(&mut click.label),
+            _ => {}
+        }
+
+        texts
+    }
+
+    /// drop control characters from the texts, other than line breaks and tabs.
+    pub fn strip_control_chars(&mut self) {
+        for text in self.texts_mut() {
+            if text.chars().any(is_stripped) {
+                text.retain(|c| !is_stripped(c));
+            }
+        }
+    }
+
+    /// check the body and every other text is at most max characters.
+    pub fn check_body_len(&self, max: usize) -> std::result::Result<(), ValidationError> {
+        if self.body.chars().count() > max {
+            return Err(ValidationError::new("body", format!("must be at most {} characters", max)));
+        }
+
+        if self.texts().iter().any(|text| text.chars().count() > max) {
+            return Err(ValidationError::new("payload", format!("texts must be at most {} characters", max)));
+        }
+
+        Ok(())
+    }
+
+    /// check a message received from a client against the schema of its type.
+    pub fn validate(&self) -> std::result::Result<(), ValidationError> {
+        if self.msg_type.is_server_only() {
+            return Err(ValidationError::new("msg_type", "is reserved for the server"));
+        }
+
+        if self.room_id.is_empty() {
+            return Err(ValidationError::new("room_id", "must not be empty"));
+        }
+
+        if self.client_id.as_ref().is_some_and(|id| id.len() > MAX_CLIENT_ID_LEN) {
+            return Err(ValidationError::new("client_id", format!("must be at most {} bytes", MAX_CLIENT_ID_LEN)));
+        }
+
+        if self.msg_type == MessageType::Edit && self.body.trim().is_empty() {
+            return Err(ValidationError::new("body", "must not be empty"));
+        }
+
+        if !self.msg_type.has_payload() {
+            if self.payload.is_some() {
+                return Err(ValidationError::new("payload", "is not allowed for text messages"));
+            }
+
+            if self.body.trim().is_empty() {
+                return Err(ValidationError::new("body", "must not be empty"));
+            }
+
+            return Ok(());
+        }
+
+        let payload = match &self.payload {
+            Some(payload) => payload,
+            None => return Err(ValidationError::new("payload", "is required")),
+        };
+
+        if payload.msg_type() != self.msg_type {
+            return Err(ValidationError::new("payload", "does not match msg_type"));
+        }
+
+        payload.validate()
+    }
+
     pub fn to_json(&self) -> Result<String> {
         Ok(serde_json::to_string(self)?)
     }
 
+    /// encode as a json text frame.
     pub fn to_message(&self) -> Message {
-        // this is a bug, if you use this function, you should check the return value.
-        Message::Text(self.to_json().unwrap())
+        // serializing plain structs to json does not fail.
+        JsonCodec.encode(self).unwrap()
     }
 }
 
-pub fn join(id: Member, room_id: RoomId) -> ClientProtocol {
-    let msg = format!("{} 加入了聊天", id.id());
-    ClientProtocol::new_tips(msg, room_id)
+/// error frame sent back to the client that produced an invalid message.
+pub fn error(reason: impl ToString, room_id: RoomId) -> ClientProtocol {
+    ClientProtocol::new_error(reason.to_string(), room_id)
 }
 
-pub fn self_join(room_id: RoomId) -> ClientProtocol {
-    let msg = format!("你加入了聊天");
-    ClientProtocol::new_tips(msg, room_id)
+#[cfg(test)]
+mod tests {
+    use crate::message::payload::ButtonClickPayload;
+
+    use super::*;
+
+    fn chat(body: &str) -> ClientProtocol {
+        ClientProtocol::new(MessageType::Chat, body.to_string(), "c1-a1".to_string())
+    }
+
+    #[test]
+    fn strips_control_chars_but_line_breaks_and_tabs() {
+        let mut msg = chat("a\u{0}b\u{7}c\r\nd\te\u{1b}[31mf\u{7f}g\u{85}h");
+        msg.strip_control_chars();
+        assert_eq!(msg.body(), "abc\nd\te[31mfgh");
+
+        let mut msg = chat("line one\nline two\tend, 你好");
+        msg.strip_control_chars();
+        assert_eq!(msg.body(), "line one\nline two\tend, 你好");
+    }
+
+    #[test]
+    fn body_len_counts_characters() {
+        assert!(chat("").check_body_len(0).is_ok());
+        assert!(chat("abcd").check_body_len(4).is_ok());
+        assert!(chat("abcde").check_body_len(4).is_err());
+
+        // 4 characters, 12 bytes.
+        assert!(chat("你好世界").check_body_len(4).is_ok());
+        assert!(chat("你好世界!").check_body_len(4).is_err());
+    }
+
+    #[test]
+    fn texts_cover_every_user_supplied_field() {
+        let mut msg: ClientProtocol = serde_json::from_str(
+            r#"{"msg_type": "Card", "body": "b", "room_id": "c1-a1", "payload": {"Card": {"kind": "Order", "id": "o1", "title": "t", "subtitle": "s", "link": "l"}}}"#,
+        )
+        .unwrap();
+        assert_eq!(msg.texts(), ["b", "t", "s", "l"]);
+        assert_eq!(msg.texts_mut().len(), 4);
+
+        let mut msg: ClientProtocol = serde_json::from_str(
+            r#"{"msg_type": "ButtonClick", "room_id": "c1-a1", "payload": {"ButtonClick": {"button_id": "b1", "label": "yes"}}}"#,
+        )
+        .unwrap();
+        for text in msg.texts_mut() {
+            text.make_ascii_uppercase();
+        }
+        assert_eq!(
+            msg.payload(),
+            Some(&Payload::ButtonClick(ButtonClickPayload {
+                button_id: "b1".to_string(),
+                label: "YES".to_string()
+            }))
+        );
+    }
+
+    fn parse(raw: &str) -> ClientProtocol {
+        serde_json::from_str(raw).unwrap()
+    }
+
+    #[test]
+    fn strips_control_chars_from_payload_texts() {
+        let mut msg = parse(
+            r#"{"msg_type": "QuickReply", "body": "pick\u0007", "room_id": "c1-a1", "payload": {"QuickReply": {"buttons": [{"id": "b1", "label": "y\u0000es"}, {"id": "b2", "label": "n\u001bo"}]}}}"#,
+        );
+        msg.strip_control_chars();
+        assert_eq!(msg.texts(), ["pick", "yes", "no"]);
+
+        let mut msg = parse(
+            r#"{"msg_type": "File", "room_id": "c1-a1", "payload": {"File": {"url": "/attachments/1", "name": "a\rb\n.txt", "size": 1, "mime": "text/plain"}}}"#,
+        );
+        msg.strip_control_chars();
+        assert_eq!(msg.texts(), ["", "ab\n.txt"]);
+    }
+
+    #[test]
+    fn payload_texts_are_limited_like_the_body() {
+        let card = |title: &str| {
+            parse(&format!(
+                r#"{{"msg_type": "Card", "room_id": "c1-a1", "payload": {{"Card": {{"kind": "Product", "id": "p1", "title": "{}"}}}}}}"#,
+                title
+            ))
+        };
+        assert!(card("你好世界").check_body_len(4).is_ok());
+        let err = card("你好世界!").check_body_len(4).unwrap_err();
+        assert_eq!(err, ValidationError::new("payload", "texts must be at most 4 characters"));
+
+        let click = parse(r#"{"msg_type": "ButtonClick", "room_id": "c1-a1", "payload": {"ButtonClick": {"button_id": "b1", "label": "abcde"}}}"#);
+        assert!(click.check_body_len(4).is_err());
+    }
 }
@@ -0,0 +1,225 @@
+use serde::Serialize;
+
+use crate::{auth::RoomId, i18n::I18n};
+
+use super::{
+    codec::CodecKind,
+    payload::{CardKind, Payload},
+    protocol::{ClientProtocol, MessageType},
+};
+
+/// protocol versions the server speaks, negotiated with `Sec-WebSocket-Protocol`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ProtocolVersion {
+    /// plain `{body, msg_type, room_id}` frames with Tips and Chat only.
+    /// clients that offer no subprotocol get this one.
+    V1,
+    /// typed payloads, sequences, acks, recall and edit.
+    V2,
+}
+
+pub const SUPPORTED_VERSIONS: &[ProtocolVersion] = &[ProtocolVersion::V1, ProtocolVersion::V2];
+
+impl ProtocolVersion {
+    pub fn number(&self) -> u32 {
+        match self {
+            ProtocolVersion::V1 => 1,
+            ProtocolVersion::V2 => 2,
+        }
+    }
+
+    /// name used in `Sec-WebSocket-Protocol`.
+    pub fn subprotocol(&self) -> &'static str {
+        match self {
+            ProtocolVersion::V1 => "im.v1",
+            ProtocolVersion::V2 => "im.v2",
+        }
+    }
+
+    /// codecs clients of this version may negotiate. v1 is json only.
+    pub fn codecs(&self) -> &'static [CodecKind] {
+        match self {
+            ProtocolVersion::V1 => &[CodecKind::Json],
+            ProtocolVersion::V2 => &[CodecKind::Json, CodecKind::MessagePack, CodecKind::Cbor],
+        }
+    }
+
+    /// features announced in the server hello.
+    pub fn features(&self) -> Vec<String> {
+        let features: &[&str] = match self {
+            ProtocolVersion::V1 => &[],
+            ProtocolVersion::V2 => &["rich_payload", "attachments", "ack", "recall", "edit", "history", "inbox"],
+        };
+
+        let codecs = self
+            .codecs()
+            .iter()
+            .filter_map(|codec| codec.suffix())
+            .map(|suffix| format!("codec.{}", suffix));
+
+        features.iter().map(|feature| feature.to_string()).chain(codecs).collect()
+    }
+
+    /// whether clients of this version receive a hello frame after connecting.
+    pub fn sends_hello(&self) -> bool {
+        *self >= ProtocolVersion::V2
+    }
+}
+
+/// a protocol version with the codec its frames are encoded in,
+/// named `im.v<N>` for json and `im.v<N>.<codec>` otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Subprotocol {
+    pub version: ProtocolVersion,
+    pub codec: CodecKind,
+}
+
+impl Subprotocol {
+    pub fn name(&self) -> String {
+        match self.codec.suffix() {
+            Some(suffix) => format!("{}.{}", self.version.subprotocol(), suffix),
+            None => self.version.subprotocol().to_string(),
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        SUPPORTED_VERSIONS
+            .iter()
+            .flat_map(|version| {
+                version.codecs().iter().map(|codec| Subprotocol {
+                    version: *version,
+                    codec: *codec,
+                })
+            })
+            .find(|subprotocol| subprotocol.name() == name)
+    }
+}
+
+/// result of subprotocol negotiation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Negotiation {
+    /// the client offered no subprotocol, speak v1 json and answer without one.
+    Legacy,
+    /// the newest version both sides support, echoed back in the response.
+    Selected(Subprotocol),
+    /// the client offered only unknown protocols.
+    Unsupported,
+}
+
+/// pick the newest supported version from a `Sec-WebSocket-Protocol` header value.
+/// among codecs of the same version the client's order of preference wins.
+pub fn negotiate(header: Option<&str>) -> Negotiation {
+    let header = match header {
+        Some(header) => header,
+        None => return Negotiation::Legacy,
+    };
+
+    let mut selected: Option<Subprotocol> = None;
+    for offered in header.split(',').filter_map(|name| Subprotocol::parse(name.trim())) {
+        if selected.is_none_or(|current| offered.version > current.version) {
+            selected = Some(offered);
+        }
+    }
+
+    selected.map_or(Negotiation::Unsupported, Negotiation::Selected)
+}
+
+/// content of the hello frame.
+#[derive(Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct HelloPayload {
+    pub version: u32,
+    pub features: Vec<String>,
+}
+
+pub fn hello(version: ProtocolVersion) -> ClientProtocol {
+    ClientProtocol::new_hello(HelloPayload {
+        version: version.number(),
+        features: version.features(),
+    })
+}
+
+/// frame shape understood by v1 clients.
+#[derive(Serialize, Debug)]
+pub struct V1Frame {
+    body: String,
+    msg_type: MessageType,
+    room_id: RoomId,
+}
+
+impl V1Frame {
+    fn chat(body: String, room_id: RoomId) -> Self {
+        V1Frame {
+            body,
+            msg_type: MessageType::Chat,
+            room_id,
+        }
+    }
+
+    fn tips(body: String, room_id: RoomId) -> Self {
+        V1Frame {
+            body,
+            msg_type: MessageType::Tips,
+            room_id,
+        }
+    }
+}
+
+/// render a frame for a v1 client. rich payloads fall back to text in the client's locale,
+/// frames v1 has no equivalent for are dropped.
+pub fn downgrade(content: &ClientProtocol, i18n: &I18n, locale: &str) -> Option<V1Frame> {
+    let room_id = content.room_id().clone();
+    let body = content.body().to_string();
+
+    let frame = match content.msg_type() {
+        MessageType::Chat => V1Frame::chat(body, room_id),
+        MessageType::Tips | MessageType::Error => V1Frame::tips(body, room_id),
+        MessageType::Recall => V1Frame::tips(i18n.text(locale, "tips.message_recalled", &[]), room_id),
+        MessageType::Edit => V1Frame::tips(i18n.text(locale, "tips.message_edited", &[("body", &body)]), room_id),
+        MessageType::Inbox => {
+            let unread = match content.payload() {
+                Some(Payload::Inbox(inbox)) => inbox.unread,
+                _ => 0,
+            };
+            V1Frame::tips(i18n.text(locale, "tips.unread", &[("count", &unread.to_string())]), room_id)
+        }
+        MessageType::Ack | MessageType::Hello | MessageType::FetchHistory | MessageType::History => return None,
+        _ => V1Frame::chat(payload_text(content.payload()?, i18n, locale), room_id),
+    };
+
+    Some(frame)
+}
+
+/// text standing in for a rich payload.
+pub fn payload_text(payload: &Payload, i18n: &I18n, locale: &str) -> String {
+    match payload {
+        Payload::Image(image) => i18n.text(locale, "fallback.image", &[("url", &image.url)]),
+        Payload::File(file) => i18n.text(locale, "fallback.file", &[("name", &file.name), ("url", &file.url)]),
+        Payload::Card(card) => {
+            let key = match card.kind {
+                CardKind::Product => "fallback.product_card",
+                CardKind::Order => "fallback.order_card",
+            };
+            let link = card.link.as_deref().unwrap_or_default();
+            i18n.text(locale, key, &[("id", &card.id), ("title", &card.title), ("link", link)])
+        }
+        Payload::QuickReply(quick_reply) => quick_reply
+            .buttons
+            .iter()
+            .enumerate()
+            .map(|(i, button)| format!("{}. {}", i + 1, button.label))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Payload::ButtonClick(click) => click.label.clone(),
+        Payload::Recall(_) | Payload::Edit(_) | Payload::Hello(_) | Payload::FetchHistory(_) | Payload::History(_) | Payload::Inbox(_) => {
+            String::new()
+        }
+    }
+}
+
+/// v1 clients may only send text chat.
+pub fn accepts(version: ProtocolVersion, content: &ClientProtocol) -> bool {
+    match version {
+        ProtocolVersion::V1 => content.msg_type() == &MessageType::Chat,
+        ProtocolVersion::V2 => true,
+    }
+}
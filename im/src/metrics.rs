@@ -0,0 +1,122 @@
+use std::sync::LazyLock;
+
+use prometheus::{Encoder, Histogram, HistogramOpts, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder};
+
+use crate::auth::UserType;
+
+/// the only waiting queue so far. the label leaves room for queues per skill group.
+pub const DEFAULT_QUEUE: &str = "default";
+
+/// mailbox label of each actor kind.
+pub const MANAGER: &str = "manager";
+pub const ROOM: &str = "room";
+pub const CONN: &str = "conn";
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(|| Metrics::new().expect("metrics are valid"));
+
+/// process wide metrics, exposed on `/metrics` in the prometheus text format.
+pub struct Metrics {
+    registry: Registry,
+
+    /// open conns by user type.
+    pub connected: IntGaugeVec,
+
+    pub open_rooms: IntGauge,
+
+    /// customers waiting for a customer service, by queue.
+    pub waiting: IntGaugeVec,
+
+    /// messages sent to an actor kind and not yet received, summed over its mailboxes.
+    pub mailbox_depth: IntGaugeVec,
+
+    /// from a customer connecting to being put in a room.
+    pub first_assignment: Histogram,
+
+    /// from a message reaching its room to being handed to every member's conn.
+    pub fanout: Histogram,
+
+    /// by the reason the client was given.
+    pub handshake_failures: IntCounterVec,
+
+    /// client messages over the rate limits, by penalty.
+    pub rate_limited: IntCounterVec,
+
+    /// client messages moderation masked, flagged or blocked, by action.
+    pub moderated: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> prometheus::Result<Self> {
+        let registry = Registry::new_custom(Some("im".to_string()), None)?;
+
+        let connected = IntGaugeVec::new(Opts::new("connected_members", "open websocket conns by user type"), &["user_type"])?;
+        let open_rooms = IntGauge::new("open_rooms", "rooms with a running actor")?;
+        let waiting = IntGaugeVec::new(Opts::new("waiting_queue_length", "customers waiting for a customer service"), &["queue"])?;
+        let mailbox_depth = IntGaugeVec::new(
+            Opts::new("mailbox_depth", "messages queued in the mailboxes of an actor kind"),
+            &["actor"],
+        )?;
+        let first_assignment = Histogram::with_opts(
+            HistogramOpts::new("time_to_first_assignment_seconds", "from a customer connecting to being put in a room")
+                .buckets(vec![0.01, 0.1, 1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0]),
+        )?;
+        let fanout = Histogram::with_opts(
+            HistogramOpts::new("message_fanout_seconds", "from a message reaching its room to every member's conn")
+                .buckets(vec![0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0]),
+        )?;
+        let handshake_failures = IntCounterVec::new(Opts::new("handshake_failures_total", "rejected websocket handshakes"), &["reason"])?;
+
+        registry.register(Box::new(connected.clone()))?;
+        registry.register(Box::new(open_rooms.clone()))?;
+        registry.register(Box::new(waiting.clone()))?;
+        registry.register(Box::new(mailbox_depth.clone()))?;
+        registry.register(Box::new(first_assignment.clone()))?;
+        registry.register(Box::new(fanout.clone()))?;
+        let rate_limited = IntCounterVec::new(
+            Opts::new("rate_limited_messages_total", "client messages over the rate limits"),
+            &["penalty"],
+        )?;
+
+        let moderated = IntCounterVec::new(
+            Opts::new("moderated_messages_total", "client messages masked, flagged or blocked"),
+            &["action"],
+        )?;
+
+        registry.register(Box::new(handshake_failures.clone()))?;
+        registry.register(Box::new(rate_limited.clone()))?;
+        registry.register(Box::new(moderated.clone()))?;
+
+        Ok(Metrics {
+            registry,
+            connected,
+            open_rooms,
+            waiting,
+            mailbox_depth,
+            first_assignment,
+            fanout,
+            handshake_failures,
+            rate_limited,
+            moderated,
+        })
+    }
+
+    /// render every metric in the prometheus text format.
+    pub fn render(&self) -> prometheus::Result<String> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+
+        Ok(String::from_utf8_lossy(&buffer).into_owned())
+    }
+
+    pub fn connected(&self, user_type: UserType) -> IntGauge {
+        self.connected.with_label_values(&[user_type.as_str()])
+    }
+
+    pub fn mailbox(&self, actor: &str) -> IntGauge {
+        self.mailbox_depth.with_label_values(&[actor])
+    }
+}
+
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
@@ -0,0 +1,127 @@
+mod pii;
+mod rules;
+mod words;
+
+use std::ops::Range;
+
+use anyhow::Result;
+
+use crate::{
+    auth::Member,
+    config::{Action, ModerationConfig},
+};
+
+pub use pii::PiiFilter;
+pub use rules::RegexRule;
+pub use words::WordList;
+
+/// what a moderator decided about a body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    Pass,
+    /// deliver the message with this body instead.
+    Mask(String),
+    /// deliver the message and ask supervisors to review it, for this reason.
+    Flag(String),
+    /// drop the message, for this reason.
+    Block(String),
+}
+
+/// Moderator is a content rule. the builtin ones are configured in `[moderation]`,
+/// custom ones are added with Moderation::with.
+pub trait Moderator: Send + Sync {
+    fn moderate(&self, member: &Member, body: &str) -> Verdict;
+}
+
+/// what the pipeline decided about a body.
+#[derive(Debug, Default)]
+pub struct Outcome {
+    /// the masked body, if any moderator masked it.
+    pub body: Option<String>,
+    /// reasons to review the message.
+    pub flags: Vec<String>,
+    /// the reason the message is dropped.
+    pub blocked: Option<String>,
+}
+
+/// moderators run in order, each on the body as masked by the ones before.
+/// the first to block stops the pipeline.
+pub struct Moderation {
+    config: ModerationConfig,
+    moderators: Vec<Box<dyn Moderator>>,
+}
+
+impl Moderation {
+    pub fn new(config: &ModerationConfig) -> Result<Self> {
+        let mut moderators: Vec<Box<dyn Moderator>> = Vec::new();
+
+        if let Some(action) = config.pii {
+            moderators.push(Box::new(PiiFilter::new(action)));
+        }
+        for list in config.word_lists.iter() {
+            moderators.push(Box::new(WordList::new(list)?));
+        }
+        for rule in config.rules.iter() {
+            moderators.push(Box::new(RegexRule::new(rule)?));
+        }
+
+        Ok(Moderation {
+            config: config.clone(),
+            moderators,
+        })
+    }
+
+    /// add a custom moderator after the configured ones.
+    pub fn with(mut self, moderator: impl Moderator + 'static) -> Self {
+        self.moderators.push(Box::new(moderator));
+        self
+    }
+
+    /// whether the messages of member are moderated.
+    pub fn applies_to(&self, member: &Member) -> bool {
+        self.config.user_types.contains(&member.user_type())
+    }
+
+    pub fn moderate(&self, member: &Member, body: &str) -> Outcome {
+        let mut outcome = Outcome::default();
+
+        for moderator in self.moderators.iter() {
+            let current = outcome.body.as_deref().unwrap_or(body);
+
+            match moderator.moderate(member, current) {
+                Verdict::Pass => {}
+                Verdict::Mask(masked) => outcome.body = Some(masked),
+                Verdict::Flag(reason) => outcome.flags.push(reason),
+                Verdict::Block(reason) => {
+                    outcome.blocked = Some(reason);
+                    break;
+                }
+            }
+        }
+
+        outcome
+    }
+}
+
+/// the verdict of action on the matches of a filter named name.
+fn verdict(action: Action, name: &str, body: &str, matches: Vec<Range<usize>>) -> Verdict {
+    if matches.is_empty() {
+        return Verdict::Pass;
+    }
+
+    match action {
+        Action::Mask => Verdict::Mask(mask(body, &matches, |_| true)),
+        Action::Flag => Verdict::Flag(name.to_string()),
+        Action::Block => Verdict::Block(name.to_string()),
+    }
+}
+
+/// replace the characters of body in ranges for which hide holds with stars.
+fn mask(body: &str, ranges: &[Range<usize>], hide: impl Fn(char) -> bool) -> String {
+    body.char_indices()
+        .map(|(at, c)| match ranges.iter().any(|range| range.contains(&at)) && hide(c) {
+            true => '*',
+            false => c,
+        })
+        .collect()
+}
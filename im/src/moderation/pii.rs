@@ -0,0 +1,169 @@
+use std::{ops::Range, sync::LazyLock};
+
+use regex::Regex;
+
+use crate::{auth::Member, config::Action};
+
+use super::{mask, Moderator, Verdict};
+
+/// 13 to 19 digits, optionally grouped by spaces or dashes.
+static CARD: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\b\d(?:[ -]?\d){12,18}\b").expect("card pattern is valid"));
+
+/// digits grouped by spaces, dashes or parentheses, optionally after a +. is_phone tells which are phone numbers.
+static PHONE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?:\+|\(|\b)\d[\d ()\-]{6,}\d\b").expect("phone pattern is valid"));
+
+/// digits left visible at the end of a masked number.
+const VISIBLE_DIGITS: usize = 4;
+
+/// card numbers that pass the luhn check, and numbers written like phone numbers.
+/// masking keeps the last digits, so both sides can still tell which number was meant.
+pub struct PiiFilter {
+    action: Action,
+}
+
+impl PiiFilter {
+    pub fn new(action: Action) -> Self {
+        PiiFilter { action }
+    }
+}
+
+impl Moderator for PiiFilter {
+    fn moderate(&self, _member: &Member, body: &str) -> Verdict {
+        let cards: Vec<Range<usize>> = CARD
+            .find_iter(body)
+            .filter(|found| luhn(found.as_str()))
+            .map(|found| found.range())
+            .collect();
+        let phones: Vec<Range<usize>> = PHONE
+            .find_iter(body)
+            .filter(|found| is_phone(found.as_str()))
+            .map(|found| found.range())
+            .filter(|phone| !cards.iter().any(|card| card.start < phone.end && phone.start < card.end))
+            .collect();
+
+        let reason = match (cards.is_empty(), phones.is_empty()) {
+            (true, true) => return Verdict::Pass,
+            (false, true) => "card number",
+            (true, false) => "phone number",
+            (false, false) => "card and phone number",
+        };
+
+        match self.action {
+            Action::Mask => {
+                let ranges: Vec<Range<usize>> = cards.into_iter().chain(phones).map(|range| hidden_digits(body, range)).collect();
+                Verdict::Mask(mask(body, &ranges, |c| c.is_ascii_digit()))
+            }
+            Action::Flag => Verdict::Flag(reason.to_string()),
+            Action::Block => Verdict::Block(reason.to_string()),
+        }
+    }
+}
+
+/// range without its last visible digits.
+fn hidden_digits(body: &str, range: Range<usize>) -> Range<usize> {
+    let end = body[range.clone()]
+        .char_indices()
+        .filter(|(_, c)| c.is_ascii_digit())
+        .rev()
+        .nth(VISIBLE_DIGITS - 1)
+        .map_or(range.start, |(at, _)| range.start + at);
+
+    range.start..end
+}
+
+/// whether number is written like a phone number: 8 to 15 digits, and either international with a +,
+/// with an area code in parentheses, in three or more groups of at least 3 digits,
+/// or a bare mainland china mobile number. other digit runs, like order numbers, amounts or dates, are not.
+fn is_phone(number: &str) -> bool {
+    let digits = number.chars().filter(char::is_ascii_digit).count();
+    if !(8..=15).contains(&digits) {
+        return false;
+    }
+
+    if number.starts_with('+') || number.contains('(') {
+        return true;
+    }
+
+    if number.chars().all(|c| c.is_ascii_digit()) {
+        let bytes = number.as_bytes();
+        return digits == 11 && bytes[0] == b'1' && (b'3'..=b'9').contains(&bytes[1]);
+    }
+
+    let groups: Vec<&str> = number.split([' ', '-']).filter(|group| !group.is_empty()).collect();
+    groups.len() >= 3 && groups.iter().all(|group| group.len() >= 3)
+}
+
+fn luhn(number: &str) -> bool {
+    let digits = number.chars().filter_map(|c| c.to_digit(10));
+
+    let sum: u32 = digits
+        .rev()
+        .enumerate()
+        .map(|(i, digit)| match i % 2 {
+            0 => digit,
+            _ if digit * 2 > 9 => digit * 2 - 9,
+            _ => digit * 2,
+        })
+        .sum();
+
+    sum.is_multiple_of(10)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::UserType;
+
+    use super::*;
+
+    fn mask(body: &str) -> Verdict {
+        let member = Member::new(UserType::Customer, "c1".to_string(), "Carol".to_string());
+        PiiFilter::new(Action::Mask).moderate(&member, body)
+    }
+
+    fn masked(body: &str) -> String {
+        match mask(body) {
+            Verdict::Mask(masked) => masked,
+            other => panic!("{:?} was not masked: {:?}", body, other),
+        }
+    }
+
+    #[test]
+    fn masks_card_numbers_but_the_last_digits() {
+        assert_eq!(masked("card 4111 1111 1111 1111 ok"), "card **** **** **** 1111 ok");
+        assert_eq!(masked("4111-1111-1111-1111"), "****-****-****-1111");
+    }
+
+    #[test]
+    fn masks_phone_numbers() {
+        assert_eq!(masked("call +1 (415) 555-2671"), "call +* (***) ***-2671");
+        assert_eq!(masked("call (415) 555-2671"), "call (***) ***-2671");
+        assert_eq!(masked("call 415-555-2671 please"), "call ***-***-2671 please");
+        assert_eq!(masked("call +44 20 7946 0958"), "call +** ** **** 0958");
+        assert_eq!(masked("手机 13812345678"), "手机 *******5678");
+    }
+
+    #[test]
+    fn ignores_other_digit_runs() {
+        for body in [
+            // an order number that fails the luhn check.
+            "order 1234567890123456",
+            "order 20240115123456",
+            "tracking 12345678",
+            "tracking 9400 1000 0000 0000 0000 00",
+            "paid 1,234,567.89",
+            "on 2024-01-15",
+            "on 15-01-2024",
+            "ref 1234-5678",
+            "qty 12 34 56 78",
+        ] {
+            assert_eq!(mask(body), Verdict::Pass, "{:?}", body);
+        }
+    }
+
+    #[test]
+    fn luhn_check() {
+        assert!(luhn("4111111111111111"));
+        assert!(luhn("5500 0000 0000 0004"));
+        assert!(!luhn("4111111111111112"));
+    }
+}
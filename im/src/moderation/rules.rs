@@ -0,0 +1,103 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+
+use crate::{
+    auth::Member,
+    config::{Action, RuleConfig},
+};
+
+use super::{verdict, Moderator, Verdict};
+
+/// a regular expression matched against a body.
+pub struct RegexRule {
+    name: String,
+    action: Action,
+    regex: Regex,
+}
+
+impl RegexRule {
+    pub fn new(config: &RuleConfig) -> Result<Self> {
+        let regex = Regex::new(&config.pattern).with_context(|| format!("moderation rule {:?}: invalid pattern", config.name))?;
+
+        Ok(RegexRule {
+            name: config.name.clone(),
+            action: config.action,
+            regex,
+        })
+    }
+}
+
+impl Moderator for RegexRule {
+    fn moderate(&self, _member: &Member, body: &str) -> Verdict {
+        let matches = self.regex.find_iter(body).map(|found| found.range()).collect();
+
+        verdict(self.action, &self.name, body, matches)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::UserType;
+
+    use super::*;
+
+    fn rule(action: Action, pattern: &str) -> RegexRule {
+        RegexRule::new(&RuleConfig {
+            name: "links".to_string(),
+            action,
+            pattern: pattern.to_string(),
+        })
+        .unwrap()
+    }
+
+    fn moderate(rule: &RegexRule, body: &str) -> Verdict {
+        let member = Member::new(UserType::Customer, "c1".to_string(), "Carol".to_string());
+        rule.moderate(&member, body)
+    }
+
+    const LINKS: &str = r"https?://\S+";
+
+    #[test]
+    fn block_drops_and_flag_delivers() {
+        assert_eq!(
+            moderate(&rule(Action::Block, LINKS), "see http://evil.example"),
+            Verdict::Block("links".to_string())
+        );
+        assert_eq!(
+            moderate(&rule(Action::Flag, LINKS), "see http://evil.example"),
+            Verdict::Flag("links".to_string())
+        );
+        assert_eq!(moderate(&rule(Action::Block, LINKS), "see the faq"), Verdict::Pass);
+    }
+
+    #[test]
+    fn mask_hides_every_match_only() {
+        let rule = rule(Action::Mask, LINKS);
+
+        assert_eq!(
+            moderate(&rule, "a http://x.io b https://y.io"),
+            Verdict::Mask("a *********** b ************".to_string())
+        );
+    }
+
+    #[test]
+    fn patterns_are_regexes() {
+        // case is only ignored when the pattern asks for it.
+        let rule = rule(Action::Block, r"(?i)\border\s*#?\d{6}\b");
+
+        assert_eq!(moderate(&rule, "ORDER #123456"), Verdict::Block("links".to_string()));
+        assert_eq!(moderate(&rule, "order 1234567"), Verdict::Pass);
+    }
+
+    #[test]
+    fn invalid_patterns_are_errors() {
+        let err = RegexRule::new(&RuleConfig {
+            name: "broken".to_string(),
+            action: Action::Block,
+            pattern: "(".to_string(),
+        })
+        .err()
+        .unwrap();
+        assert!(format!("{:#}", err).contains("moderation rule \"broken\": invalid pattern"));
+    }
+}
@@ -0,0 +1,147 @@
+use std::fs;
+
+use aho_corasick::{AhoCorasick, MatchKind};
+use anyhow::{Context, Result};
+
+use crate::{
+    auth::Member,
+    config::{Action, WordListConfig},
+};
+
+use super::{verdict, Moderator, Verdict};
+
+/// words found anywhere in a body, ignoring ascii case.
+pub struct WordList {
+    name: String,
+    action: Action,
+    matcher: AhoCorasick,
+}
+
+impl WordList {
+    pub fn new(config: &WordListConfig) -> Result<Self> {
+        let mut words = config.words.clone();
+        if let Some(path) = &config.path {
+            let raw = fs::read_to_string(path).with_context(|| format!("read word list {}", path.display()))?;
+            words.extend(
+                raw.lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(str::to_string),
+            );
+        }
+        words.retain(|word| !word.trim().is_empty());
+
+        let matcher = AhoCorasick::builder()
+            .ascii_case_insensitive(true)
+            .match_kind(MatchKind::LeftmostLongest)
+            .build(&words)
+            .with_context(|| format!("build word list {:?}", config.name))?;
+
+        Ok(WordList {
+            name: config.name.clone(),
+            action: config.action,
+            matcher,
+        })
+    }
+}
+
+impl Moderator for WordList {
+    fn moderate(&self, _member: &Member, body: &str) -> Verdict {
+        let matches = self.matcher.find_iter(body).map(|found| found.range()).collect();
+
+        verdict(self.action, &self.name, body, matches)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::auth::UserType;
+
+    use super::*;
+
+    fn list(action: Action, words: &[&str]) -> WordList {
+        WordList::new(&WordListConfig {
+            name: "profanity".to_string(),
+            action,
+            words: words.iter().map(|word| word.to_string()).collect(),
+            path: None,
+        })
+        .unwrap()
+    }
+
+    fn moderate(list: &WordList, body: &str) -> Verdict {
+        let member = Member::new(UserType::Customer, "c1".to_string(), "Carol".to_string());
+        list.moderate(&member, body)
+    }
+
+    #[test]
+    fn masks_words_anywhere_ignoring_ascii_case() {
+        let list = list(Action::Mask, &["heck", "darn"]);
+
+        assert_eq!(
+            moderate(&list, "what the HeCk, darn it"),
+            Verdict::Mask("what the ****, **** it".to_string())
+        );
+        assert_eq!(moderate(&list, "checkout"), Verdict::Mask("c****out".to_string()));
+        assert_eq!(moderate(&list, "hello there"), Verdict::Pass);
+    }
+
+    #[test]
+    fn longest_overlapping_word_wins() {
+        let list = list(Action::Mask, &["bad", "badword", "坏"]);
+
+        assert_eq!(moderate(&list, "a badwords"), Verdict::Mask("a *******s".to_string()));
+        // stars replace characters, not bytes.
+        assert_eq!(moderate(&list, "很坏的"), Verdict::Mask("很*的".to_string()));
+    }
+
+    #[test]
+    fn flags_and_blocks_name_the_list() {
+        assert_eq!(
+            moderate(&list(Action::Flag, &["refund"]), "a REFUND please"),
+            Verdict::Flag("profanity".to_string())
+        );
+        assert_eq!(
+            moderate(&list(Action::Block, &["refund"]), "a refund please"),
+            Verdict::Block("profanity".to_string())
+        );
+        assert_eq!(moderate(&list(Action::Block, &["refund"]), "thanks"), Verdict::Pass);
+    }
+
+    #[test]
+    fn reads_more_words_from_a_file() {
+        let path = std::env::temp_dir().join(format!("im-words-{}.txt", uuid::Uuid::new_v4()));
+        fs::write(&path, "# one word per line\n\n  damn  \nheck\n").unwrap();
+
+        let list = WordList::new(&WordListConfig {
+            name: "profanity".to_string(),
+            action: Action::Block,
+            words: vec!["darn".to_string(), " ".to_string()],
+            path: Some(path.clone()),
+        })
+        .unwrap();
+        fs::remove_file(&path).unwrap();
+
+        for body in ["damn", "heck", "darn"] {
+            assert_eq!(moderate(&list, body), Verdict::Block("profanity".to_string()), "{}", body);
+        }
+        // neither blank words nor comments match everything.
+        assert_eq!(moderate(&list, "one word per line"), Verdict::Pass);
+        assert_eq!(moderate(&list, "a b"), Verdict::Pass);
+    }
+
+    #[test]
+    fn a_missing_file_is_an_error() {
+        let err = WordList::new(&WordListConfig {
+            name: "profanity".to_string(),
+            action: Action::Block,
+            words: Vec::new(),
+            path: Some(PathBuf::from("/nonexistent/words.txt")),
+        })
+        .err()
+        .unwrap();
+        assert!(format!("{:#}", err).contains("read word list /nonexistent/words.txt"));
+    }
+}
@@ -0,0 +1,67 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use serde::Serialize;
+use tracing::{warn, Instrument};
+
+use crate::auth::{Member, RoomId};
+
+/// webhook requests give up after this long.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// characters of the message included in a notification.
+const PREVIEW_CHARS: usize = 100;
+
+/// posted to the webhook when an offline member gets its first unread message in a room.
+#[derive(Serialize, Debug, Clone)]
+pub struct OfflineNotification {
+    pub member: Member,
+    pub room_id: RoomId,
+    pub unread: u32,
+    pub preview: String,
+    pub sent_at: u64,
+}
+
+impl OfflineNotification {
+    pub fn preview(body: &str) -> String {
+        body.chars().take(PREVIEW_CHARS).collect()
+    }
+}
+
+/// Webhook posts notifications as json, so members can be reached outside the chat.
+#[derive(Clone)]
+pub struct Webhook {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl std::fmt::Debug for Webhook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Webhook").field("url", &self.url).finish()
+    }
+}
+
+impl Webhook {
+    pub fn new(url: String) -> Result<Self> {
+        let client = reqwest::Client::builder().timeout(WEBHOOK_TIMEOUT).build()?;
+
+        Ok(Webhook { client, url })
+    }
+
+    /// post notification in the background. failures are logged, not retried.
+    pub fn notify(&self, notification: OfflineNotification) {
+        let request = self.client.post(&self.url).json(&notification);
+
+        let member_id = notification.member.id().to_string();
+
+        tokio::spawn(
+            async move {
+                match request.send().await.and_then(|response| response.error_for_status()) {
+                    Ok(_) => {}
+                    Err(err) => warn!(member_id, error = %err, "webhook notify error"),
+                }
+            }
+            .in_current_span(),
+        );
+    }
+}
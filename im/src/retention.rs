@@ -0,0 +1,137 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{auth::RoomId, clock, journal::JournalHandle, message::protocol::MessageType, store::StoreHandle};
+
+const DAY_MILLIS: u64 = 24 * 60 * 60 * 1000;
+
+fn default_interval_secs() -> u64 {
+    60 * 60
+}
+
+/// what happens to a message once it is too old.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RetentionAction {
+    Delete,
+    /// keep type, seq and time, drop content and sender.
+    Anonymize,
+}
+
+/// messages of msg_type older than days get action. a rule without msg_type covers the types no other rule names.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RetentionRule {
+    pub msg_type: Option<MessageType>,
+    pub days: u32,
+    pub action: RetentionAction,
+}
+
+/// RetentionPolicy is the `[retention]` table of the config, messages without a rule are kept forever.
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RetentionPolicy {
+    /// how often expired messages are looked for.
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+    #[serde(default)]
+    pub rules: Vec<RetentionRule>,
+}
+
+/// message types a rule applies to.
+#[derive(Debug, Clone)]
+pub enum MessageTypes {
+    Only(MessageType),
+    AllBut(Vec<MessageType>),
+}
+
+impl MessageTypes {
+    pub fn contains(&self, msg_type: &MessageType) -> bool {
+        match self {
+            MessageTypes::Only(only) => only == msg_type,
+            MessageTypes::AllBut(excluded) => !excluded.contains(msg_type),
+        }
+    }
+}
+
+impl RetentionPolicy {
+    /// the types each rule applies to.
+    fn scopes(&self) -> Vec<(MessageTypes, &RetentionRule)> {
+        let named: Vec<MessageType> = self.rules.iter().filter_map(|rule| rule.msg_type).collect();
+
+        self.rules
+            .iter()
+            .map(|rule| match rule.msg_type {
+                Some(msg_type) => (MessageTypes::Only(msg_type), rule),
+                None => (MessageTypes::AllBut(named.clone()), rule),
+            })
+            .collect()
+    }
+}
+
+/// how to erase a customer.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ErasureMode {
+    /// delete the customer's rooms with everything in them.
+    #[default]
+    Purge,
+    /// anonymize the customer's messages and identity, keep the rest of the conversations.
+    Redact,
+}
+
+/// a room of an erased customer.
+#[derive(Serialize, Debug, Clone)]
+pub struct ErasedRoom {
+    pub room_id: RoomId,
+    /// redacted rooms get a new id, the old one contains the customer id.
+    pub renamed_to: Option<RoomId>,
+}
+
+/// what erasing a customer touched.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Erased {
+    pub rooms: Vec<ErasedRoom>,
+    pub messages: u64,
+    pub attachments: u64,
+}
+
+/// apply the policy once. the journal is checkpointed after changes,
+/// so the next start doesn't replay the expired messages into the store again.
+async fn enforce(policy: &RetentionPolicy, store: &StoreHandle, journal: &JournalHandle) {
+    let now = clock::now_millis();
+    let mut expired = 0;
+
+    for (types, rule) in policy.scopes() {
+        let before = now.saturating_sub(rule.days as u64 * DAY_MILLIS);
+
+        if let Some(count) = store.expire(types.clone(), before, rule.action).await {
+            expired += count;
+            if count > 0 {
+                info!(action = ?rule.action, count, types = ?types, days = rule.days, "retention: expired messages");
+            }
+        }
+    }
+
+    if expired > 0 && !journal.checkpoint(store, true).await {
+        warn!("retention: journal not checkpointed, expired messages may come back at the next start");
+    }
+}
+
+/// enforce policy in the background until the store is gone.
+pub fn spawn(policy: RetentionPolicy, store: StoreHandle, journal: JournalHandle) {
+    if policy.rules.is_empty() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(policy.interval_secs));
+
+        loop {
+            interval.tick().await;
+            enforce(&policy, &store, &journal).await;
+        }
+    });
+}
@@ -0,0 +1,176 @@
+use serde::{Deserialize, Serialize};
+
+use crate::auth::{Member, RoomId};
+
+/// most hits returned per page.
+pub const MAX_SEARCH_LIMIT: u32 = 100;
+
+pub const DEFAULT_SEARCH_LIMIT: u32 = 20;
+
+/// terms beyond this are ignored.
+const MAX_TERMS: usize = 8;
+
+/// characters of context kept on each side of the first match.
+const SNIPPET_CONTEXT: usize = 32;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RoomStatus {
+    Open,
+    Closed,
+}
+
+impl RoomStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RoomStatus::Open => "open",
+            RoomStatus::Closed => "closed",
+        }
+    }
+}
+
+/// messages containing every term. from and to are unix millis and bound when the message was sent, to is exclusive.
+#[derive(Debug, Clone)]
+pub struct SearchQuery {
+    pub terms: Vec<String>,
+    /// id of a customer service member of the room.
+    pub agent: Option<String>,
+    /// id of a customer member of the room.
+    pub customer: Option<String>,
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+    pub status: Option<RoomStatus>,
+    pub limit: u32,
+    pub offset: u32,
+}
+
+/// a matching message. snippet is html escaped with the terms wrapped in `<mark>`.
+#[derive(Serialize, Debug, Clone)]
+pub struct SearchHit {
+    pub room_id: RoomId,
+    pub room_status: RoomStatus,
+    pub seq: u64,
+    pub sender: Member,
+    pub sent_at: u64,
+    pub snippet: String,
+}
+
+/// newest hits first.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct SearchResult {
+    pub hits: Vec<SearchHit>,
+    /// rooms of the hits, in order of their first hit.
+    pub room_ids: Vec<RoomId>,
+    pub has_more: bool,
+}
+
+impl SearchResult {
+    /// page of hits, one more than limit is passed in to tell whether there are more.
+    pub fn new(mut hits: Vec<SearchHit>, limit: u32) -> Self {
+        let has_more = hits.len() > limit as usize;
+        hits.truncate(limit as usize);
+
+        let mut room_ids: Vec<RoomId> = Vec::new();
+        for hit in hits.iter() {
+            if !room_ids.contains(&hit.room_id) {
+                room_ids.push(hit.room_id.clone());
+            }
+        }
+
+        SearchResult { hits, room_ids, has_more }
+    }
+}
+
+/// split q into terms. double quotes keep a phrase together.
+pub fn parse_terms(q: &str) -> Vec<String> {
+    let mut terms = Vec::new();
+
+    for (i, part) in q.split('"').enumerate() {
+        // odd parts were inside quotes.
+        if i % 2 == 1 {
+            terms.push(part.trim().to_string());
+        } else {
+            terms.extend(part.split_whitespace().map(str::to_string));
+        }
+    }
+
+    terms.retain(|term| !term.is_empty());
+    terms.truncate(MAX_TERMS);
+    terms
+}
+
+fn lowercase(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+/// positions of the chars of body matching any term, case insensitive.
+fn matches(body: &[char], terms: &[String]) -> Vec<(usize, usize)> {
+    let terms: Vec<Vec<char>> = terms.iter().map(|term| term.chars().map(lowercase).collect()).collect();
+    let body: Vec<char> = body.iter().copied().map(lowercase).collect();
+    let mut found = Vec::new();
+
+    let mut i = 0;
+    while i < body.len() {
+        let hit = terms
+            .iter()
+            .filter(|term| !term.is_empty() && body[i..].starts_with(term))
+            .map(Vec::len)
+            .max();
+
+        match hit {
+            Some(len) => {
+                found.push((i, i + len));
+                i += len;
+            }
+            None => i += 1,
+        }
+    }
+
+    found
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// the part of body around the first match, with matches marked.
+pub fn snippet(body: &str, terms: &[String]) -> String {
+    let chars: Vec<char> = body.chars().collect();
+    let found = matches(&chars, terms);
+
+    let first = found.first().map_or(0, |(start, _)| *start);
+    let start = first.saturating_sub(SNIPPET_CONTEXT);
+    let end = found
+        .first()
+        .map_or(0, |(_, end)| *end)
+        .saturating_add(SNIPPET_CONTEXT)
+        .min(chars.len())
+        .max(start);
+
+    let text = |from: usize, to: usize| escape(&chars[from..to].iter().collect::<String>());
+
+    let mut out = String::new();
+    if start > 0 {
+        out.push('…');
+    }
+
+    let mut at = start;
+    for (match_start, match_end) in found.into_iter().filter(|(s, e)| *s >= start && *e <= end) {
+        out.push_str(&text(at, match_start));
+        out.push_str("<mark>");
+        out.push_str(&text(match_start, match_end));
+        out.push_str("</mark>");
+        at = match_end;
+    }
+    out.push_str(&text(at, end));
+
+    if end < chars.len() {
+        out.push('…');
+    }
+
+    out
+}
@@ -3,46 +3,177 @@ use futures_util::{
     SinkExt,
 };
 
-use tokio::{net::TcpStream, sync::mpsc};
+use std::{
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use tokio::sync::mpsc;
 use tokio_tungstenite::WebSocketStream;
-use tungstenite::Message;
+use tracing::{error, field, info_span, warn, Instrument, Span};
+use tungstenite::{
+    protocol::{frame::coding::CloseCode, CloseFrame},
+    Message,
+};
+
+use uuid::Uuid;
 
 use crate::{
-    auth::Member,
+    admission::Permit,
+    auth::{audit, ConnWrapper, Member, RoomId},
+    config::{ConnConfig, Penalty},
     dispatch::DispatchHandle,
+    i18n::I18n,
     message::{
+        codec::Codec,
         internal::{ConnMessage, RoomMessage},
+        payload::Payload,
         protocol::{self, ClientProtocol},
+        version::{self, ProtocolVersion},
     },
+    metrics::{self, metrics},
+    moderation::Moderation,
+    tls::ClientStream,
 };
 
+use super::limit::{Limits, MemberLimits, Mute};
+
 /// wrapper websocket connection (actor)
 pub struct Conn {
     id: Member,
 
+    /// unique per connection, a member may connect more than once.
+    conn_id: String,
+
+    /// protocol version negotiated at handshake.
+    version: ProtocolVersion,
+
+    /// frame codec negotiated at handshake.
+    codec: Box<dyn Codec>,
+
+    /// locale system notices are rendered in.
+    locale: String,
+
+    i18n: Arc<I18n>,
+
     /// write is a websocket stream. it can send message to client.
-    write: SplitSink<WebSocketStream<TcpStream>, Message>,
+    write: SplitSink<WebSocketStream<ClientStream>, Message>,
 
     /// read is a websocket stream. it can receive message from client.
-    read: SplitStream<WebSocketStream<TcpStream>>,
+    read: SplitStream<WebSocketStream<ClientStream>>,
 
     /// receiver_from_room is a channel. it can receive message from room.
     mailbox: mpsc::Receiver<RoomMessage>,
 
     /// dispatch actor handle. use this to send message to dispatch.
     dispatch_handle: DispatchHandle,
+
+    /// size and rate limits of client messages.
+    config: ConnConfig,
+
+    /// what this conn may still send.
+    limits: Limits,
+
+    /// what the member may still send, over all its conns.
+    member_limits: Arc<Mutex<Limits>>,
+
+    /// filters the bodies of client messages.
+    moderation: Arc<Moderation>,
+
+    /// messages of the client are dropped while muted.
+    mute: Mute,
+
+    /// frees the admission slot of the conn when it ends.
+    _permit: Permit,
 }
 
 impl Conn {
-    pub fn new(id: Member, stream: WebSocketStream<TcpStream>, mailbox: mpsc::Receiver<RoomMessage>, dispatch_handle: DispatchHandle) -> Self {
-        let (write, read) = stream.split();
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        conn_id: String,
+        conn_wrapper: ConnWrapper,
+        mailbox: mpsc::Receiver<RoomMessage>,
+        dispatch_handle: DispatchHandle,
+        i18n: Arc<I18n>,
+        config: ConnConfig,
+        member_limits: Arc<Mutex<Limits>>,
+        moderation: Arc<Moderation>,
+    ) -> Self {
+        let (write, read) = conn_wrapper.stream.split();
+        let locale = i18n.negotiate(&conn_wrapper.locales);
+        let limits = Limits::new(&config.rate_limit.conn);
 
         Conn {
-            id,
+            id: conn_wrapper.member,
+            conn_id,
+            version: conn_wrapper.version,
+            codec: conn_wrapper.codec.codec(),
+            locale,
+            i18n,
             write,
             read,
             mailbox,
             dispatch_handle,
+            config,
+            limits,
+            member_limits,
+            moderation,
+            mute: Mute::default(),
+            _permit: conn_wrapper.permit,
+        }
+    }
+
+    /// encode frame for the negotiated protocol version.
+    /// returns None when the client's version has no equivalent of frame.
+    fn encode(&self, frame: &ClientProtocol) -> Option<Message> {
+        let encoded = match self.version {
+            ProtocolVersion::V1 => {
+                let frame = version::downgrade(frame, &self.i18n, &self.locale)?;
+                serde_json::to_string(&frame).map(Message::Text).map_err(Into::into)
+            }
+            ProtocolVersion::V2 => self.codec.encode(frame),
+        };
+
+        match encoded {
+            Ok(message) => Some(message),
+            Err(err) => {
+                error!(error = ?err, msg_type = frame.msg_type().as_str(), "encode message error");
+                None
+            }
+        }
+    }
+
+    /// send frame to client.
+    async fn send_frame(&mut self, frame: ClientProtocol) {
+        let message = match self.encode(&frame) {
+            Some(message) => message,
+            None => return,
+        };
+
+        if let Err(err) = self.write.send(message).await {
+            warn!(error = ?err, "send message to client error");
+        }
+    }
+
+    /// render a system notice in the client's locale.
+    fn tips(&self, room_id: RoomId, key: &str, params: &[(&str, &str)]) -> ClientProtocol {
+        ClientProtocol::new_tips(self.i18n.text(&self.locale, key, params), room_id)
+    }
+
+    /// tell dispatch this conn is gone.
+    async fn leave(&mut self) {
+        let room_msg = ConnMessage::OnLeave {
+            member: self.id.clone(),
+            conn_id: self.conn_id.clone(),
+        };
+
+        self.dispatch_handle.send_conn_message(room_msg).await
+    }
+
+    /// greet clients that understand the hello frame.
+    async fn send_hello(&mut self) {
+        if self.version.sends_hello() {
+            self.send_frame(version::hello(self.version)).await;
         }
     }
 
@@ -52,106 +183,378 @@ impl Conn {
         match message {
             RoomMessage::OnJoin { room_id, member } => {
                 if member == self.id {
-                    let _ = self.write.send(protocol::self_join(room_id).to_message()).await;
+                    self.send_frame(self.tips(room_id, "tips.self_joined", &[])).await;
 
                     return;
                 }
 
-                let _ = self.write.send(protocol::join(member, room_id).to_message()).await;
+                let tips = self.tips(room_id, "tips.member_joined", &[("name", member.display_name())]);
+                self.send_frame(tips).await;
             }
-            RoomMessage::OnLeave { room_id, member } => todo!(),
-            RoomMessage::OnNewMessage { room_id, member, content } => {
+            RoomMessage::OnLeave { room_id, member } => {
                 if member == self.id {
                     return;
                 }
 
-                if let Err(err) = self.write.send(content).await {
-                    println!("send message to client err: {:?}", err);
+                let tips = self.tips(room_id, "tips.member_left", &[("name", member.display_name())]);
+                self.send_frame(tips).await;
+            }
+            RoomMessage::OnClose { room_id } => {
+                self.send_frame(self.tips(room_id, "tips.room_closed", &[])).await;
+            }
+            // messages of the member sent on its other conns show up here too.
+            RoomMessage::OnNewMessage { content, .. } => {
+                self.send_frame(content).await;
+            }
+            RoomMessage::OnNotice { content, .. } => {
+                let missed = match (self.version, content.payload()) {
+                    (ProtocolVersion::V1, Some(Payload::Inbox(inbox))) => inbox.messages.clone(),
+                    _ => Vec::new(),
+                };
+
+                self.send_frame(content).await;
+
+                // v1 only gets the unread count, the missed messages follow one by one.
+                for message in missed {
+                    self.send_frame(message).await;
+                }
+            }
+            RoomMessage::OnFlagged {
+                room_id,
+                member,
+                content,
+                reason,
+            } => {
+                // every text of the message, the body may be empty for a card or button click.
+                let texts: Vec<&str> = content.texts().into_iter().map(String::as_str).filter(|text| !text.is_empty()).collect();
+                let texts = texts.join(" / ");
+                let params = [("name", member.display_name()), ("reason", reason.as_str()), ("body", texts.as_str())];
+                let tips = self.tips(room_id, "tips.flagged", &params);
+                self.send_frame(tips).await;
+            }
+            // the listener stops after disconnecting.
+            RoomMessage::Disconnect { code, key, hint } => {
+                self.disconnect(code, &key, hint).await;
+            }
+        }
+    }
+
+    /// send the tips of key and a close frame, whose reason is hint or else key.
+    async fn disconnect(&mut self, code: CloseCode, key: &str, hint: Option<String>) {
+        self.send_frame(self.tips(RoomId::new(), key, &[])).await;
+
+        let frame = CloseFrame {
+            code,
+            reason: hint.unwrap_or_else(|| key.to_string()).into(),
+        };
+        if let Err(err) = self.write.send(Message::Close(Some(frame))).await {
+            warn!(error = ?err, "send close frame error");
+        }
+    }
+
+    /// send an error frame with reason, then close with code.
+    async fn reject(&mut self, code: CloseCode, reason: String) {
+        self.send_frame(protocol::error(&reason, RoomId::new())).await;
+
+        let frame = CloseFrame { code, reason: reason.into() };
+        if let Err(err) = self.write.send(Message::Close(Some(frame))).await {
+            warn!(error = ?err, "send close frame error");
+        }
+    }
+
+    /// run the texts of msg through moderation. returns None when one of them is blocked.
+    async fn moderate(&mut self, mut msg: ClientProtocol) -> Option<ClientProtocol> {
+        if !self.moderation.applies_to(&self.id) {
+            return Some(msg);
+        }
+
+        let mut masked = false;
+        let mut blocked = None;
+        let mut flags: Vec<String> = Vec::new();
+        for text in msg.texts_mut() {
+            if text.is_empty() {
+                continue;
+            }
+
+            let outcome = self.moderation.moderate(&self.id, text);
+            if outcome.blocked.is_some() {
+                blocked = outcome.blocked;
+                break;
+            }
+            if let Some(body) = outcome.body {
+                *text = body;
+                masked = true;
+            }
+            for flag in outcome.flags {
+                if !flags.contains(&flag) {
+                    flags.push(flag);
                 }
             }
         }
+
+        if let Some(reason) = blocked {
+            audit::moderate_message(&self.id, msg.room_id(), "block", &reason);
+            metrics().moderated.with_label_values(&["block"]).inc();
+            self.send_frame(protocol::error("message blocked by moderation", msg.room_id().clone()))
+                .await;
+            return None;
+        }
+
+        if masked {
+            metrics().moderated.with_label_values(&["mask"]).inc();
+        }
+
+        // supervisors see the message as it is delivered, masked.
+        if !flags.is_empty() {
+            let reason = flags.join(", ");
+            audit::moderate_message(&self.id, msg.room_id(), "flag", &reason);
+            metrics().moderated.with_label_values(&["flag"]).inc();
+            self.dispatch_handle
+                .send_conn_message(ConnMessage::OnFlagged {
+                    member: self.id.clone(),
+                    message: msg.clone(),
+                    reason,
+                })
+                .await;
+        }
+
+        Some(msg)
+    }
+
+    /// check a decoded client message and forward it to dispatch.
+    async fn forward(&mut self, mut msg: ClientProtocol, span: Span) {
+        if !version::accepts(self.version, &msg) {
+            self.send_frame(protocol::error("message type not supported by protocol version", msg.room_id().clone()))
+                .await;
+            return;
+        }
+
+        msg.strip_control_chars();
+
+        let max_body_len = self.config.max_body_len(*msg.msg_type());
+        if let Err(err) = msg.validate().and_then(|_| msg.check_body_len(max_body_len)) {
+            warn!(error = %err, "invalid message");
+            self.send_frame(protocol::error(err, msg.room_id().clone())).await;
+            return;
+        }
+
+        let msg = match self.moderate(msg).await {
+            Some(msg) => msg,
+            None => return,
+        };
+
+        self.dispatch_handle
+            .send_conn_message(ConnMessage::OnNewMessage {
+                member: self.id.clone(),
+                conn_id: self.conn_id.clone(),
+                message: msg,
+                span,
+            })
+            .await;
+    }
+
+    /// apply the penalty to a message over the rate limits. returns whether the conn stays open.
+    async fn punish(&mut self) -> bool {
+        let penalty = self.config.rate_limit.penalty;
+        warn!(penalty = penalty.as_str(), "rate limited");
+        metrics().rate_limited.with_label_values(&[penalty.as_str()]).inc();
+
+        match penalty {
+            Penalty::Reject => {
+                self.send_frame(protocol::error("rate limit exceeded", RoomId::new())).await;
+                true
+            }
+            Penalty::Mute => {
+                self.mute.start(self.config.rate_limit.mute(), Instant::now());
+                let secs = self.config.rate_limit.mute_secs.to_string();
+                self.send_frame(self.tips(RoomId::new(), "tips.muted", &[("secs", &secs)])).await;
+                true
+            }
+            Penalty::Disconnect => {
+                self.disconnect(CloseCode::Policy, "tips.rate_limited", None).await;
+                false
+            }
+        }
     }
 
     /// on message received from client.
-    /// forward these message to dispatc.
-    async fn handle_client_message(&mut self, message: Message) {
+    /// forward these message to dispatc. returns whether the conn stays open.
+    async fn handle_client_message(&mut self, message: Message) -> bool {
         match message {
-            Message::Text(msg) => {
-                let msg: ClientProtocol = match serde_json::from_str(&msg) {
+            Message::Text(_) | Message::Binary(_) => {
+                // a muted client isn't told again about every message it sends.
+                if self.mute.is_muted(Instant::now()) {
+                    return true;
+                }
+
+                if !Limits::acquire(&mut self.limits, &self.member_limits, message.len()) {
+                    return self.punish().await;
+                }
+
+                let msg = match self.codec.decode(&message) {
                     Ok(ret) => ret,
                     Err(err) => {
-                        println!("parse message error: {:?}", err);
-                        return;
+                        warn!(error = ?err, "parse message error");
+                        self.send_frame(protocol::error(err, RoomId::new())).await;
+                        return true;
                     }
                 };
 
-                self.dispatch_handle
-                    .send_conn_message(ConnMessage::OnNewMessage {
-                        member: self.id.clone(),
-                        message: msg,
-                    })
-                    .await;
-            }
-            Message::Close(_) => {
-                let room_msg = ConnMessage::OnLeave { member: self.id.clone() };
-
-                self.dispatch_handle.send_conn_message(room_msg).await
+                // the rest of the message's way through dispatch and its room is logged in this span.
+                let span = info_span!(
+                    "message",
+                    message_id = %Uuid::new_v4(),
+                    room_id = %msg.room_id(),
+                    msg_type = msg.msg_type().as_str(),
+                    client_id = msg.client_id(),
+                    seq = field::Empty,
+                );
+                self.forward(msg, span.clone()).instrument(span).await;
             }
-            _ => todo!(),
+            // close ends the listener, ping and pong are answered by tungstenite.
+            _ => {}
         }
+
+        true
     }
 }
 
 /// listener for conn actor.
+/// runs until the client closes the connection or the stream fails.
 async fn listener(mut conn: Conn) {
+    let connected = metrics().connected(conn.id.user_type());
+    let mailbox = metrics().mailbox(metrics::CONN);
+    connected.inc();
+
+    conn.send_hello().await;
+
     loop {
         tokio::select! {
 
             // receive message from room.
             Some(msg) = conn.mailbox.recv() => {
+                mailbox.dec();
+                let disconnect = matches!(msg, RoomMessage::Disconnect { .. });
                 conn.handle_room_message(msg).await;
+                if disconnect {
+                    break;
+                }
             }
 
             // receive message from client.
-            Some(msg) = conn.read.next() => {
+            msg = conn.read.next() => {
                 match msg {
-                    Ok(msg) => {
-                        conn.handle_client_message(msg).await;
+                    Some(Ok(Message::Close(_))) | None => break,
+                    Some(Ok(msg)) => {
+                        if !conn.handle_client_message(msg).await {
+                            break;
+                        }
                     }
-                    Err(err) => {
-                        println!("receive message from client err: {:?}", err);
+                    // the stream can't be read past a frame over the limits or with invalid utf-8.
+                    Some(Err(tungstenite::Error::Capacity(err))) => {
+                        warn!(error = %err, "client message too large");
+                        conn.reject(CloseCode::Size, err.to_string()).await;
+                        break;
+                    }
+                    Some(Err(tungstenite::Error::Utf8)) => {
+                        warn!("client message is not valid utf-8");
+                        conn.reject(CloseCode::Invalid, "invalid utf-8".to_string()).await;
+                        break;
+                    }
+                    Some(Err(err)) => {
+                        warn!(error = ?err, "receive message from client error");
+                        break;
                     }
                 }
             }
         }
     }
+
+    connected.dec();
+    mailbox.sub(conn.mailbox.len() as i64);
+
+    conn.leave().await;
 }
 
 /// conn actor handle. use this to send message to conn.
 #[derive(Debug, Clone)]
 pub struct ConnHandle {
     id: Member,
+    conn_id: String,
     tx: mpsc::Sender<RoomMessage>,
+    connected_at: Instant,
 }
 
 impl ConnHandle {
-    pub fn new(id: Member, stream: WebSocketStream<TcpStream>, dispatch_handle: DispatchHandle) -> Self {
-        let (tx, rx) = mpsc::channel(100);
+    pub fn new(
+        conn_id: String,
+        conn_wrapper: ConnWrapper,
+        dispatch_handle: DispatchHandle,
+        i18n: Arc<I18n>,
+        config: &ConnConfig,
+        member_limits: &MemberLimits,
+        moderation: Arc<Moderation>,
+    ) -> Self {
+        let (tx, rx) = mpsc::channel(config.channel_capacity);
 
-        let conn = Conn::new(id.clone(), stream, rx, dispatch_handle);
+        let id = conn_wrapper.member.clone();
+        let limits = member_limits.get(&id);
+        let conn = Conn::new(
+            conn_id.clone(),
+            conn_wrapper,
+            rx,
+            dispatch_handle,
+            i18n,
+            config.clone(),
+            limits,
+            moderation,
+        );
 
-        tokio::spawn(listener(conn));
+        tokio::spawn(listener(conn).in_current_span());
 
-        ConnHandle { id, tx }
+        ConnHandle {
+            id,
+            conn_id,
+            tx,
+            connected_at: Instant::now(),
+        }
     }
 
     pub fn identity(&self) -> &Member {
         &self.id
     }
 
+    pub fn conn_id(&self) -> &str {
+        &self.conn_id
+    }
+
+    pub fn connected_at(&self) -> Instant {
+        self.connected_at
+    }
+
     pub async fn send_message(&self, message: RoomMessage) {
+        let mailbox = metrics().mailbox(metrics::CONN);
+        mailbox.inc();
         if let Err(err) = self.tx.send(message).await {
-            println!("send message error: {:?}", err);
+            mailbox.dec();
+            warn!(member_id = self.id.id(), error = %err, "send message to conn error");
         }
     }
 }
+
+#[cfg(test)]
+impl ConnHandle {
+    /// a handle with no client behind it. what the conn is sent arrives on the receiver.
+    pub fn detached(member: Member, conn_id: &str) -> (Self, mpsc::Receiver<RoomMessage>) {
+        let (tx, rx) = mpsc::channel(100);
+
+        let conn_handle = ConnHandle {
+            id: member,
+            conn_id: conn_id.to_string(),
+            tx,
+            connected_at: Instant::now(),
+        };
+
+        (conn_handle, rx)
+    }
+}
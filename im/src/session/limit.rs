@@ -0,0 +1,240 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, PoisonError, Weak},
+    time::{Duration, Instant},
+};
+
+use crate::{auth::Member, config::BucketConfig};
+
+/// refills rate tokens a second, holding at most burst.
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: f64, burst: f64) -> Self {
+        TokenBucket {
+            rate,
+            burst,
+            tokens: burst,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.refilled_at = now;
+    }
+
+    fn has(&self, amount: f64) -> bool {
+        self.tokens >= amount
+    }
+
+    fn take(&mut self, amount: f64) {
+        self.tokens -= amount;
+    }
+}
+
+/// a message bucket and a byte bucket.
+#[derive(Debug)]
+pub struct Limits {
+    messages: TokenBucket,
+    bytes: TokenBucket,
+}
+
+impl Limits {
+    pub fn new(config: &BucketConfig) -> Self {
+        Limits {
+            messages: TokenBucket::new(config.messages_per_sec, config.messages_burst),
+            bytes: TokenBucket::new(config.bytes_per_sec, config.bytes_burst),
+        }
+    }
+
+    fn allows(&mut self, bytes: f64, now: Instant) -> bool {
+        self.messages.refill(now);
+        self.bytes.refill(now);
+
+        self.messages.has(1.0) && self.bytes.has(bytes)
+    }
+
+    fn take(&mut self, bytes: f64) {
+        self.messages.take(1.0);
+        self.bytes.take(bytes);
+    }
+
+    /// take a message of bytes from both conn and member, or from neither when either is out of tokens.
+    pub fn acquire(conn: &mut Limits, member: &Mutex<Limits>, bytes: usize) -> bool {
+        Limits::acquire_at(conn, member, bytes, Instant::now())
+    }
+
+    fn acquire_at(conn: &mut Limits, member: &Mutex<Limits>, bytes: usize, now: Instant) -> bool {
+        let bytes = bytes as f64;
+        let mut member = member.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if !(conn.allows(bytes, now) && member.allows(bytes, now)) {
+            return false;
+        }
+
+        conn.take(bytes);
+        member.take(bytes);
+        true
+    }
+}
+
+/// drops every message of a conn for a while, the mute penalty.
+#[derive(Debug, Default)]
+pub struct Mute {
+    until: Option<Instant>,
+}
+
+impl Mute {
+    pub fn start(&mut self, duration: Duration, now: Instant) {
+        self.until = Some(now + duration);
+    }
+
+    /// whether messages are still dropped at now. the mute ends once it has lasted its duration.
+    pub fn is_muted(&mut self, now: Instant) -> bool {
+        match self.until {
+            Some(until) if now < until => true,
+            Some(_) => {
+                self.until = None;
+                false
+            }
+            None => false,
+        }
+    }
+}
+
+/// the limits of each member, shared by its conns. dropped once the member has no conn left.
+#[derive(Debug)]
+pub struct MemberLimits {
+    config: BucketConfig,
+    members: Mutex<HashMap<Member, Weak<Mutex<Limits>>>>,
+}
+
+impl MemberLimits {
+    pub fn new(config: BucketConfig) -> Self {
+        MemberLimits {
+            config,
+            members: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// the limits of member, held for as long as one of its conns is open.
+    pub fn get(&self, member: &Member) -> Arc<Mutex<Limits>> {
+        let mut members = self.members.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if let Some(limits) = members.get(member).and_then(Weak::upgrade) {
+            return limits;
+        }
+
+        members.retain(|_, limits| limits.strong_count() > 0);
+
+        let limits = Arc::new(Mutex::new(Limits::new(&self.config)));
+        members.insert(member.clone(), Arc::downgrade(&limits));
+        limits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auth::UserType;
+
+    use super::*;
+
+    fn config(messages_per_sec: f64, messages_burst: f64, bytes_per_sec: f64, bytes_burst: f64) -> BucketConfig {
+        BucketConfig {
+            messages_per_sec,
+            messages_burst,
+            bytes_per_sec,
+            bytes_burst,
+        }
+    }
+
+    #[test]
+    fn bucket_allows_burst_then_refills_at_rate() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(2.0, 4.0);
+        bucket.refilled_at = start;
+
+        for _ in 0..4 {
+            assert!(bucket.has(1.0));
+            bucket.take(1.0);
+        }
+        assert!(!bucket.has(1.0));
+
+        bucket.refill(start + Duration::from_millis(500));
+        assert!(bucket.has(1.0));
+        assert!(!bucket.has(1.5));
+
+        // never more than the burst, however long it waits.
+        bucket.refill(start + Duration::from_secs(60));
+        assert!(bucket.has(4.0));
+        assert!(!bucket.has(4.5));
+    }
+
+    #[test]
+    fn acquire_takes_from_conn_and_member() {
+        let now = Instant::now();
+        let mut conn = Limits::new(&config(1.0, 2.0, 1000.0, 1000.0));
+        let member = Mutex::new(Limits::new(&config(10.0, 10.0, 1000.0, 1000.0)));
+
+        assert!(Limits::acquire_at(&mut conn, &member, 100, now));
+        assert!(Limits::acquire_at(&mut conn, &member, 100, now));
+        // the conn is out of messages, the member is not charged for the rejected one.
+        assert!(!Limits::acquire_at(&mut conn, &member, 100, now));
+        assert!(member.lock().unwrap().messages.has(8.0));
+        assert!(!member.lock().unwrap().messages.has(8.5));
+
+        assert!(Limits::acquire_at(&mut conn, &member, 100, now + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn acquire_is_limited_by_bytes() {
+        let now = Instant::now();
+        let mut conn = Limits::new(&config(100.0, 100.0, 100.0, 1000.0));
+        let member = Mutex::new(Limits::new(&config(100.0, 100.0, 100.0, 1000.0)));
+
+        assert!(!Limits::acquire_at(&mut conn, &member, 1001, now));
+        assert!(Limits::acquire_at(&mut conn, &member, 1000, now));
+        assert!(!Limits::acquire_at(&mut conn, &member, 1, now));
+        assert!(Limits::acquire_at(&mut conn, &member, 100, now + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn member_limits_are_shared_by_its_conns() {
+        let now = Instant::now();
+        let limits = MemberLimits::new(config(1.0, 1.0, 1000.0, 1000.0));
+        let member = Member::new(UserType::Customer, "c1".to_string(), "Carol".to_string());
+
+        let first = limits.get(&member);
+        let second = limits.get(&member);
+        let mut conn = Limits::new(&config(10.0, 10.0, 1000.0, 1000.0));
+        assert!(Limits::acquire_at(&mut conn, &first, 1, now));
+        assert!(!Limits::acquire_at(&mut conn, &second, 1, now));
+
+        // a member coming back after its last conn closed starts afresh.
+        drop(first);
+        drop(second);
+        let third = limits.get(&member);
+        assert!(Limits::acquire_at(&mut conn, &third, 1, now));
+    }
+
+    #[test]
+    fn mute_lasts_its_duration() {
+        let now = Instant::now();
+        let mut mute = Mute::default();
+        assert!(!mute.is_muted(now));
+
+        mute.start(Duration::from_secs(30), now);
+        assert!(mute.is_muted(now));
+        assert!(mute.is_muted(now + Duration::from_secs(29)));
+        assert!(!mute.is_muted(now + Duration::from_secs(30)));
+        assert!(!mute.is_muted(now + Duration::from_secs(1)));
+    }
+}
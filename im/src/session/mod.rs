@@ -1,2 +1,3 @@
 pub mod conn;
+pub mod limit;
 pub mod room;
@@ -1,36 +1,143 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    time::Instant,
+};
 
 use tokio::sync::mpsc;
+use tracing::{debug, info, info_span, warn, Instrument, Span};
 
 use crate::{
-    auth::{Member, RoomId},
-    message::internal::{DispatchMessage, RoomMessage},
+    auth::{audit, Member, RoomId},
+    clock,
+    config::RoomConfig,
+    dispatch::admin::{ConnInfo, RoomInfo},
+    journal::{JournalEntry, JournalHandle, RecoveredRoom},
+    message::{
+        internal::{DispatchMessage, RoomMessage},
+        payload::{HistoryQuery, InboxPayload},
+        protocol::{self, ClientProtocol, MessageType},
+    },
+    metrics::{self, metrics},
+    notify::{OfflineNotification, Webhook},
+    store::{RoomEvent, StoreHandle},
 };
 
 use super::conn::ConnHandle;
 
+/// messages that arrived while a member was offline.
+#[derive(Default)]
+struct Inbox {
+    unread: u32,
+    messages: VecDeque<ClientProtocol>,
+}
+
+/// a message recently sent in the room. kept while it can still be recalled or edited.
+struct SentMessage {
+    sender: Member,
+    content: ClientProtocol,
+}
+
 pub struct ChatRoom {
     id: RoomId,
 
-    members: HashMap<Member, ConnHandle>,
+    /// members online in the room, with their conns by conn id. a member may have several, e.g. browser tabs.
+    members: HashMap<Member, HashMap<String, ConnHandle>>,
+
+    /// everyone who joined the room, online or not.
+    participants: HashSet<Member>,
+
+    /// undelivered messages of offline participants.
+    inboxes: HashMap<Member, Inbox>,
+
+    /// offline participants are notified here of their first unread message.
+    webhook: Option<Webhook>,
 
     manager_receiver: mpsc::Receiver<DispatchMessage>,
+
+    /// last sequence assigned to a message.
+    seq: u64,
+
+    /// messages sent within the edit window, oldest first.
+    recent: VecDeque<SentMessage>,
+
+    /// edit window, join history and inbox limits.
+    config: RoomConfig,
+
+    /// history of the room is recorded here.
+    store: StoreHandle,
+
+    /// events are journaled too, so the room can be rebuilt after a restart.
+    journal: JournalHandle,
+
+    /// set when the room was rebuilt from the journal rather than created.
+    restored: bool,
+
+    /// set once the room is closed, the actor stops after that.
+    closed: bool,
 }
 
 /// ChatRoom is a actor.
 impl ChatRoom {
-    pub fn new(id: RoomId, receiver: mpsc::Receiver<DispatchMessage>) -> Self {
+    pub fn new(
+        id: RoomId,
+        receiver: mpsc::Receiver<DispatchMessage>,
+        config: RoomConfig,
+        store: StoreHandle,
+        journal: JournalHandle,
+        webhook: Option<Webhook>,
+    ) -> Self {
         ChatRoom {
             id,
             members: HashMap::new(),
+            participants: HashSet::new(),
+            inboxes: HashMap::new(),
+            webhook,
             manager_receiver: receiver,
+            seq: 0,
+            recent: VecDeque::new(),
+            config,
+            store,
+            journal,
+            restored: false,
+            closed: false,
         }
     }
 
-    /// send message to all conn.
-    async fn broadcast(&mut self, msg: RoomMessage, filter: Vec<Member>) {
-        for (id, conn_handle) in self.members.iter() {
-            if filter.contains(id) {
+    /// pick up a room rebuilt from the journal. members join again as they reconnect.
+    fn restore(&mut self, room: RecoveredRoom) {
+        self.seq = room.last_seq;
+        self.participants = room.members.into_iter().collect();
+        self.recent = room.recent.into_iter().map(|(sender, content)| SentMessage { sender, content }).collect();
+        self.restored = true;
+    }
+
+    /// continue the sequence of earlier conversations in this room and record its creation.
+    async fn start(&mut self) {
+        self.seq = self.seq.max(self.store.last_seq(self.id.clone()).await);
+        self.prune_recent(clock::now_millis());
+
+        if self.restored {
+            return;
+        }
+
+        let event = RoomEvent::Created {
+            room_id: self.id.clone(),
+            at: clock::now_millis(),
+        };
+        self.record(event).await;
+    }
+
+    /// queue event for the store, then journal it. in this order a journal checkpoint,
+    /// which syncs the store, never drops an event the store has not been given yet.
+    async fn record(&self, event: RoomEvent) {
+        self.store.record(event.clone()).await;
+        self.journal.append(JournalEntry::Room(event)).await;
+    }
+
+    /// send message to every conn but the one with conn id except.
+    async fn broadcast(&mut self, msg: RoomMessage, except: Option<&str>) {
+        for conn_handle in self.members.values().flat_map(HashMap::values) {
+            if except == Some(conn_handle.conn_id()) {
                 continue;
             }
 
@@ -38,58 +145,398 @@ impl ChatRoom {
         }
     }
 
-    /// send from_member message to all conn. except from_member.
-    async fn broadcast_join(&mut self, from_member: Member) {
-        let chat_message = RoomMessage::OnJoin {
+    /// the sender may speak in this room if it is online in it. supervisors are no exception,
+    /// they join the rooms they supervise through the admin api.
+    fn can_speak(&self, member: &Member) -> bool {
+        self.members.contains_key(member)
+    }
+
+    /// send a frame to one conn of member only. conns not in this room are skipped.
+    async fn notify(&self, member: &Member, conn_id: &str, content: ClientProtocol) {
+        if let Some(conn_handle) = self.members.get(member).and_then(|conns| conns.get(conn_id)) {
+            let message = RoomMessage::OnNotice {
+                room_id: self.id.clone(),
+                content,
+            };
+
+            conn_handle.send_message(message).await;
+        }
+    }
+
+    /// send a page of history to a conn of member. the store is queried off the room actor.
+    /// skip_empty avoids sending empty pages nobody asked for.
+    fn send_history(&self, member: &Member, conn_id: &str, query: HistoryQuery, skip_empty: bool) {
+        let conn_handle = match self.members.get(member).and_then(|conns| conns.get(conn_id)) {
+            Some(conn_handle) => conn_handle.clone(),
+            None => return,
+        };
+
+        let store = self.store.clone();
+        let room_id = self.id.clone();
+
+        tokio::spawn(async move {
+            let history = match store.history(room_id.clone(), query).await {
+                Some(history) => history,
+                None => return,
+            };
+
+            if skip_empty && history.messages.is_empty() {
+                return;
+            }
+
+            let message = RoomMessage::OnNotice {
+                content: ClientProtocol::new_history(room_id.clone(), history),
+                room_id,
+            };
+            conn_handle.send_message(message).await;
+        });
+    }
+
+    /// keep message for the participants that are offline.
+    fn queue_offline(&mut self, from_member: &Member, message: &ClientProtocol) {
+        let offline = self
+            .participants
+            .iter()
+            .filter(|member| *member != from_member && !self.members.contains_key(*member));
+
+        for member in offline {
+            let inbox = self.inboxes.entry(member.clone()).or_default();
+            inbox.unread += 1;
+            inbox.messages.push_back(message.clone());
+            if inbox.messages.len() > self.config.inbox_size {
+                inbox.messages.pop_front();
+            }
+
+            // one notification per offline spell is enough to bring the member back.
+            if let (1, Some(webhook)) = (inbox.unread, &self.webhook) {
+                webhook.notify(OfflineNotification {
+                    member: member.clone(),
+                    room_id: self.id.clone(),
+                    unread: inbox.unread,
+                    preview: OfflineNotification::preview(message.body()),
+                    sent_at: message.sent_at().unwrap_or_default(),
+                });
+            }
+        }
+    }
+
+    /// apply a recall or edit to the messages still waiting in inboxes.
+    fn amend_inboxes(&mut self, msg_type: MessageType, seq: u64, body: &str) {
+        for inbox in self.inboxes.values_mut() {
+            let index = match inbox.messages.iter().position(|message| message.seq() == Some(seq)) {
+                Some(index) => index,
+                None => continue,
+            };
+
+            if msg_type == MessageType::Edit {
+                inbox.messages[index].set_body(body.to_string());
+            } else {
+                inbox.messages.remove(index);
+                inbox.unread = inbox.unread.saturating_sub(1);
+            }
+        }
+
+        self.inboxes.retain(|_, inbox| inbox.unread > 0);
+    }
+
+    /// drop messages whose edit window has passed.
+    fn prune_recent(&mut self, now: u64) {
+        let window = self.config.edit_window().as_millis() as u64;
+
+        while let Some(sent) = self.recent.front() {
+            if sent.content.sent_at().unwrap_or_default() + window >= now {
+                break;
+            }
+
+            self.recent.pop_front();
+        }
+    }
+
+    /// assign a sequence to the message, ack the sending conn and forward it to every other conn,
+    /// those of the sender included.
+    async fn handle_chat(&mut self, from_member: Member, conn_id: &str, mut message: ClientProtocol) {
+        let now = clock::now_millis();
+        self.seq += 1;
+        message.stamp(self.seq, from_member.id().to_string(), now);
+        Span::current().record("seq", self.seq);
+
+        self.prune_recent(now);
+        self.recent.push_back(SentMessage {
+            sender: from_member.clone(),
+            content: message.clone(),
+        });
+
+        let event = RoomEvent::Message {
+            room_id: self.id.clone(),
+            member: from_member.clone(),
+            content: message.clone(),
+        };
+        self.record(event).await;
+
+        let ack = ClientProtocol::new_ack(self.id.clone(), self.seq, message.client_id().map(str::to_string));
+        self.notify(&from_member, conn_id, ack).await;
+
+        let chat_message = RoomMessage::OnNewMessage {
             room_id: self.id.clone(),
             member: from_member.clone(),
+            content: message.clone(),
+        };
+
+        let fanout = Instant::now();
+        self.broadcast(chat_message, Some(conn_id)).await;
+        metrics().fanout.observe(fanout.elapsed().as_secs_f64());
+        self.queue_offline(&from_member, &message);
+        debug!("message broadcast");
+    }
+
+    /// recall or edit a recent message. only its sender may do so, within the edit window.
+    async fn handle_amendment(&mut self, from_member: Member, conn_id: &str, message: ClientProtocol) {
+        let target = match message.target() {
+            Some(target) => target,
+            None => return,
         };
 
-        self.broadcast(chat_message, vec![from_member]).await;
+        self.prune_recent(clock::now_millis());
+
+        let index = match self.recent.iter().position(|sent| sent.content.seq() == Some(target.seq)) {
+            Some(index) => index,
+            None => {
+                let err = protocol::error("message not found or edit window expired", self.id.clone());
+                self.notify(&from_member, conn_id, err).await;
+                return;
+            }
+        };
+
+        if self.recent[index].sender != from_member {
+            audit::reject_message(&from_member, &self.id, "amend message of another member");
+            let err = protocol::error("only the sender may change this message", self.id.clone());
+            self.notify(&from_member, conn_id, err).await;
+            return;
+        }
+
+        let msg_type = *message.msg_type();
+        let at = clock::now_millis();
+        let event = if msg_type == MessageType::Edit {
+            if self.recent[index].content.msg_type() != &MessageType::Chat {
+                let err = protocol::error("only text messages can be edited", self.id.clone());
+                self.notify(&from_member, conn_id, err).await;
+                return;
+            }
+
+            self.recent[index].content.set_body(message.body().to_string());
+
+            RoomEvent::Edited {
+                room_id: self.id.clone(),
+                seq: target.seq,
+                body: message.body().to_string(),
+                at,
+            }
+        } else {
+            self.recent.remove(index);
+
+            RoomEvent::Recalled {
+                room_id: self.id.clone(),
+                seq: target.seq,
+                at,
+            }
+        };
+        self.record(event).await;
+        self.amend_inboxes(msg_type, target.seq, message.body());
+
+        let event = ClientProtocol::new_amendment(
+            msg_type,
+            message.body().to_string(),
+            self.id.clone(),
+            target,
+            from_member.id().to_string(),
+        );
+
+        let room_message = RoomMessage::OnNotice {
+            room_id: self.id.clone(),
+            content: event,
+        };
+
+        self.broadcast(room_message, None).await;
+    }
+
+    /// tell every conn but the joining one that from_member joined.
+    async fn broadcast_join(&mut self, from_member: Member, conn_id: &str) {
+        let chat_message = RoomMessage::OnJoin {
+            room_id: self.id.clone(),
+            member: from_member,
+        };
+
+        self.broadcast(chat_message, Some(conn_id)).await;
+    }
+
+    /// a conn of member is gone. once it was the last one the others are told,
+    /// and the member stays out until it joins again.
+    async fn handle_leave(&mut self, member: Member, conn_id: &str) {
+        let conns = match self.members.get_mut(&member) {
+            Some(conns) => conns,
+            None => return,
+        };
+        conns.remove(conn_id);
+        if !conns.is_empty() {
+            return;
+        }
+        self.members.remove(&member);
+
+        let event = RoomEvent::Left {
+            room_id: self.id.clone(),
+            member: member.clone(),
+            at: clock::now_millis(),
+        };
+        self.record(event).await;
+
+        let leave_message = RoomMessage::OnLeave {
+            room_id: self.id.clone(),
+            member,
+        };
+        self.broadcast(leave_message, None).await;
+    }
+
+    /// end the conversation. remaining members are told and the actor stops.
+    async fn handle_close(&mut self) {
+        let event = RoomEvent::Closed {
+            room_id: self.id.clone(),
+            at: clock::now_millis(),
+        };
+        self.record(event).await;
+
+        let close_message = RoomMessage::OnClose { room_id: self.id.clone() };
+        self.broadcast(close_message, None).await;
+
+        self.members.clear();
+        self.closed = true;
+    }
+
+    /// who is in the room, for the admin api.
+    fn describe(&self) -> RoomInfo {
+        let online = self
+            .members
+            .iter()
+            .flat_map(|(member, conns)| {
+                conns.keys().map(|conn_id| ConnInfo {
+                    conn_id: conn_id.clone(),
+                    member: member.clone(),
+                })
+            })
+            .collect();
+
+        RoomInfo {
+            room_id: self.id.clone(),
+            online,
+            participants: self.participants.iter().cloned().collect(),
+            seq: self.seq,
+        }
+    }
+
+    /// message of from_member sent on conn_id, forwarded by the manager.
+    async fn handle_new_message(&mut self, from_member: Member, conn_id: String, message: ClientProtocol) {
+        if message.room_id() != &self.id {
+            audit::reject_message(&from_member, message.room_id(), "room id mismatch");
+            return;
+        }
+
+        if !self.can_speak(&from_member) {
+            audit::reject_message(&from_member, &self.id, "not a member of the room");
+            return;
+        }
+
+        if let Some(query) = message.history_query() {
+            self.send_history(&from_member, &conn_id, query, false);
+        } else if message.msg_type().is_amendment() {
+            self.handle_amendment(from_member, &conn_id, message).await;
+        } else {
+            self.handle_chat(from_member, &conn_id, message).await;
+        }
     }
 
     /// on message received from dispatch manager or conn.
-    /// OnJoin from dispatch manager.
-    /// OnLeave from conn.
+    /// OnJoin, OnLeave and Close from dispatch manager.
     /// OnNewMessage from conn.
     async fn handle_dispatch_message(&mut self, msg: DispatchMessage) {
         match msg {
             DispatchMessage::OnJoin { conn_handle } => {
-                println!(
-                    "member join room: {:?}, member: {:?}",
-                    self.id,
-                    conn_handle.identity()
-                );
+                let member = conn_handle.identity().clone();
+                let conn_id = conn_handle.conn_id().to_string();
+                info!(member_id = member.id(), user_type = ?member.user_type(), %conn_id, "member join room");
+
+                // another conn of a member already online, only that conn needs catching up.
+                let conns = self.members.entry(member.clone()).or_default();
+                let first = conns.is_empty();
+                conns.insert(conn_id.clone(), conn_handle);
+
+                if first {
+                    let event = RoomEvent::Joined {
+                        room_id: self.id.clone(),
+                        member: member.clone(),
+                        at: clock::now_millis(),
+                    };
+                    self.record(event).await;
 
-                self.members
-                    .insert(conn_handle.identity().clone(), conn_handle.clone());
+                    self.participants.insert(member.clone());
+                    self.broadcast_join(member.clone(), &conn_id).await;
+                }
 
-                self.broadcast_join(conn_handle.identity().clone()).await;
+                // what the member missed while offline, or else the latest history.
+                match self.inboxes.remove(&member) {
+                    Some(inbox) => {
+                        let payload = InboxPayload {
+                            unread: inbox.unread,
+                            messages: inbox.messages.into(),
+                        };
+                        self.notify(&member, &conn_id, ClientProtocol::new_inbox(self.id.clone(), payload)).await;
+                    }
+                    None => self.send_history(&member, &conn_id, HistoryQuery::latest(self.config.history_on_join), true),
+                }
+            }
+            DispatchMessage::OnLeave { member, conn_id } => {
+                self.handle_leave(member, &conn_id).await;
+            }
+            DispatchMessage::Close => {
+                self.handle_close().await;
             }
             DispatchMessage::OnNewMessage {
                 member: from_member,
+                conn_id,
                 message,
+                span,
             } => {
-                let chat_message = RoomMessage::OnNewMessage {
-                    room_id: self.id.clone(),
-                    member: from_member.clone(),
-                    content: message.to_message(),
-                };
-
-                self.broadcast(chat_message, vec![from_member]).await;
+                self.handle_new_message(from_member, conn_id, message).instrument(span).await;
             }
             DispatchMessage::GetMemberCount { respond_to } => {
                 let _ = respond_to.send(self.members.len() as u32);
             }
+            DispatchMessage::IsMember { member, respond_to } => {
+                let _ = respond_to.send(self.members.contains_key(&member));
+            }
+            DispatchMessage::Describe { respond_to } => {
+                let _ = respond_to.send(self.describe());
+            }
         }
     }
 }
 
 /// listen message from dispatch manager or conn.
 async fn listener(mut room: ChatRoom) {
+    let mailbox = metrics().mailbox(metrics::ROOM);
+    metrics().open_rooms.inc();
+
+    room.start().await;
+
     while let Some(msg) = room.manager_receiver.recv().await {
+        mailbox.dec();
         room.handle_dispatch_message(msg).await;
+
+        if room.closed {
+            break;
+        }
     }
+
+    metrics().open_rooms.dec();
+    mailbox.sub(room.manager_receiver.len() as i64);
 }
 
 #[derive(Clone, Debug)]
@@ -99,11 +546,23 @@ pub struct RoomHandle {
 }
 
 impl RoomHandle {
-    pub fn new(id: RoomId) -> Self {
-        let (tx, rx) = mpsc::channel(100);
-        let room = ChatRoom::new(id.clone(), rx);
+    pub fn new(id: RoomId, config: &RoomConfig, store: StoreHandle, journal: JournalHandle, webhook: Option<Webhook>) -> Self {
+        let (tx, rx) = mpsc::channel(config.channel_capacity);
+        let room = ChatRoom::new(id.clone(), rx, config.clone(), store, journal, webhook);
+
+        tokio::spawn(listener(room).instrument(info_span!(parent: None, "room", room_id = %id)));
+
+        RoomHandle { id, sender: tx }
+    }
 
-        tokio::spawn(listener(room));
+    /// start a room rebuilt from the journal.
+    pub fn restore(room: RecoveredRoom, config: &RoomConfig, store: StoreHandle, journal: JournalHandle, webhook: Option<Webhook>) -> Self {
+        let id = room.room_id.clone();
+        let (tx, rx) = mpsc::channel(config.channel_capacity);
+        let mut chat_room = ChatRoom::new(id.clone(), rx, config.clone(), store, journal, webhook);
+        chat_room.restore(room);
+
+        tokio::spawn(listener(chat_room).instrument(info_span!(parent: None, "room", room_id = %id, restored = true)));
 
         RoomHandle { id, sender: tx }
     }
@@ -114,8 +573,11 @@ impl RoomHandle {
 
     /// send message to room
     async fn send_message(&self, message: DispatchMessage) {
+        let mailbox = metrics().mailbox(metrics::ROOM);
+        mailbox.inc();
         if let Err(err) = self.sender.send(message).await {
-            println!("send message error: {:?}", err);
+            mailbox.dec();
+            warn!(room_id = %self.id, error = %err, "send message to room error");
         }
     }
 
@@ -129,6 +591,16 @@ impl RoomHandle {
         }
     }
 
+    /// send on leave message of a conn of member to room.
+    pub async fn leave(&self, member: Member, conn_id: String) {
+        self.send_message(DispatchMessage::OnLeave { member, conn_id }).await;
+    }
+
+    /// close the room.
+    pub async fn close(&self) {
+        self.send_message(DispatchMessage::Close).await;
+    }
+
     /// send on new message to room.
     pub async fn new_message(&self, message: DispatchMessage) {
         self.send_message(message).await;
@@ -144,4 +616,142 @@ impl RoomHandle {
 
         rx.await.unwrap()
     }
+
+    /// return whether member joined this room.
+    pub async fn is_member(&self, member: Member) -> bool {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+
+        let message = DispatchMessage::IsMember { member, respond_to: tx };
+
+        self.send_message(message).await;
+
+        rx.await.unwrap_or(false)
+    }
+
+    /// who is in the room. None when the room has stopped.
+    pub async fn describe(&self) -> Option<RoomInfo> {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+
+        self.send_message(DispatchMessage::Describe { respond_to: tx }).await;
+
+        rx.await.ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, sync::Arc, time::Duration};
+
+    use tokio::time::timeout;
+
+    use crate::{auth::UserType, journal::Journal, store::SqliteStore};
+
+    use super::*;
+
+    const ROOM_ID: &str = "c1-a1";
+
+    struct Fixture {
+        dir: PathBuf,
+        room: RoomHandle,
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("im-room-{}", uuid::Uuid::new_v4()));
+            let store = StoreHandle::new(Arc::new(SqliteStore::open(dir.join("im.db")).unwrap()));
+            let journal = Journal::open(dir.join("journal")).unwrap().start(true).unwrap();
+            let room = RoomHandle::new(ROOM_ID.to_string(), &RoomConfig::default(), store, journal, None);
+
+            Fixture { dir, room }
+        }
+
+        /// a conn of a new member, joined to the room when join is set.
+        async fn conn(&self, user_type: UserType, id: &str, join: bool) -> (Member, mpsc::Receiver<RoomMessage>) {
+            let member = Member::new(user_type, id.to_string(), id.to_string());
+            let (conn_handle, rx) = ConnHandle::detached(member.clone(), id);
+            if join {
+                self.room.join(vec![conn_handle]).await;
+            }
+
+            (member, rx)
+        }
+
+        async fn say(&self, member: &Member, body: &str) {
+            let message = serde_json::from_value(serde_json::json!({"msg_type": "Chat", "body": body, "room_id": ROOM_ID})).unwrap();
+            let message = DispatchMessage::OnNewMessage {
+                member: member.clone(),
+                conn_id: member.id().to_string(),
+                message,
+                span: Span::none(),
+            };
+            self.room.new_message(message).await;
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    /// what a conn was sent until it went quiet.
+    async fn received(rx: &mut mpsc::Receiver<RoomMessage>) -> Vec<RoomMessage> {
+        let mut messages = Vec::new();
+        while let Ok(Some(message)) = timeout(Duration::from_millis(200), rx.recv()).await {
+            messages.push(message);
+        }
+
+        messages
+    }
+
+    /// bodies of the chat messages of others among messages.
+    fn chats(messages: &[RoomMessage]) -> Vec<String> {
+        messages
+            .iter()
+            .filter_map(|message| match message {
+                RoomMessage::OnNewMessage { content, .. } => Some(content.body().to_string()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn acks(messages: &[RoomMessage]) -> usize {
+        messages
+            .iter()
+            .filter(|message| matches!(message, RoomMessage::OnNotice { content, .. } if content.msg_type() == &MessageType::Ack))
+            .count()
+    }
+
+    #[tokio::test]
+    async fn only_members_speak() {
+        let fixture = Fixture::new();
+        let (_, mut customer) = fixture.conn(UserType::Customer, "c1", true).await;
+        let (_, mut agent) = fixture.conn(UserType::CustomerService, "a1", true).await;
+        let (stranger, mut stranger_rx) = fixture.conn(UserType::Customer, "c2", false).await;
+        let (supervisor, mut supervisor_rx) = fixture.conn(UserType::Supervisor, "s1", false).await;
+
+        fixture.say(&stranger, "from a stranger").await;
+        fixture.say(&supervisor, "from a supervisor").await;
+
+        assert!(chats(&received(&mut customer).await).is_empty());
+        assert!(chats(&received(&mut agent).await).is_empty());
+        assert!(received(&mut stranger_rx).await.is_empty());
+        assert!(received(&mut supervisor_rx).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_joined_supervisor_speaks_and_is_acked() {
+        let fixture = Fixture::new();
+        let (customer, mut customer_rx) = fixture.conn(UserType::Customer, "c1", true).await;
+        let (supervisor, mut supervisor_rx) = fixture.conn(UserType::Supervisor, "s1", true).await;
+        received(&mut customer_rx).await;
+
+        fixture.say(&supervisor, "from a supervisor").await;
+        fixture.say(&customer, "from the customer").await;
+
+        assert_eq!(chats(&received(&mut customer_rx).await), ["from a supervisor"]);
+        let supervisor_rx = received(&mut supervisor_rx).await;
+        assert_eq!(acks(&supervisor_rx), 1);
+        assert_eq!(chats(&supervisor_rx), ["from the customer"]);
+    }
 }
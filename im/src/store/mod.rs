@@ -0,0 +1,522 @@
+mod sqlite;
+
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{error, info_span, Instrument};
+
+use crate::{
+    auth::{Member, RoomId},
+    message::{
+        payload::{HistoryPayload, HistoryQuery},
+        protocol::ClientProtocol,
+    },
+    retention::{Erased, ErasureMode, MessageTypes, RetentionAction},
+    search::{SearchQuery, SearchResult},
+    transcript::{Transcript, TranscriptFilter},
+};
+
+pub use sqlite::SqliteStore;
+
+/// events flush when this many are buffered.
+const BATCH_SIZE: usize = 256;
+
+/// buffered events flush at least this often.
+const FLUSH_INTERVAL: Duration = Duration::from_millis(200);
+
+/// something that happened in a room. timestamps are unix millis.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum RoomEvent {
+    Created {
+        room_id: RoomId,
+        at: u64,
+    },
+    Joined {
+        room_id: RoomId,
+        member: Member,
+        at: u64,
+    },
+    Left {
+        room_id: RoomId,
+        member: Member,
+        at: u64,
+    },
+    /// content is stamped with seq and sent_at by the room.
+    Message {
+        room_id: RoomId,
+        member: Member,
+        content: ClientProtocol,
+    },
+    Recalled {
+        room_id: RoomId,
+        seq: u64,
+        at: u64,
+    },
+    Edited {
+        room_id: RoomId,
+        seq: u64,
+        body: String,
+        at: u64,
+    },
+    Closed {
+        room_id: RoomId,
+        at: u64,
+    },
+}
+
+/// MessageStore persists room events.
+/// implementations are blocking, they run on the blocking pool behind a StoreHandle.
+pub trait MessageStore: Send + Sync {
+    /// apply events in order.
+    fn append(&self, events: &[RoomEvent]) -> Result<()>;
+
+    /// the highest message sequence stored for room, 0 if none.
+    fn last_seq(&self, room_id: &str) -> Result<u64>;
+
+    /// whether member ever joined room, even if it left or the room is closed.
+    fn is_participant(&self, room_id: &str, member: &Member) -> Result<bool>;
+
+    /// page of messages of room. recalled messages come back as recall placeholders.
+    fn history(&self, room_id: &str, query: &HistoryQuery) -> Result<HistoryPayload>;
+
+    /// full history of the rooms matching filter, oldest room first.
+    fn transcripts(&self, filter: &TranscriptFilter) -> Result<Vec<Transcript>>;
+
+    /// messages matching query, newest first. recalled messages are never found.
+    fn search(&self, query: &SearchQuery) -> Result<SearchResult>;
+
+    /// delete or anonymize messages of types sent before `before`, unix millis. returns how many changed.
+    fn expire(&self, types: &MessageTypes, before: u64, action: RetentionAction) -> Result<u64>;
+
+    /// remove customer member_id from history. attachments are left to the caller.
+    fn erase_customer(&self, member_id: &str, mode: ErasureMode) -> Result<Erased>;
+
+    /// fail unless the backing storage can be read.
+    fn ping(&self) -> Result<()>;
+}
+
+/// whether a page walks forward from `after`. otherwise it walks back from `before` or the newest message.
+fn pages_forward(query: &HistoryQuery) -> bool {
+    query.after.is_some() && query.before.is_none()
+}
+
+enum StoreCommand {
+    Record(RoomEvent),
+    LastSeq {
+        room_id: RoomId,
+        respond_to: oneshot::Sender<u64>,
+    },
+    IsParticipant {
+        room_id: RoomId,
+        member: Member,
+        respond_to: oneshot::Sender<bool>,
+    },
+    History {
+        room_id: RoomId,
+        query: HistoryQuery,
+        respond_to: oneshot::Sender<HistoryPayload>,
+    },
+    Transcripts {
+        filter: TranscriptFilter,
+        respond_to: oneshot::Sender<Vec<Transcript>>,
+    },
+    Search {
+        query: SearchQuery,
+        respond_to: oneshot::Sender<SearchResult>,
+    },
+    Expire {
+        types: MessageTypes,
+        before: u64,
+        action: RetentionAction,
+        respond_to: oneshot::Sender<u64>,
+    },
+    EraseCustomer {
+        member_id: String,
+        mode: ErasureMode,
+        respond_to: oneshot::Sender<Erased>,
+    },
+    Flush {
+        respond_to: oneshot::Sender<()>,
+    },
+    Sync {
+        respond_to: oneshot::Sender<bool>,
+    },
+    Ping {
+        respond_to: oneshot::Sender<()>,
+    },
+}
+
+/// store actor. batches events so rooms never wait on disk.
+struct StoreWriter {
+    store: Arc<dyn MessageStore>,
+    buffer: Vec<RoomEvent>,
+    /// set while the buffer holds a batch that failed. until it is written the journal must keep its events.
+    failed: bool,
+    mailbox: mpsc::Receiver<StoreCommand>,
+}
+
+impl StoreWriter {
+    /// write buffered events. a failed batch stays buffered, ahead of later events, and is tried again at the next flush.
+    async fn flush(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+
+        let events = Arc::new(std::mem::take(&mut self.buffer));
+        let store = self.store.clone();
+        let batch = events.clone();
+
+        let err = match tokio::task::spawn_blocking(move || store.append(&batch)).await {
+            Ok(Ok(())) => {
+                self.failed = false;
+                return;
+            }
+            Ok(Err(err)) => err,
+            Err(err) => err.into(),
+        };
+        error!(error = ?err, events = events.len(), "store append error, batch kept for retry");
+
+        // the blocking task is done with its clone by now.
+        self.buffer = Arc::try_unwrap(events).unwrap_or_else(|events| events.as_ref().clone());
+        self.failed = true;
+    }
+
+    /// run query on the store off the actor and answer with its result. the buffered events are written first,
+    /// so it sees every event recorded before it. failures are logged as what and leave respond_to unanswered.
+    async fn query<T: Send + 'static>(
+        &mut self,
+        what: &str,
+        respond_to: oneshot::Sender<T>,
+        query: impl FnOnce(&dyn MessageStore) -> Result<T> + Send + 'static,
+    ) {
+        self.flush().await;
+
+        let store = self.store.clone();
+        match tokio::task::spawn_blocking(move || query(store.as_ref())).await {
+            Ok(Ok(value)) => {
+                let _ = respond_to.send(value);
+            }
+            Ok(Err(err)) => error!(error = ?err, "store {} error", what),
+            Err(err) => error!(error = ?err, "store {} task error", what),
+        }
+    }
+
+    async fn handle_command(&mut self, command: StoreCommand) {
+        match command {
+            StoreCommand::Record(event) => {
+                self.buffer.push(event);
+                // a failing store is retried on the flush interval, not on every event.
+                if self.buffer.len() >= BATCH_SIZE && !self.failed {
+                    self.flush().await;
+                }
+            }
+            StoreCommand::LastSeq { room_id, respond_to } => {
+                self.query("last seq", respond_to, move |store| store.last_seq(&room_id)).await;
+            }
+            StoreCommand::IsParticipant { room_id, member, respond_to } => {
+                self.query("participant", respond_to, move |store| store.is_participant(&room_id, &member))
+                    .await;
+            }
+            StoreCommand::History { room_id, query, respond_to } => {
+                self.query("history", respond_to, move |store| store.history(&room_id, &query)).await;
+            }
+            StoreCommand::Transcripts { filter, respond_to } => {
+                self.query("transcripts", respond_to, move |store| store.transcripts(&filter)).await;
+            }
+            StoreCommand::Search { query, respond_to } => {
+                self.query("search", respond_to, move |store| store.search(&query)).await;
+            }
+            StoreCommand::Expire {
+                types,
+                before,
+                action,
+                respond_to,
+            } => {
+                self.query("expire", respond_to, move |store| store.expire(&types, before, action)).await;
+            }
+            StoreCommand::EraseCustomer { member_id, mode, respond_to } => {
+                self.query("erase", respond_to, move |store| store.erase_customer(&member_id, mode)).await;
+            }
+            StoreCommand::Flush { respond_to } => {
+                self.flush().await;
+                let _ = respond_to.send(());
+            }
+            StoreCommand::Sync { respond_to } => {
+                self.flush().await;
+                let _ = respond_to.send(!self.failed);
+            }
+            StoreCommand::Ping { respond_to } => {
+                self.query("ping", respond_to, |store| store.ping()).await;
+            }
+        }
+    }
+}
+
+async fn listener(mut writer: StoreWriter) {
+    let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                writer.flush().await;
+            }
+
+            command = writer.mailbox.recv() => {
+                match command {
+                    Some(command) => writer.handle_command(command).await,
+                    None => {
+                        writer.flush().await;
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// store actor handle. use this to record and query room history.
+#[derive(Clone)]
+pub struct StoreHandle {
+    sender: mpsc::Sender<StoreCommand>,
+}
+
+impl std::fmt::Debug for StoreHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StoreHandle").finish()
+    }
+}
+
+impl StoreHandle {
+    pub fn new(store: Arc<dyn MessageStore>) -> Self {
+        let (sender, mailbox) = mpsc::channel(10_000);
+
+        let writer = StoreWriter {
+            store,
+            buffer: Vec::with_capacity(BATCH_SIZE),
+            failed: false,
+            mailbox,
+        };
+
+        tokio::spawn(listener(writer).instrument(info_span!(parent: None, "store")));
+
+        StoreHandle { sender }
+    }
+
+    async fn send_command(&self, command: StoreCommand) {
+        if self.sender.send(command).await.is_err() {
+            error!("send store command error: store closed");
+        }
+    }
+
+    /// queue event for writing.
+    pub async fn record(&self, event: RoomEvent) {
+        self.send_command(StoreCommand::Record(event)).await;
+    }
+
+    /// the highest message sequence stored for room.
+    pub async fn last_seq(&self, room_id: RoomId) -> u64 {
+        let (tx, rx) = oneshot::channel();
+
+        self.send_command(StoreCommand::LastSeq { room_id, respond_to: tx }).await;
+
+        rx.await.unwrap_or_default()
+    }
+
+    /// whether member ever joined room. false when the store failed.
+    pub async fn is_participant(&self, room_id: RoomId, member: Member) -> bool {
+        let (tx, rx) = oneshot::channel();
+
+        self.send_command(StoreCommand::IsParticipant {
+            room_id,
+            member,
+            respond_to: tx,
+        })
+        .await;
+
+        rx.await.unwrap_or_default()
+    }
+
+    /// page of messages of room. None when the store failed.
+    pub async fn history(&self, room_id: RoomId, query: HistoryQuery) -> Option<HistoryPayload> {
+        let (tx, rx) = oneshot::channel();
+
+        self.send_command(StoreCommand::History {
+            room_id,
+            query,
+            respond_to: tx,
+        })
+        .await;
+
+        rx.await.ok()
+    }
+
+    /// full history of the rooms matching filter. None when the store failed.
+    pub async fn transcripts(&self, filter: TranscriptFilter) -> Option<Vec<Transcript>> {
+        let (tx, rx) = oneshot::channel();
+
+        self.send_command(StoreCommand::Transcripts { filter, respond_to: tx }).await;
+
+        rx.await.ok()
+    }
+
+    /// messages matching query. None when the store failed.
+    pub async fn search(&self, query: SearchQuery) -> Option<SearchResult> {
+        let (tx, rx) = oneshot::channel();
+
+        self.send_command(StoreCommand::Search { query, respond_to: tx }).await;
+
+        rx.await.ok()
+    }
+
+    /// apply a retention rule. None when the store failed.
+    pub async fn expire(&self, types: MessageTypes, before: u64, action: RetentionAction) -> Option<u64> {
+        let (tx, rx) = oneshot::channel();
+
+        self.send_command(StoreCommand::Expire {
+            types,
+            before,
+            action,
+            respond_to: tx,
+        })
+        .await;
+
+        rx.await.ok()
+    }
+
+    /// remove customer member_id from history. None when the store failed.
+    pub async fn erase_customer(&self, member_id: String, mode: ErasureMode) -> Option<Erased> {
+        let (tx, rx) = oneshot::channel();
+
+        self.send_command(StoreCommand::EraseCustomer {
+            member_id,
+            mode,
+            respond_to: tx,
+        })
+        .await;
+
+        rx.await.ok()
+    }
+
+    /// write every event recorded so far.
+    pub async fn flush(&self) {
+        let (tx, rx) = oneshot::channel();
+
+        self.send_command(StoreCommand::Flush { respond_to: tx }).await;
+
+        let _ = rx.await;
+    }
+
+    /// write every event recorded so far. returns whether every event recorded since the start is written.
+    pub async fn sync(&self) -> bool {
+        let (tx, rx) = oneshot::channel();
+
+        self.send_command(StoreCommand::Sync { respond_to: tx }).await;
+
+        rx.await.unwrap_or_default()
+    }
+
+    /// whether the store answers and can read its storage.
+    pub async fn ping(&self) -> bool {
+        let (tx, rx) = oneshot::channel();
+
+        self.send_command(StoreCommand::Ping { respond_to: tx }).await;
+
+        rx.await.is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    };
+
+    use super::*;
+
+    /// keeps the rooms of appended events, or fails while failing is set.
+    #[derive(Default)]
+    struct FlakyStore {
+        failing: AtomicBool,
+        appended: Mutex<Vec<RoomId>>,
+    }
+
+    impl MessageStore for FlakyStore {
+        fn append(&self, events: &[RoomEvent]) -> Result<()> {
+            if self.failing.load(Ordering::SeqCst) {
+                anyhow::bail!("disk full");
+            }
+
+            let mut appended = self.appended.lock().unwrap();
+            for event in events {
+                if let RoomEvent::Created { room_id, .. } = event {
+                    appended.push(room_id.clone());
+                }
+            }
+
+            Ok(())
+        }
+
+        fn last_seq(&self, _: &str) -> Result<u64> {
+            Ok(self.appended.lock().unwrap().len() as u64)
+        }
+
+        fn is_participant(&self, _: &str, _: &Member) -> Result<bool> {
+            unimplemented!()
+        }
+
+        fn history(&self, _: &str, _: &HistoryQuery) -> Result<HistoryPayload> {
+            unimplemented!()
+        }
+
+        fn transcripts(&self, _: &TranscriptFilter) -> Result<Vec<Transcript>> {
+            unimplemented!()
+        }
+
+        fn search(&self, _: &SearchQuery) -> Result<SearchResult> {
+            unimplemented!()
+        }
+
+        fn expire(&self, _: &MessageTypes, _: u64, _: RetentionAction) -> Result<u64> {
+            unimplemented!()
+        }
+
+        fn erase_customer(&self, _: &str, _: ErasureMode) -> Result<Erased> {
+            unimplemented!()
+        }
+
+        fn ping(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn created(room_id: &str) -> RoomEvent {
+        RoomEvent::Created {
+            room_id: room_id.to_string(),
+            at: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_batches_are_retried_in_order() {
+        let store = Arc::new(FlakyStore::default());
+        let handle = StoreHandle::new(store.clone());
+
+        store.failing.store(true, Ordering::SeqCst);
+        handle.record(created("r1")).await;
+        assert!(!handle.sync().await);
+        handle.record(created("r2")).await;
+        assert!(!handle.sync().await);
+
+        store.failing.store(false, Ordering::SeqCst);
+        handle.record(created("r3")).await;
+        assert!(handle.sync().await);
+        assert_eq!(*store.appended.lock().unwrap(), ["r1", "r2", "r3"]);
+
+        // queries see what was recorded before them.
+        handle.record(created("r4")).await;
+        assert_eq!(handle.last_seq("r4".to_string()).await, 4);
+    }
+}
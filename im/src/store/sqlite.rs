@@ -0,0 +1,595 @@
+use std::{path::Path, sync::Mutex};
+
+use anyhow::Result;
+use rusqlite::{params, params_from_iter, types::Value, Connection, OptionalExtension};
+
+use crate::{
+    auth::{Member, UserType},
+    clock,
+    message::{
+        payload::{HistoryPayload, HistoryQuery},
+        protocol::ClientProtocol,
+    },
+    retention::{Erased, ErasedRoom, ErasureMode, MessageTypes, RetentionAction},
+    search::{self, RoomStatus, SearchHit, SearchQuery, SearchResult},
+    transcript::{Participant, Transcript, TranscriptFilter, TranscriptMessage},
+};
+
+use super::{pages_forward, MessageStore, RoomEvent};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS rooms (
+    room_id    TEXT PRIMARY KEY,
+    status     TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    closed_at  INTEGER
+);
+
+CREATE TABLE IF NOT EXISTS room_members (
+    room_id   TEXT NOT NULL,
+    member_id TEXT NOT NULL,
+    user_type TEXT NOT NULL,
+    user_name TEXT NOT NULL,
+    joined_at INTEGER NOT NULL,
+    left_at   INTEGER,
+    PRIMARY KEY (room_id, member_id, user_type)
+);
+
+CREATE TABLE IF NOT EXISTS messages (
+    room_id     TEXT NOT NULL,
+    seq         INTEGER NOT NULL,
+    sender_id   TEXT NOT NULL,
+    sender_type TEXT NOT NULL,
+    sender_name TEXT NOT NULL,
+    msg_type    TEXT NOT NULL,
+    body        TEXT NOT NULL,
+    content     TEXT NOT NULL,
+    sent_at     INTEGER NOT NULL,
+    edited_at   INTEGER,
+    recalled_at INTEGER,
+    PRIMARY KEY (room_id, seq)
+);
+
+CREATE INDEX IF NOT EXISTS messages_sent_at ON messages (sent_at);
+
+-- bodies of messages that weren't recalled, keyed by rowid of messages.
+-- trigram tokens match substrings, so text without spaces between words is searchable too.
+CREATE VIRTUAL TABLE IF NOT EXISTS messages_fts USING fts5(body, tokenize = 'trigram');
+";
+
+/// terms shorter than this can't use the trigram index and are matched with LIKE.
+const MIN_INDEXED_TERM: usize = 3;
+
+/// embedded sqlite database.
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        if let Some(dir) = path.as_ref().parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+
+        let indexed: bool = conn.query_row("SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE name = 'messages_fts')", [], |row| {
+            row.get(0)
+        })?;
+        conn.execute_batch(SCHEMA)?;
+
+        // anonymized messages are marked, databases from before get the column.
+        let redactable: bool = conn.query_row(
+            "SELECT EXISTS (SELECT 1 FROM pragma_table_info('messages') WHERE name = 'redacted_at')",
+            [],
+            |row| row.get(0),
+        )?;
+        if !redactable {
+            conn.execute("ALTER TABLE messages ADD COLUMN redacted_at INTEGER", [])?;
+        }
+
+        // databases from before the search index get their messages indexed once.
+        if !indexed {
+            conn.execute(
+                "INSERT INTO messages_fts (rowid, body) SELECT rowid, body FROM messages WHERE recalled_at IS NULL",
+                [],
+            )?;
+        }
+
+        Ok(SqliteStore { conn: Mutex::new(conn) })
+    }
+}
+
+/// rebuild a member from its stored columns.
+fn member(id: String, user_type: &str, user_name: String) -> Result<Member> {
+    let user_type = UserType::parse(user_type).ok_or_else(|| anyhow::anyhow!("unknown user type {}", user_type))?;
+    Ok(Member::new(user_type, id, user_name))
+}
+
+fn participants(conn: &Connection, room_id: &str) -> Result<Vec<Participant>> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT member_id, user_type, user_name, joined_at, left_at FROM room_members
+         WHERE room_id = ?1 ORDER BY joined_at",
+    )?;
+    let rows = stmt.query_map(params![room_id], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, String>(2)?,
+            row.get::<_, u64>(3)?,
+            row.get::<_, Option<u64>>(4)?,
+        ))
+    })?;
+
+    let mut participants = Vec::new();
+    for row in rows {
+        let (id, user_type, user_name, joined_at, left_at) = row?;
+        participants.push(Participant {
+            member: member(id, &user_type, user_name)?,
+            joined_at,
+            left_at,
+        });
+    }
+
+    Ok(participants)
+}
+
+fn transcript_messages(conn: &Connection, room_id: &str) -> Result<Vec<TranscriptMessage>> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT sender_id, sender_type, sender_name, content, edited_at, recalled_at FROM messages
+         WHERE room_id = ?1 ORDER BY seq",
+    )?;
+    let rows = stmt.query_map(params![room_id], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, String>(2)?,
+            row.get::<_, String>(3)?,
+            row.get::<_, Option<u64>>(4)?,
+            row.get::<_, Option<u64>>(5)?,
+        ))
+    })?;
+
+    let mut messages = Vec::new();
+    for row in rows {
+        let (id, user_type, user_name, content, edited_at, recalled_at) = row?;
+        messages.push(TranscriptMessage {
+            sender: member(id, &user_type, user_name)?,
+            content: serde_json::from_str(&content)?,
+            edited_at,
+            recalled_at,
+        });
+    }
+
+    Ok(messages)
+}
+
+/// sql condition on msg_type with its values, numbered from ?1.
+fn type_condition(types: &MessageTypes) -> (String, Vec<Value>) {
+    match types {
+        MessageTypes::Only(msg_type) => ("msg_type = ?1".to_string(), vec![Value::Text(msg_type.as_str().to_string())]),
+        MessageTypes::AllBut(excluded) if excluded.is_empty() => ("1".to_string(), Vec::new()),
+        MessageTypes::AllBut(excluded) => {
+            let placeholders: Vec<String> = (1..=excluded.len()).map(|i| format!("?{}", i)).collect();
+            let values = excluded.iter().map(|msg_type| Value::Text(msg_type.as_str().to_string())).collect();
+            (format!("msg_type NOT IN ({})", placeholders.join(", ")), values)
+        }
+    }
+}
+
+/// anonymize the messages selected by the query, which returns rowid and content.
+fn redact(tx: &rusqlite::Transaction, query: &str, values: &[Value]) -> Result<u64> {
+    let rows = {
+        let mut stmt = tx.prepare(query)?;
+        let rows = stmt.query_map(params_from_iter(values.iter()), |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+        })?;
+        rows.collect::<rusqlite::Result<Vec<_>>>()?
+    };
+
+    let at = clock::now_millis();
+    for (rowid, content) in rows.iter() {
+        let content: ClientProtocol = serde_json::from_str(content)?;
+
+        tx.execute(
+            "UPDATE messages SET sender_id = '', sender_name = '', body = '', content = ?2, redacted_at = ?3 WHERE rowid = ?1",
+            params![rowid, serde_json::to_string(&content.into_redacted())?, at],
+        )?;
+        tx.execute("DELETE FROM messages_fts WHERE rowid = ?1", params![rowid])?;
+    }
+
+    Ok(rows.len() as u64)
+}
+
+fn apply(tx: &rusqlite::Transaction, event: &RoomEvent) -> Result<()> {
+    match event {
+        RoomEvent::Created { room_id, at } => {
+            tx.execute(
+                "INSERT INTO rooms (room_id, status, created_at) VALUES (?1, 'open', ?2)
+                 ON CONFLICT (room_id) DO UPDATE SET status = 'open', closed_at = NULL",
+                params![room_id, at],
+            )?;
+        }
+        RoomEvent::Joined { room_id, member, at } => {
+            tx.execute(
+                "INSERT INTO room_members (room_id, member_id, user_type, user_name, joined_at) VALUES (?1, ?2, ?3, ?4, ?5)
+                 ON CONFLICT (room_id, member_id, user_type) DO UPDATE SET user_name = ?4, left_at = NULL",
+                params![room_id, member.id(), member.user_type().as_str(), member.user_name(), at],
+            )?;
+        }
+        RoomEvent::Left { room_id, member, at } => {
+            tx.execute(
+                "UPDATE room_members SET left_at = ?4 WHERE room_id = ?1 AND member_id = ?2 AND user_type = ?3",
+                params![room_id, member.id(), member.user_type().as_str(), at],
+            )?;
+        }
+        RoomEvent::Message { room_id, member, content } => {
+            // a message written again replaces its row, and its index entry with it.
+            tx.execute(
+                "DELETE FROM messages_fts WHERE rowid = (SELECT rowid FROM messages WHERE room_id = ?1 AND seq = ?2)",
+                params![room_id, content.seq().unwrap_or_default()],
+            )?;
+            tx.execute(
+                "INSERT OR REPLACE INTO messages
+                 (room_id, seq, sender_id, sender_type, sender_name, msg_type, body, content, sent_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![
+                    room_id,
+                    content.seq().unwrap_or_default(),
+                    member.id(),
+                    member.user_type().as_str(),
+                    member.user_name(),
+                    content.msg_type().as_str(),
+                    content.body(),
+                    serde_json::to_string(content)?,
+                    content.sent_at().unwrap_or_default(),
+                ],
+            )?;
+            tx.execute(
+                "INSERT INTO messages_fts (rowid, body) VALUES (?1, ?2)",
+                params![tx.last_insert_rowid(), content.body()],
+            )?;
+        }
+        RoomEvent::Recalled { room_id, seq, at } => {
+            tx.execute(
+                "UPDATE messages SET recalled_at = ?3 WHERE room_id = ?1 AND seq = ?2",
+                params![room_id, seq, at],
+            )?;
+            tx.execute(
+                "DELETE FROM messages_fts WHERE rowid = (SELECT rowid FROM messages WHERE room_id = ?1 AND seq = ?2)",
+                params![room_id, seq],
+            )?;
+        }
+        RoomEvent::Edited { room_id, seq, body, at } => {
+            let content: Option<String> = tx
+                .query_row(
+                    "SELECT content FROM messages WHERE room_id = ?1 AND seq = ?2",
+                    params![room_id, seq],
+                    |row| row.get(0),
+                )
+                .optional()?;
+
+            if let Some(content) = content {
+                let mut content: ClientProtocol = serde_json::from_str(&content)?;
+                content.set_body(body.clone());
+
+                tx.execute(
+                    "UPDATE messages SET body = ?3, content = ?4, edited_at = ?5 WHERE room_id = ?1 AND seq = ?2",
+                    params![room_id, seq, body, serde_json::to_string(&content)?, at],
+                )?;
+                tx.execute(
+                    "UPDATE messages_fts SET body = ?3 WHERE rowid = (SELECT rowid FROM messages WHERE room_id = ?1 AND seq = ?2)",
+                    params![room_id, seq, body],
+                )?;
+            }
+        }
+        RoomEvent::Closed { room_id, at } => {
+            tx.execute(
+                "UPDATE rooms SET status = 'closed', closed_at = ?2 WHERE room_id = ?1",
+                params![room_id, at],
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+impl MessageStore for SqliteStore {
+    fn append(&self, events: &[RoomEvent]) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+
+        for event in events {
+            apply(&tx, event)?;
+        }
+
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    fn last_seq(&self, room_id: &str) -> Result<u64> {
+        let conn = self.conn.lock().unwrap();
+
+        let seq: Option<u64> = conn.query_row("SELECT MAX(seq) FROM messages WHERE room_id = ?1", params![room_id], |row| row.get(0))?;
+
+        Ok(seq.unwrap_or_default())
+    }
+
+    fn is_participant(&self, room_id: &str, member: &Member) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+
+        let participant = conn
+            .prepare_cached("SELECT 1 FROM room_members WHERE room_id = ?1 AND member_id = ?2 AND user_type = ?3")?
+            .exists(params![room_id, member.id(), member.user_type().as_str()])?;
+
+        Ok(participant)
+    }
+
+    fn history(&self, room_id: &str, query: &HistoryQuery) -> Result<HistoryPayload> {
+        let conn = self.conn.lock().unwrap();
+
+        let order = if pages_forward(query) { "ASC" } else { "DESC" };
+        let sql = format!(
+            "SELECT content, recalled_at FROM messages
+             WHERE room_id = ?1 AND seq > ?2 AND seq < ?3
+             ORDER BY seq {} LIMIT ?4",
+            order
+        );
+
+        let mut stmt = conn.prepare_cached(&sql)?;
+        let rows = stmt.query_map(
+            params![
+                room_id,
+                query.after.unwrap_or(0),
+                query.before.unwrap_or(i64::MAX as u64),
+                query.limit + 1
+            ],
+            |row| Ok((row.get::<_, String>(0)?, row.get::<_, Option<u64>>(1)?)),
+        )?;
+
+        let mut messages = Vec::new();
+        for row in rows {
+            let (content, recalled_at) = row?;
+            let content: ClientProtocol = serde_json::from_str(&content)?;
+
+            messages.push(match recalled_at {
+                Some(_) => content.into_recalled(),
+                None => content,
+            });
+        }
+
+        let has_more = messages.len() > query.limit as usize;
+        messages.truncate(query.limit as usize);
+        if !pages_forward(query) {
+            messages.reverse();
+        }
+
+        Ok(HistoryPayload { messages, has_more })
+    }
+
+    fn transcripts(&self, filter: &TranscriptFilter) -> Result<Vec<Transcript>> {
+        let conn = self.conn.lock().unwrap();
+
+        let mut stmt = conn.prepare_cached(
+            "SELECT room_id, created_at, closed_at FROM rooms
+             WHERE (?1 IS NULL OR room_id = ?1)
+               AND (?2 IS NULL OR created_at >= ?2)
+               AND (?3 IS NULL OR created_at < ?3)
+               AND (?4 IS NULL OR EXISTS (SELECT 1 FROM room_members m
+                    WHERE m.room_id = rooms.room_id AND m.member_id = ?4 AND m.user_type = 'CustomerService'))
+               AND (?5 IS NULL OR EXISTS (SELECT 1 FROM room_members m
+                    WHERE m.room_id = rooms.room_id AND m.member_id = ?5 AND m.user_type = 'Customer'))
+             ORDER BY created_at, room_id",
+        )?;
+        let rows = stmt.query_map(params![filter.room_id, filter.from, filter.to, filter.agent, filter.customer], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, u64>(1)?, row.get::<_, Option<u64>>(2)?))
+        })?;
+
+        let mut transcripts = Vec::new();
+        for row in rows {
+            let (room_id, created_at, closed_at) = row?;
+            transcripts.push(Transcript {
+                participants: participants(&conn, &room_id)?,
+                messages: transcript_messages(&conn, &room_id)?,
+                room_id,
+                created_at,
+                closed_at,
+            });
+        }
+
+        Ok(transcripts)
+    }
+
+    fn search(&self, query: &SearchQuery) -> Result<SearchResult> {
+        let conn = self.conn.lock().unwrap();
+
+        let mut conditions = vec!["m.recalled_at IS NULL".to_string()];
+        let mut values: Vec<Value> = Vec::new();
+        let mut bind = |conditions: &mut Vec<String>, condition: &str, value: Value| {
+            values.push(value);
+            conditions.push(condition.replace('?', &format!("?{}", values.len())));
+        };
+
+        let (indexed, short): (Vec<&String>, Vec<&String>) = query.terms.iter().partition(|term| term.chars().count() >= MIN_INDEXED_TERM);
+        if !indexed.is_empty() {
+            let phrases: Vec<String> = indexed.iter().map(|term| format!("\"{}\"", term.replace('"', "\"\""))).collect();
+            bind(
+                &mut conditions,
+                "m.rowid IN (SELECT rowid FROM messages_fts WHERE messages_fts MATCH ?)",
+                Value::Text(phrases.join(" ")),
+            );
+        }
+        for term in short {
+            let pattern = format!("%{}%", term.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_"));
+            bind(&mut conditions, "m.body LIKE ? ESCAPE '\\'", Value::Text(pattern));
+        }
+
+        if let Some(from) = query.from {
+            bind(&mut conditions, "m.sent_at >= ?", Value::Integer(from as i64));
+        }
+        if let Some(to) = query.to {
+            bind(&mut conditions, "m.sent_at < ?", Value::Integer(to as i64));
+        }
+        if let Some(agent) = &query.agent {
+            bind(
+                &mut conditions,
+                "EXISTS (SELECT 1 FROM room_members a WHERE a.room_id = m.room_id AND a.member_id = ? AND a.user_type = 'CustomerService')",
+                Value::Text(agent.clone()),
+            );
+        }
+        if let Some(customer) = &query.customer {
+            bind(
+                &mut conditions,
+                "EXISTS (SELECT 1 FROM room_members c WHERE c.room_id = m.room_id AND c.member_id = ? AND c.user_type = 'Customer')",
+                Value::Text(customer.clone()),
+            );
+        }
+        if let Some(status) = query.status {
+            bind(&mut conditions, "r.status = ?", Value::Text(status.as_str().to_string()));
+        }
+
+        values.push(Value::Integer(query.limit as i64 + 1));
+        values.push(Value::Integer(query.offset as i64));
+        let sql = format!(
+            "SELECT m.room_id, r.status, m.seq, m.sender_id, m.sender_type, m.sender_name, m.sent_at, m.body
+             FROM messages m JOIN rooms r ON r.room_id = m.room_id
+             WHERE {}
+             ORDER BY m.sent_at DESC, m.room_id, m.seq DESC
+             LIMIT ?{} OFFSET ?{}",
+            conditions.join(" AND "),
+            values.len() - 1,
+            values.len()
+        );
+
+        let mut stmt = conn.prepare(&sql)?;
+        let rows = stmt.query_map(params_from_iter(values), |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, u64>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, String>(4)?,
+                row.get::<_, String>(5)?,
+                row.get::<_, u64>(6)?,
+                row.get::<_, String>(7)?,
+            ))
+        })?;
+
+        let mut hits = Vec::new();
+        for row in rows {
+            let (room_id, status, seq, sender_id, sender_type, sender_name, sent_at, body) = row?;
+            hits.push(SearchHit {
+                room_id,
+                room_status: if status == "closed" { RoomStatus::Closed } else { RoomStatus::Open },
+                seq,
+                sender: member(sender_id, &sender_type, sender_name)?,
+                sent_at,
+                snippet: search::snippet(&body, &query.terms),
+            });
+        }
+
+        Ok(SearchResult::new(hits, query.limit))
+    }
+
+    fn expire(&self, types: &MessageTypes, before: u64, action: RetentionAction) -> Result<u64> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+
+        let (condition, mut values) = type_condition(types);
+        values.push(Value::Integer(before as i64));
+        let scope = format!("{} AND sent_at < ?{}", condition, values.len());
+
+        let count = match action {
+            RetentionAction::Delete => {
+                tx.execute(
+                    &format!("DELETE FROM messages_fts WHERE rowid IN (SELECT rowid FROM messages WHERE {})", scope),
+                    params_from_iter(values.iter()),
+                )?;
+                tx.execute(&format!("DELETE FROM messages WHERE {}", scope), params_from_iter(values.iter()))? as u64
+            }
+            RetentionAction::Anonymize => redact(
+                &tx,
+                &format!("SELECT rowid, content FROM messages WHERE {} AND redacted_at IS NULL", scope),
+                &values,
+            )?,
+        };
+
+        tx.commit()?;
+        Ok(count)
+    }
+
+    fn erase_customer(&self, member_id: &str, mode: ErasureMode) -> Result<Erased> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+
+        let room_ids = {
+            let mut stmt = tx.prepare("SELECT DISTINCT room_id FROM room_members WHERE member_id = ?1 AND user_type = 'Customer'")?;
+            let rows = stmt.query_map(params![member_id], |row| row.get::<_, String>(0))?;
+            rows.collect::<rusqlite::Result<Vec<_>>>()?
+        };
+
+        let mut rooms = Vec::new();
+        let mut messages = 0;
+
+        match mode {
+            ErasureMode::Purge => {
+                for room_id in room_ids {
+                    tx.execute(
+                        "DELETE FROM messages_fts WHERE rowid IN (SELECT rowid FROM messages WHERE room_id = ?1)",
+                        params![room_id],
+                    )?;
+                    messages += tx.execute("DELETE FROM messages WHERE room_id = ?1", params![room_id])? as u64;
+                    tx.execute("DELETE FROM room_members WHERE room_id = ?1", params![room_id])?;
+                    tx.execute("DELETE FROM rooms WHERE room_id = ?1", params![room_id])?;
+
+                    rooms.push(ErasedRoom { room_id, renamed_to: None });
+                }
+            }
+            ErasureMode::Redact => {
+                messages = redact(
+                    &tx,
+                    "SELECT rowid, content FROM messages WHERE sender_id = ?1 AND sender_type = 'Customer'",
+                    &[Value::Text(member_id.to_string())],
+                )?;
+                tx.execute(
+                    "UPDATE room_members SET member_id = 'erased', user_name = '' WHERE member_id = ?1 AND user_type = 'Customer'",
+                    params![member_id],
+                )?;
+
+                for room_id in room_ids {
+                    let renamed = format!("erased-{}", uuid::Uuid::new_v4().simple());
+
+                    tx.execute("UPDATE rooms SET room_id = ?2 WHERE room_id = ?1", params![room_id, renamed])?;
+                    tx.execute("UPDATE room_members SET room_id = ?2 WHERE room_id = ?1", params![room_id, renamed])?;
+                    tx.execute(
+                        "UPDATE messages SET room_id = ?2, content = json_set(content, '$.room_id', ?2) WHERE room_id = ?1",
+                        params![room_id, renamed],
+                    )?;
+
+                    rooms.push(ErasedRoom {
+                        room_id,
+                        renamed_to: Some(renamed),
+                    });
+                }
+            }
+        }
+
+        tx.commit()?;
+        Ok(Erased {
+            rooms,
+            messages,
+            ..Default::default()
+        })
+    }
+
+    fn ping(&self) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+
+        conn.prepare_cached("SELECT 1 FROM rooms LIMIT 1")?.exists([])?;
+
+        Ok(())
+    }
+}
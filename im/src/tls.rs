@@ -0,0 +1,375 @@
+use std::{
+    io,
+    path::PathBuf,
+    pin::Pin,
+    sync::{Arc, PoisonError, RwLock},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use anyhow::{Context as _, Result};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use rustls::{
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    server::WebPkiClientVerifier,
+    RootCertStore, ServerConfig,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpStream,
+    signal::unix::{signal, SignalKind},
+    sync::mpsc,
+};
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
+use tracing::{error, info, info_span, warn, Instrument};
+
+use crate::config::TlsConfig;
+
+/// files are usually replaced one after the other, changes within this are reloaded once.
+const RELOAD_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// a websocket client's stream, plain or tls.
+#[derive(Debug)]
+pub enum ClientStream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl AsyncRead for ClientStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            ClientStream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for ClientStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            ClientStream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            ClientStream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            ClientStream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            ClientStream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+/// terminates tls with the latest certificates. a reload only affects conns accepted after it.
+#[derive(Clone)]
+pub struct Acceptor {
+    config: TlsConfig,
+    current: Arc<RwLock<Arc<ServerConfig>>>,
+}
+
+impl Acceptor {
+    pub fn new(config: TlsConfig) -> Result<Self> {
+        let current = Arc::new(RwLock::new(Arc::new(server_config(&config)?)));
+
+        Ok(Acceptor { config, current })
+    }
+
+    pub async fn accept(&self, stream: TcpStream) -> io::Result<ClientStream> {
+        let server_config = self.current.read().unwrap_or_else(PoisonError::into_inner).clone();
+        let stream = TlsAcceptor::from(server_config).accept(stream).await?;
+
+        Ok(ClientStream::Tls(Box::new(stream)))
+    }
+
+    /// read the files again. on error the current certificates stay in use.
+    pub fn reload(&self) -> Result<()> {
+        let server_config = Arc::new(server_config(&self.config)?);
+        *self.current.write().unwrap_or_else(PoisonError::into_inner) = server_config;
+
+        Ok(())
+    }
+
+    /// directories of the files. they are watched rather than the files, which are often replaced by a rename.
+    fn watched_dirs(&self) -> Vec<PathBuf> {
+        let mut dirs: Vec<PathBuf> = [
+            Some(&self.config.cert_path),
+            Some(&self.config.key_path),
+            self.config.client_ca_path.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        .map(|path| match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        })
+        .collect();
+        dirs.sort();
+        dirs.dedup();
+
+        dirs
+    }
+}
+
+fn server_config(config: &TlsConfig) -> Result<ServerConfig> {
+    let certs = read_certs(&config.cert_path)?;
+    let key = PrivateKeyDer::from_pem_file(&config.key_path).with_context(|| format!("read tls key {}", config.key_path.display()))?;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone()).with_safe_default_protocol_versions()?;
+
+    let builder = match &config.client_ca_path {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(path)? {
+                roots.add(cert).with_context(|| format!("add client ca {}", path.display()))?;
+            }
+
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+            let verifier = match config.require_client_cert {
+                true => verifier,
+                false => verifier.allow_unauthenticated(),
+            };
+            builder.with_client_cert_verifier(verifier.build()?)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut server_config = builder.with_single_cert(certs, key).context("tls certificate does not match its key")?;
+    server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    Ok(server_config)
+}
+
+fn read_certs(path: &PathBuf) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("read tls certificates {}", path.display()))?;
+    if certs.is_empty() {
+        anyhow::bail!("no certificate in {}", path.display());
+    }
+
+    Ok(certs)
+}
+
+/// watch the directories of the files of acceptor, sending on tx when something in them changes.
+fn watch(acceptor: &Acceptor, tx: mpsc::Sender<()>) -> Result<RecommendedWatcher> {
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        // reading the files on reload is an access, it must not trigger another one.
+        if event.is_ok_and(|event| !event.kind.is_access()) {
+            let _ = tx.try_send(());
+        }
+    })?;
+    for dir in acceptor.watched_dirs() {
+        watcher
+            .watch(&dir, RecursiveMode::NonRecursive)
+            .with_context(|| format!("watch {}", dir.display()))?;
+    }
+
+    Ok(watcher)
+}
+
+/// reload the certificates of acceptor on SIGHUP and whenever their files change.
+/// when the files can't be watched, e.g. out of inotify watches, only SIGHUP reloads them.
+pub fn spawn_reload(acceptor: Acceptor) -> Result<()> {
+    let (tx, mut changes) = mpsc::channel(1);
+    let watcher = match watch(&acceptor, tx) {
+        Ok(watcher) => Some(watcher),
+        Err(err) => {
+            warn!(error = ?err, "failed to watch tls files, reloading on SIGHUP only");
+            None
+        }
+    };
+
+    let mut hangup = signal(SignalKind::hangup())?;
+
+    tokio::spawn(
+        async move {
+            // dropping the watcher stops it.
+            let _watcher = watcher;
+
+            loop {
+                tokio::select! {
+                    _ = hangup.recv() => info!("SIGHUP received, reloading"),
+                    Some(()) = changes.recv() => {
+                        tokio::time::sleep(RELOAD_DEBOUNCE).await;
+                        while changes.try_recv().is_ok() {}
+                        info!("files changed, reloading");
+                    }
+                }
+
+                match acceptor.reload() {
+                    Ok(()) => info!("certificates reloaded"),
+                    Err(err) => error!(error = ?err, "reload failed, the current certificates stay in use"),
+                }
+            }
+        }
+        .instrument(info_span!(parent: None, "tls")),
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use rcgen::{BasicConstraints, CertificateParams, CertifiedKey, IsCa, KeyPair};
+    use rustls::{pki_types::ServerName, ClientConfig};
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsConnector;
+
+    use super::*;
+
+    struct Fixture {
+        dir: PathBuf,
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    impl Fixture {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("im-tls-{}", uuid::Uuid::new_v4()));
+            fs::create_dir_all(&dir).unwrap();
+
+            Fixture { dir }
+        }
+
+        fn write(&self, name: &str, contents: &str) -> PathBuf {
+            let path = self.dir.join(name);
+            fs::write(&path, contents).unwrap();
+
+            path
+        }
+
+        /// a self signed certificate for localhost and its key.
+        fn server_cert(&self) -> (CertifiedKey, TlsConfig) {
+            let server = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+            let config = TlsConfig {
+                cert_path: self.write("cert.pem", &server.cert.pem()),
+                key_path: self.write("key.pem", &server.key_pair.serialize_pem()),
+                client_ca_path: None,
+                require_client_cert: false,
+            };
+
+            (server, config)
+        }
+    }
+
+    fn ca() -> (rcgen::Certificate, KeyPair) {
+        let mut params = CertificateParams::new(Vec::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let key = KeyPair::generate().unwrap();
+        let cert = params.self_signed(&key).unwrap();
+
+        (cert, key)
+    }
+
+    /// handshake with acceptor as a client trusting server, without a client certificate.
+    async fn handshake(acceptor: &Acceptor, server: &CertifiedKey) -> io::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut roots = RootCertStore::empty();
+        roots.add(server.cert.der().clone()).unwrap();
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let client_config = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        let client = tokio::spawn(async move {
+            let stream = TcpStream::connect(addr).await?;
+            TlsConnector::from(Arc::new(client_config))
+                .connect(ServerName::try_from("localhost").unwrap(), stream)
+                .await
+        });
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let accepted = acceptor.accept(stream).await.map(|_| ());
+        let _ = client.await;
+
+        accepted
+    }
+
+    fn error(config: TlsConfig) -> String {
+        format!("{:#}", Acceptor::new(config).err().unwrap())
+    }
+
+    #[tokio::test]
+    async fn accepts_with_a_matching_key() {
+        let fixture = Fixture::new();
+        let (server, config) = fixture.server_cert();
+
+        let acceptor = Acceptor::new(config).unwrap();
+        handshake(&acceptor, &server).await.unwrap();
+    }
+
+    #[test]
+    fn rejects_a_key_of_another_certificate() {
+        let fixture = Fixture::new();
+        let (_, mut config) = fixture.server_cert();
+        let other = KeyPair::generate().unwrap();
+        config.key_path = fixture.write("other.pem", &other.serialize_pem());
+
+        assert!(error(config).contains("does not match"));
+    }
+
+    #[test]
+    fn rejects_empty_files() {
+        let fixture = Fixture::new();
+
+        let (_, mut config) = fixture.server_cert();
+        config.cert_path = fixture.write("empty.pem", "");
+        assert!(error(config).contains("no certificate"));
+
+        let (_, mut config) = fixture.server_cert();
+        config.client_ca_path = Some(fixture.write("empty.pem", ""));
+        assert!(error(config).contains("no certificate"));
+    }
+
+    #[tokio::test]
+    async fn client_certificates_are_optional_unless_required() {
+        let fixture = Fixture::new();
+        let (ca, _) = ca();
+        let (server, mut config) = fixture.server_cert();
+        config.client_ca_path = Some(fixture.write("ca.pem", &ca.pem()));
+
+        let optional = Acceptor::new(config.clone()).unwrap();
+        handshake(&optional, &server).await.unwrap();
+
+        config.require_client_cert = true;
+        let required = Acceptor::new(config).unwrap();
+        assert!(handshake(&required, &server).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn failed_reloads_keep_the_current_certificates() {
+        let fixture = Fixture::new();
+        let (server, config) = fixture.server_cert();
+        let acceptor = Acceptor::new(config.clone()).unwrap();
+        let current = || acceptor.current.read().unwrap().clone();
+        let before = current();
+
+        fs::write(&config.cert_path, "").unwrap();
+        assert!(acceptor.reload().is_err());
+        assert!(Arc::ptr_eq(&before, &current()));
+        handshake(&acceptor, &server).await.unwrap();
+
+        let (renewed, _) = fixture.server_cert();
+        acceptor.reload().unwrap();
+        assert!(!Arc::ptr_eq(&before, &current()));
+        handshake(&acceptor, &renewed).await.unwrap();
+    }
+}
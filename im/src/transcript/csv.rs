@@ -0,0 +1,52 @@
+use crate::i18n::I18n;
+
+use super::{format_time, Transcript};
+
+const HEADER: &str = "room_id,time,seq,sender_id,sender_type,sender_name,msg_type,text,edited_at,recalled_at";
+
+/// quote a field when needed. fields a spreadsheet would run as a formula get a leading quote.
+fn field(value: &str) -> String {
+    let value = match value.starts_with(['=', '+', '-', '@']) {
+        true => format!("'{}", value),
+        false => value.to_string(),
+    };
+
+    if value.contains([',', '"', '\n', '\r']) {
+        return format!("\"{}\"", value.replace('"', "\"\""));
+    }
+
+    value
+}
+
+/// one row per timeline entry of every room.
+pub fn render(transcripts: &[Transcript], i18n: &I18n, locale: &str) -> String {
+    let mut out = String::from(HEADER);
+    out.push_str("\r\n");
+
+    for transcript in transcripts {
+        for entry in transcript.entries(i18n, locale) {
+            let (sender_id, sender_type, sender_name) = match entry.sender {
+                Some(sender) => (sender.id(), sender.user_type().as_str(), sender.user_name()),
+                None => ("", "", ""),
+            };
+
+            let row = [
+                field(&transcript.room_id),
+                format_time(entry.at),
+                entry.seq.map(|seq| seq.to_string()).unwrap_or_default(),
+                field(sender_id),
+                sender_type.to_string(),
+                field(sender_name),
+                entry.msg_type.as_str().to_string(),
+                field(&entry.text),
+                entry.edited_at.map(format_time).unwrap_or_default(),
+                entry.recalled_at.map(format_time).unwrap_or_default(),
+            ];
+
+            out.push_str(&row.join(","));
+            out.push_str("\r\n");
+        }
+    }
+
+    out
+}
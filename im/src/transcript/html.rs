@@ -0,0 +1,120 @@
+use std::fmt::Write;
+
+use crate::{auth::Member, i18n::I18n};
+
+use super::{format_time, Transcript};
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em auto; max-width: 50em; color: #222; }
+section { border-top: 1px solid #ccc; padding-top: 1em; margin-top: 2em; }
+.meta { color: #666; font-size: 0.9em; }
+.tips { text-align: center; color: #888; font-size: 0.9em; margin: 0.5em 0; }
+.message { margin: 0.75em 0; }
+.message .sender { font-weight: bold; }
+.message.CustomerService .sender { color: #1565c0; }
+.message.Supervisor .sender { color: #6a1b9a; }
+.message time, .message .edited { color: #888; font-size: 0.8em; margin-left: 0.5em; }
+.message .text { white-space: pre-wrap; margin-top: 0.2em; }
+.message.recalled .text { color: #888; font-style: italic; }
+";
+
+fn escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+
+    out
+}
+
+fn member_label(member: &Member) -> String {
+    format!(
+        "{} ({}, {})",
+        escape(member.display_name()),
+        escape(member.id()),
+        member.user_type().as_str()
+    )
+}
+
+/// a standalone page, styles inlined so it opens without the server.
+pub fn render(transcripts: &[Transcript], i18n: &I18n, locale: &str) -> String {
+    let text = |key: &str| escape(&i18n.text(locale, key, &[]));
+    let mut out = String::new();
+
+    let _ = write!(
+        out,
+        "<!DOCTYPE html>\n<html lang=\"{}\">\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{}</style>\n</head>\n<body>\n<h1>{}</h1>\n",
+        escape(locale),
+        text("transcript.title"),
+        STYLE,
+        text("transcript.title")
+    );
+
+    for transcript in transcripts {
+        let closed_at = transcript
+            .closed_at
+            .map(format_time)
+            .unwrap_or_else(|| i18n.text(locale, "transcript.open", &[]));
+
+        let _ = write!(
+            out,
+            "<section>\n<h2>{}</h2>\n<p class=\"meta\">{}: <time>{}</time> · {}: <time>{}</time></p>\n",
+            escape(&transcript.room_id),
+            text("transcript.created_at"),
+            format_time(transcript.created_at),
+            text("transcript.closed_at"),
+            escape(&closed_at)
+        );
+
+        let _ = write!(out, "<p class=\"meta\">{}:</p>\n<ul class=\"meta\">\n", text("transcript.participants"));
+        for participant in transcript.participants.iter() {
+            let _ = writeln!(out, "<li>{}</li>", member_label(&participant.member));
+        }
+        out.push_str("</ul>\n");
+
+        for entry in transcript.entries(i18n, locale) {
+            let sender = match entry.sender {
+                Some(sender) => sender,
+                None => {
+                    let _ = writeln!(
+                        out,
+                        "<p class=\"tips\">{} · <time>{}</time></p>",
+                        escape(&entry.text),
+                        format_time(entry.at)
+                    );
+                    continue;
+                }
+            };
+
+            let recalled = if entry.recalled_at.is_some() { " recalled" } else { "" };
+            let edited = match entry.edited_at {
+                Some(_) if entry.recalled_at.is_none() => format!("<span class=\"edited\">{}</span>", text("transcript.edited")),
+                _ => String::new(),
+            };
+
+            let _ = writeln!(
+                out,
+                "<div class=\"message {}{}\"><span class=\"sender\">{}</span><time>{}</time>{}<div class=\"text\">{}</div></div>",
+                sender.user_type().as_str(),
+                recalled,
+                member_label(sender),
+                format_time(entry.at),
+                edited,
+                escape(&entry.text)
+            );
+        }
+
+        out.push_str("</section>\n");
+    }
+
+    out.push_str("</body>\n</html>\n");
+    out
+}
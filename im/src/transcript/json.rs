@@ -0,0 +1,31 @@
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::{auth::RoomId, i18n::I18n};
+
+use super::{Entry, Participant, Transcript};
+
+#[derive(Serialize)]
+struct JsonTranscript<'a> {
+    room_id: &'a RoomId,
+    created_at: u64,
+    closed_at: Option<u64>,
+    participants: &'a [Participant],
+    entries: Vec<Entry<'a>>,
+}
+
+/// an array of rooms with their timelines. times are unix millis.
+pub fn render(transcripts: &[Transcript], i18n: &I18n, locale: &str) -> Result<String> {
+    let transcripts: Vec<JsonTranscript> = transcripts
+        .iter()
+        .map(|transcript| JsonTranscript {
+            room_id: &transcript.room_id,
+            created_at: transcript.created_at,
+            closed_at: transcript.closed_at,
+            participants: &transcript.participants,
+            entries: transcript.entries(i18n, locale),
+        })
+        .collect();
+
+    Ok(serde_json::to_string_pretty(&transcripts)?)
+}
@@ -0,0 +1,251 @@
+mod csv;
+mod html;
+mod json;
+
+use std::fmt;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use time::{format_description::well_known::Rfc3339, macros::format_description, Date, OffsetDateTime};
+
+use crate::{
+    auth::{Member, RoomId},
+    i18n::I18n,
+    message::{
+        payload::Payload,
+        protocol::{ClientProtocol, MessageType},
+        version,
+    },
+};
+
+const DAY_MILLIS: u64 = 24 * 60 * 60 * 1000;
+
+/// which rooms to export. from and to are unix millis and bound the creation of the room, to is exclusive.
+#[derive(Debug, Clone, Default)]
+pub struct TranscriptFilter {
+    pub room_id: Option<RoomId>,
+    /// id of a customer service member of the room.
+    pub agent: Option<String>,
+    /// id of a customer member of the room.
+    pub customer: Option<String>,
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+}
+
+/// a member of a room. joined_at and left_at are of its latest visit.
+#[derive(Serialize, Debug, Clone)]
+pub struct Participant {
+    pub member: Member,
+    pub joined_at: u64,
+    pub left_at: Option<u64>,
+}
+
+/// a stored message, content holds the latest body of edited messages.
+#[derive(Serialize, Debug, Clone)]
+pub struct TranscriptMessage {
+    pub sender: Member,
+    pub content: ClientProtocol,
+    pub edited_at: Option<u64>,
+    pub recalled_at: Option<u64>,
+}
+
+/// everything stored about a room.
+#[derive(Serialize, Debug, Clone)]
+pub struct Transcript {
+    pub room_id: RoomId,
+    pub created_at: u64,
+    pub closed_at: Option<u64>,
+    pub participants: Vec<Participant>,
+    /// ordered by seq.
+    pub messages: Vec<TranscriptMessage>,
+}
+
+/// a line of a transcript: a message, or a system tip rendered from the room's events.
+#[derive(Serialize, Debug)]
+pub struct Entry<'a> {
+    pub at: u64,
+    pub msg_type: MessageType,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sender: Option<&'a Member>,
+    pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload: Option<&'a Payload>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub edited_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recalled_at: Option<u64>,
+}
+
+impl<'a> Entry<'a> {
+    fn tips(at: u64, text: String) -> Self {
+        Entry {
+            at,
+            msg_type: MessageType::Tips,
+            seq: None,
+            sender: None,
+            text,
+            payload: None,
+            edited_at: None,
+            recalled_at: None,
+        }
+    }
+}
+
+impl Transcript {
+    /// the room's timeline, oldest first. tips are rendered in locale, rich payloads get their text fallback.
+    pub fn entries(&self, i18n: &I18n, locale: &str) -> Vec<Entry<'_>> {
+        let mut entries = Vec::new();
+
+        for participant in self.participants.iter() {
+            let text = i18n.text(locale, "tips.member_joined", &[("name", participant.member.display_name())]);
+            entries.push(Entry::tips(participant.joined_at, text));
+        }
+
+        for message in self.messages.iter() {
+            let content = &message.content;
+            let (text, payload) = match (message.recalled_at, content.payload()) {
+                (Some(_), _) => (i18n.text(locale, "tips.message_recalled", &[]), None),
+                (None, Some(payload)) => (version::payload_text(payload, i18n, locale), Some(payload)),
+                (None, None) => (content.body().to_string(), None),
+            };
+
+            entries.push(Entry {
+                at: content.sent_at().unwrap_or_default(),
+                msg_type: *content.msg_type(),
+                seq: content.seq(),
+                sender: Some(&message.sender),
+                text,
+                payload,
+                edited_at: message.edited_at,
+                recalled_at: message.recalled_at,
+            });
+        }
+
+        for participant in self.participants.iter() {
+            if let Some(left_at) = participant.left_at {
+                let text = i18n.text(locale, "tips.member_left", &[("name", participant.member.display_name())]);
+                entries.push(Entry::tips(left_at, text));
+            }
+        }
+
+        if let Some(closed_at) = self.closed_at {
+            entries.push(Entry::tips(closed_at, i18n.text(locale, "tips.room_closed", &[])));
+        }
+
+        // stable, so joins stay ahead of messages sent in the same millisecond.
+        entries.sort_by_key(|entry| entry.at);
+        entries
+    }
+}
+
+/// export format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Json,
+    Csv,
+    Html,
+}
+
+impl Format {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Format::Json => "application/json",
+            Format::Csv => "text/csv; charset=utf-8",
+            Format::Html => "text/html; charset=utf-8",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Json => "json",
+            Format::Csv => "csv",
+            Format::Html => "html",
+        }
+    }
+}
+
+/// render transcripts as a single document.
+pub fn render(format: Format, transcripts: &[Transcript], i18n: &I18n, locale: &str) -> Result<String> {
+    match format {
+        Format::Json => json::render(transcripts, i18n, locale),
+        Format::Csv => Ok(csv::render(transcripts, i18n, locale)),
+        Format::Html => Ok(html::render(transcripts, i18n, locale)),
+    }
+}
+
+/// unix millis as rfc 3339 in utc.
+pub fn format_time(millis: u64) -> String {
+    OffsetDateTime::from_unix_timestamp_nanos(millis as i128 * 1_000_000)
+        .ok()
+        .and_then(|time| time.format(&Rfc3339).ok())
+        .unwrap_or_default()
+}
+
+/// parse unix millis, an rfc 3339 time or a `YYYY-MM-DD` date in utc.
+/// a date used as the end of a range covers that whole day.
+pub fn parse_time(value: &str, end: bool) -> Option<u64> {
+    if let Ok(millis) = value.parse() {
+        return Some(millis);
+    }
+
+    if let Ok(time) = OffsetDateTime::parse(value, &Rfc3339) {
+        return u64::try_from(time.unix_timestamp_nanos() / 1_000_000).ok();
+    }
+
+    let date = Date::parse(value, format_description!("[year]-[month]-[day]")).ok()?;
+    let millis = u64::try_from(date.midnight().assume_utc().unix_timestamp()).ok()? * 1000;
+
+    Some(if end { millis + DAY_MILLIS } else { millis })
+}
+
+/// returned when a bound of a time range is none of the formats parse_time reads.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidTime(String);
+
+impl fmt::Display for InvalidTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid time: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidTime {}
+
+/// parse_time for an optional bound of a range given by a user, e.g. `from` and `to` of a query.
+pub fn parse_bound(value: Option<&str>, end: bool) -> std::result::Result<Option<u64>, InvalidTime> {
+    value
+        .map(|value| parse_time(value, end).ok_or_else(|| InvalidTime(value.to_string())))
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn times_parse_as_millis_rfc3339_or_dates() {
+        assert_eq!(parse_time("1700000000123", false), Some(1_700_000_000_123));
+        assert_eq!(parse_time("2024-01-02T03:04:05.678Z", false), Some(1_704_164_645_678));
+        assert_eq!(parse_time("2024-01-02T11:04:05+08:00", false), Some(1_704_164_645_000));
+        assert_eq!(parse_time("2024-01-02", false), Some(1_704_153_600_000));
+        // a date ends with its day.
+        assert_eq!(parse_time("2024-01-02", true), Some(1_704_153_600_000 + DAY_MILLIS));
+        assert_eq!(parse_time("2024-01-02T03:04:05Z", true), Some(1_704_164_645_000));
+
+        for value in ["", "yesterday", "-1", "2024-13-01", "2024-01-02 03:04:05", "1969-12-31T00:00:00Z"] {
+            assert_eq!(parse_time(value, false), None, "{}", value);
+        }
+    }
+
+    #[test]
+    fn bounds_are_optional() {
+        assert_eq!(parse_bound(None, false), Ok(None));
+        assert_eq!(parse_bound(Some("1000"), true), Ok(Some(1000)));
+
+        let err = parse_bound(Some("soon"), false).unwrap_err();
+        assert_eq!(err.to_string(), "invalid time: soon");
+    }
+}